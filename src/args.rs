@@ -1,5 +1,13 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
 use anyhow::Result;
-use clap::{CommandFactory, Parser, Subcommand};
+use chrono::{DateTime, Utc};
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+
+use crate::pikpak::file;
+use crate::utils::category::FileCategory;
+use crate::utils::parse::{parse_range, parse_size, parse_time, ByteRange};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -10,11 +18,15 @@ pub struct Cli {
     #[arg(short, long, action = clap::ArgAction::Set,default_value_t=0,help = "net error retry times, if it is set to negative, it will infinitely retry",allow_hyphen_values=true)]
     pub retry: i8,
 
+    #[arg(long, action = clap::ArgAction::SetTrue, help = "fail instead of prompting when captcha verification is required")]
+    pub non_interactive: bool,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
 
 #[derive(Subcommand, Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum Commands {
     #[command(about = "Download a dir or a file", visible_alias = "d")]
     Download {
@@ -24,6 +36,268 @@ pub enum Commands {
         output: String,
         #[arg(short, long, default_value_t = 4, help = "download parallel count")]
         parallel: usize,
+        #[arg(
+            long,
+            conflicts_with = "paths",
+            help = "resume an interrupted download job by its id"
+        )]
+        resume: Option<String>,
+        #[arg(long, help = "write the download summary as json to this file")]
+        report: Option<PathBuf>,
+        #[arg(
+            long,
+            conflicts_with_all = ["resume", "paths"],
+            help = "retry the failed items of a json report"
+        )]
+        retry_from: Option<PathBuf>,
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = ConflictPolicy::Skip,
+            help = "what to do when the local file already exists"
+        )]
+        on_conflict: ConflictPolicy,
+        #[arg(
+            long,
+            conflicts_with_all = ["resume", "retry_from"],
+            help = "print the planned actions without downloading anything"
+        )]
+        dry_run: bool,
+        #[arg(
+            long,
+            help = "store the file id, hash and original url as extended attributes (linux only)"
+        )]
+        xattrs: bool,
+        #[arg(
+            long,
+            help = "download into a hidden partial file and rename it into place after verification"
+        )]
+        atomic: bool,
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = SanitizeMode::Unix,
+            help = "how to rewrite remote names that are invalid as local file names"
+        )]
+        sanitize: SanitizeMode,
+        #[arg(
+            long,
+            default_value_t = '_',
+            help = "character used to replace invalid characters in file names"
+        )]
+        replace_char: char,
+        #[arg(
+            long,
+            help = "treat names differing only in case as collisions, e.g. on a case-insensitive NAS"
+        )]
+        case_insensitive: bool,
+        #[arg(
+            long,
+            value_name = "URL",
+            help = "submit files to an aria2 JSON-RPC endpoint instead of downloading, e.g. http://host:6800/jsonrpc"
+        )]
+        aria2: Option<String>,
+        #[arg(long, requires = "aria2", help = "aria2 rpc secret token")]
+        aria2_secret: Option<String>,
+        #[arg(
+            long,
+            requires = "aria2",
+            value_parser = humantime::parse_duration,
+            default_value = "2s",
+            help = "how often to query the status of aria2 tasks"
+        )]
+        aria2_poll_interval: std::time::Duration,
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = Quality::Original,
+            help = "download a transcoded video variant instead of the original, falls back to the closest one"
+        )]
+        quality: Quality,
+        #[arg(
+            long,
+            conflicts_with = "aria2",
+            help = "also save the thumbnail of each downloaded file"
+        )]
+        with_thumbnails: bool,
+        #[arg(
+            long,
+            requires = "with_thumbnails",
+            help = "save thumbnails into this directory mirroring the remote tree instead of next to the files"
+        )]
+        thumbnail_dir: Option<PathBuf>,
+        #[arg(long, value_enum, default_value_t = ThumbnailSize::Medium, help = "thumbnail size")]
+        thumbnail_size: ThumbnailSize,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+
+    #[command(about = "Save thumbnails of remote files")]
+    Thumbnails {
+        #[arg(help = "specify multi path, can be a dir or a file")]
+        paths: Vec<String>,
+        #[arg(short, long, default_value_t = String::from("./"), help = "directory the files are downloaded to, thumbnails are saved next to them")]
+        output: String,
+        #[arg(
+            long,
+            help = "save thumbnails into this directory mirroring the remote tree instead"
+        )]
+        thumbnail_dir: Option<PathBuf>,
+        #[arg(long, value_enum, default_value_t = ThumbnailSize::Medium, help = "thumbnail size")]
+        thumbnail_size: ThumbnailSize,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+
+    #[command(about = "Print the content of a remote file to stdout")]
+    Cat {
+        #[arg(help = "file path")]
+        path: String,
+        #[arg(long, value_parser = parse_range, conflicts_with_all = ["head", "tail"], help = "byte range to print, e.g. 100-199, 1M-")]
+        range: Option<ByteRange>,
+        #[arg(long, value_parser = parse_size, conflicts_with = "tail", help = "print the first bytes, e.g. 1K")]
+        head: Option<u64>,
+        #[arg(long, value_parser = parse_size, help = "print the last bytes, e.g. 1K")]
+        tail: Option<u64>,
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = Quality::Original,
+            help = "read a transcoded video variant instead of the original, falls back to the closest one"
+        )]
+        quality: Quality,
+    },
+
+    #[command(about = "Manage download jobs")]
+    Jobs {
+        #[command(subcommand)]
+        command: JobsCommands,
+    },
+
+    #[command(about = "Export download links for aria2, curl or wget")]
+    Links {
+        #[arg(help = "specify multi path, can be a dir or a file")]
+        paths: Vec<String>,
+        #[arg(short, long, value_enum, default_value_t = LinkFormat::Aria2, help = "export format")]
+        format: LinkFormat,
+        #[arg(short, long, help = "write to this file instead of stdout")]
+        output: Option<PathBuf>,
+        #[arg(short, long, default_value_t = String::from("./"), help = "local directory the files will be downloaded to")]
+        dir: String,
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = Quality::Original,
+            help = "export links of a transcoded video variant instead of the original, falls back to the closest one"
+        )]
+        quality: Quality,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+
+    #[command(about = "Serve the drive over the local network")]
+    Serve {
+        #[command(subcommand)]
+        command: ServeCommands,
+    },
+
+    #[command(about = "Generate a .strm media library that streams videos through serve http")]
+    Strm {
+        #[arg(help = "remote folder")]
+        path: String,
+        #[arg(short, long, default_value_t = String::from("./"), help = "local library directory")]
+        output: String,
+        #[arg(
+            long,
+            default_value_t = String::from("http://127.0.0.1:8080"),
+            help = "base url of the serve http gateway as seen by the media server"
+        )]
+        gateway: String,
+        #[arg(
+            long,
+            help = "only write new or changed entries and remove deleted ones"
+        )]
+        incremental: bool,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+
+    #[command(about = "Export audio and video files of a remote folder as an M3U playlist")]
+    Playlist {
+        #[arg(help = "remote folder")]
+        path: String,
+        #[arg(
+            long,
+            help = "base url of the serve http gateway, use stable gateway links instead of expiring download links"
+        )]
+        gateway: Option<String>,
+        #[arg(short, long, help = "write to this file instead of stdout")]
+        output: Option<PathBuf>,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+
+    #[command(about = "Two-way sync between a local folder and a remote folder")]
+    Sync {
+        #[arg(help = "local folder")]
+        local: PathBuf,
+        #[arg(help = "remote folder")]
+        remote: String,
+        #[arg(long, help = "only print the planned changes")]
+        dry_run: bool,
+    },
+
+    #[command(about = "Make a local folder an exact copy of a remote folder")]
+    Mirror {
+        #[arg(help = "remote folder")]
+        remote: String,
+        #[arg(help = "local folder")]
+        local: PathBuf,
+        #[arg(
+            long,
+            help = "move local files missing remotely into this folder instead of deleting them"
+        )]
+        quarantine: Option<PathBuf>,
+        #[arg(
+            long,
+            default_value_t = 100,
+            help = "abort without changes when more local files than this would be removed"
+        )]
+        max_delete: usize,
+        #[arg(short, long, default_value_t = 4, help = "download parallel count")]
+        parallel: usize,
+        #[arg(long, help = "only print the planned changes")]
+        dry_run: bool,
+    },
+
+    #[cfg(all(feature = "fuse", target_os = "linux"))]
+    #[command(about = "Mount the drive as a read-only filesystem")]
+    Mount {
+        #[arg(help = "local directory to mount on")]
+        mountpoint: PathBuf,
+        #[arg(long, default_value_t = String::from("/"), help = "remote folder to mount")]
+        root: String,
+        #[arg(
+            long,
+            help = "directory for cached file chunks, defaults to a temporary directory"
+        )]
+        cache_dir: Option<PathBuf>,
+        #[arg(long, value_parser = parse_size, default_value = "4M", help = "size of each cached chunk")]
+        chunk_size: u64,
+        #[arg(
+            long,
+            value_parser = parse_size,
+            default_value = "1G",
+            help = "max total size of cached chunks, least recently used chunks are removed first"
+        )]
+        cache_size: u64,
+        #[arg(long, default_value_t = 2, help = "number of chunks to read ahead")]
+        read_ahead: u64,
+        #[arg(
+            long,
+            help = "allow other users to access the mount, and unmount it when the process exits"
+        )]
+        allow_other: bool,
     },
 
     #[command(about = "List file", visible_alias = "ls")]
@@ -37,6 +311,184 @@ pub enum Commands {
     },
 }
 
+impl Commands {
+    // 命令的输出内容写到 stdout
+    pub fn writes_stdout(&self) -> bool {
+        matches!(
+            self,
+            Commands::Cat { .. }
+                | Commands::Links { output: None, .. }
+                | Commands::Playlist { output: None, .. }
+        )
+    }
+}
+
+#[derive(Args, Debug, Clone, Default)]
+pub struct FilterArgs {
+    #[arg(long, help = "only include files whose relative path matches the glob")]
+    pub include: Vec<String>,
+    #[arg(
+        long,
+        help = "skip files and folders whose relative path matches the glob"
+    )]
+    pub exclude: Vec<String>,
+    #[arg(
+        long,
+        help = "only include files whose relative path matches the regex"
+    )]
+    pub include_regex: Vec<String>,
+    #[arg(
+        long,
+        help = "skip files and folders whose relative path matches the regex"
+    )]
+    pub exclude_regex: Vec<String>,
+    #[arg(long, value_parser = parse_size, help = "skip files smaller than this size, e.g. 10M")]
+    pub min_size: Option<u64>,
+    #[arg(long, value_parser = parse_size, help = "skip files larger than this size, e.g. 4G")]
+    pub max_size: Option<u64>,
+    #[arg(long, value_parser = parse_time, help = "only include files modified after this time, e.g. 2024-01-01 or 7d")]
+    pub newer_than: Option<DateTime<Utc>>,
+    #[arg(long, value_parser = parse_time, help = "only include files modified before this time, e.g. 2024-01-01 or 7d")]
+    pub older_than: Option<DateTime<Utc>>,
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        help = "only include files of these categories"
+    )]
+    pub category: Vec<FileCategory>,
+    #[arg(
+        long,
+        help = "max folder depth to walk, 0 means only the files directly in the folder"
+    )]
+    pub max_depth: Option<usize>,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    // 保留本地文件
+    #[default]
+    Skip,
+    Overwrite,
+    // 下载为 name (1).ext
+    Rename,
+    // 远程文件的修改时间比本地新时覆盖
+    Newer,
+    SizeMismatch,
+    // 比较 md5 或 gcid, 不一致时覆盖
+    Checksum,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkFormat {
+    // aria2 input file, 使用 aria2c -i 下载
+    Aria2,
+    Curl,
+    Wget,
+    Json,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SanitizeMode {
+    // 保留原始文件名
+    Off,
+    // 只替换 / 和 \0, 并限制文件名长度
+    #[default]
+    Unix,
+    // 同时替换 windows 和多数 NAS 不支持的字符, 去掉结尾的 . 和空格
+    Windows,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Quality {
+    // 原始文件
+    #[default]
+    Original,
+    #[value(name = "2160p")]
+    P2160,
+    #[value(name = "1440p")]
+    P1440,
+    #[value(name = "1080p")]
+    P1080,
+    #[value(name = "720p")]
+    P720,
+    #[value(name = "480p")]
+    P480,
+    #[value(name = "360p")]
+    P360,
+}
+
+// 缩略图尺寸
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ThumbnailSize {
+    Small,
+    #[default]
+    Medium,
+    Large,
+}
+
+impl From<ThumbnailSize> for file::ThumbnailSize {
+    fn from(size: ThumbnailSize) -> Self {
+        match size {
+            ThumbnailSize::Small => file::ThumbnailSize::Small,
+            ThumbnailSize::Medium => file::ThumbnailSize::Medium,
+            ThumbnailSize::Large => file::ThumbnailSize::Large,
+        }
+    }
+}
+
+impl Quality {
+    pub fn height(self) -> Option<u64> {
+        match self {
+            Quality::Original => None,
+            Quality::P2160 => Some(2160),
+            Quality::P1440 => Some(1440),
+            Quality::P1080 => Some(1080),
+            Quality::P720 => Some(720),
+            Quality::P480 => Some(480),
+            Quality::P360 => Some(360),
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ServeCommands {
+    #[command(about = "Serve directory listings and files over http, with Range support")]
+    Http {
+        #[arg(
+            short,
+            long,
+            default_value = "127.0.0.1:8080",
+            help = "address to listen on"
+        )]
+        bind: SocketAddr,
+        #[arg(long, default_value_t = String::from("/"), help = "remote folder to serve")]
+        root: String,
+    },
+    #[command(about = "Serve the drive over WebDAV")]
+    Webdav {
+        #[arg(
+            short,
+            long,
+            default_value = "127.0.0.1:8081",
+            help = "address to listen on"
+        )]
+        bind: SocketAddr,
+        #[arg(long, default_value_t = String::from("/"), help = "remote folder to serve")]
+        root: String,
+        #[arg(long, requires = "password", help = "basic auth username")]
+        username: Option<String>,
+        #[arg(long, requires = "username", help = "basic auth password")]
+        password: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum JobsCommands {
+    #[command(about = "List download jobs", visible_alias = "ls")]
+    List,
+}
+
 pub fn parse_cli() -> Cli {
     Cli::parse()
 }
@@ -44,3 +496,20 @@ pub fn parse_cli() -> Cli {
 pub fn print_cli_help() -> Result<()> {
     Ok(Cli::command().print_help()?)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resume_conflicts_with_paths() {
+        let err =
+            Cli::try_parse_from(["pikpakcli", "download", "--resume", "job", "/a"]).unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+        let cli = Cli::try_parse_from(["pikpakcli", "download", "--resume", "job"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Download { resume: Some(job), .. }) if job == "job"
+        ));
+    }
+}
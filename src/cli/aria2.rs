@@ -0,0 +1,409 @@
+// 通过 JSON-RPC 把下载任务推送到 aria2, 并跟踪任务状态直到完成
+// 下载链接过期导致任务失败时, 重新获取链接后再次提交
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use log::*;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::args::Quality;
+use crate::cli::download::finished_outcomes;
+use crate::cli::journal::{EntryState, Journal};
+use crate::cli::quality::variant_path;
+use crate::cli::summary::{Outcome, OutcomeStatus, Summary};
+use crate::pikpak::file::FileType;
+use crate::pikpak::{Client, USER_AGENT};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+// 同一个文件最多重新提交的次数
+const MAX_RESUBMIT: u32 = 3;
+// 连续查询状态失败多少次后放弃这个任务
+const MAX_POLL_ERRORS: u32 = 5;
+
+#[derive(Debug, Clone)]
+pub struct Aria2 {
+    url: String,
+    secret: Option<String>,
+    client: reqwest::Client,
+    poll_interval: Duration,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Aria2Status {
+    pub status: String,
+    pub completed_length: String,
+    pub total_length: String,
+    pub error_code: String,
+    pub error_message: String,
+}
+
+impl Aria2Status {
+    fn running(&self) -> bool {
+        matches!(self.status.as_str(), "active" | "waiting" | "paused")
+    }
+}
+
+impl Aria2 {
+    pub fn new(url: String, secret: Option<String>) -> Self {
+        Aria2 {
+            url,
+            secret,
+            client: reqwest::Client::new(),
+            poll_interval: POLL_INTERVAL,
+        }
+    }
+
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    async fn call(&self, method: &str, params: Vec<Value>) -> Result<Value> {
+        let mut all_params = vec![];
+        if let Some(secret) = &self.secret {
+            all_params.push(json!(format!("token:{}", secret)));
+        }
+        all_params.extend(params);
+        let req = json!({
+            "jsonrpc": "2.0",
+            "id": "pikpakcli",
+            "method": method,
+            "params": all_params,
+        });
+        debug!("aria2 req: {}", req);
+        let resp: Value = self
+            .client
+            .post(&self.url)
+            .json(&req)
+            .send()
+            .await
+            .with_context(|| format!("[aria2] call {} failed", method))?
+            .json()
+            .await
+            .with_context(|| format!("[aria2] parse {} response failed", method))?;
+        debug!("aria2 resp: {}", resp);
+        if let Some(err) = resp.get("error") {
+            return Err(anyhow::anyhow!("[aria2] {} error: {}", method, err));
+        }
+        Ok(resp["result"].clone())
+    }
+
+    pub async fn add_uri(&self, file: &FileType, local_path: &Path) -> Result<String> {
+        let mut options = json!({
+            "dir": local_path.parent().unwrap_or(Path::new(".")).to_string_lossy(),
+            "out": local_path.file_name().unwrap_or_default().to_string_lossy(),
+            "header": [format!("User-Agent: {}", USER_AGENT)],
+            "continue": "true",
+        });
+        if !file.md5_checksum.is_empty() {
+            options["checksum"] = json!(format!("md5={}", file.md5_checksum));
+        }
+        let url = &file.links.application_octet_stream.url;
+        let gid = self
+            .call("aria2.addUri", vec![json!([url]), options])
+            .await?;
+        gid.as_str()
+            .map(|x| x.to_string())
+            .ok_or(anyhow::anyhow!("[aria2] invalid gid: {}", gid))
+    }
+
+    pub async fn tell_status(&self, gid: &str) -> Result<Aria2Status> {
+        let keys = json!([
+            "status",
+            "completedLength",
+            "totalLength",
+            "errorCode",
+            "errorMessage"
+        ]);
+        let status = self
+            .call("aria2.tellStatus", vec![json!(gid), keys])
+            .await?;
+        Ok(serde_json::from_value(status)?)
+    }
+
+    // 清理失败的任务, 避免重新提交时 aria2 中残留记录
+    async fn remove_result(&self, gid: &str) {
+        if let Err(err) = self
+            .call("aria2.removeDownloadResult", vec![json!(gid)])
+            .await
+        {
+            warn!("remove aria2 result failed, gid: {}, err: {:#}", gid, err);
+        }
+    }
+}
+
+struct Tracked {
+    index: usize,
+    start: Instant,
+    resubmits: u32,
+    // 连续查询状态失败的次数
+    errors: u32,
+}
+
+impl Client {
+    // 获取最新的下载链接后提交到 aria2
+    async fn submit(
+        &mut self,
+        aria2: &Aria2,
+        quality: Quality,
+        journal: &mut Journal,
+        index: usize,
+    ) -> Result<()> {
+        let entry = &journal.entries[index];
+        let file = self
+            .get_file_with_quality(entry.remote_id.clone(), quality)
+            .await?;
+        let local_path = variant_path(&entry.local_path, &file);
+        let gid = aria2.add_uri(&file, &local_path).await?;
+        info!("submitted to aria2: {}, gid: {}", entry.remote_path, gid);
+
+        let entry = &mut journal.entries[index];
+        entry.local_path = local_path;
+        entry.size = file.size.parse().unwrap_or(entry.size);
+        entry.gid = gid;
+        entry.attempts += 1;
+        entry.state = EntryState::Downloading;
+        entry.error.clear();
+        journal.save_throttled()
+    }
+
+    pub(super) async fn run_aria2(
+        mut self,
+        mut journal: Journal,
+        aria2: &Aria2,
+        quality: Quality,
+        failures: Vec<Outcome>,
+        start: Instant,
+    ) -> Result<Summary> {
+        let mut outcomes = failures;
+        outcomes.extend(finished_outcomes(&journal));
+
+        let mut tracking = vec![];
+        for index in journal.unfinished() {
+            // 恢复任务时继续跟踪 aria2 中还在下载的任务
+            let gid = journal.entries[index].gid.clone();
+            if !gid.is_empty() {
+                match aria2.tell_status(&gid).await {
+                    Result::Ok(status) if status.running() || status.status == "complete" => {
+                        tracking.push(Tracked {
+                            index,
+                            start: Instant::now(),
+                            resubmits: 0,
+                            errors: 0,
+                        });
+                        continue;
+                    }
+                    _ => info!("aria2 task {} not found, submit again", gid),
+                }
+            }
+            let task_start = Instant::now();
+            if let Err(err) = self.submit(aria2, quality, &mut journal, index).await {
+                error!("submit to aria2 failed, err: {:#}", err);
+                outcomes.push(fail(
+                    &mut journal,
+                    index,
+                    format!("{:#}", err),
+                    0,
+                    task_start,
+                ));
+                continue;
+            }
+            tracking.push(Tracked {
+                index,
+                start: task_start,
+                resubmits: 0,
+                errors: 0,
+            });
+        }
+
+        while !tracking.is_empty() {
+            tokio::time::sleep(aria2.poll_interval).await;
+            let mut running = vec![];
+            for mut task in tracking {
+                let gid = journal.entries[task.index].gid.clone();
+                // 偶尔查询失败时继续跟踪, 连续失败多次才放弃
+                let status = match aria2.tell_status(&gid).await {
+                    Result::Ok(status) => status,
+                    Err(err) if task.errors + 1 < MAX_POLL_ERRORS => {
+                        task.errors += 1;
+                        warn!(
+                            "query aria2 task {} failed ({}/{}), err: {:#}",
+                            gid, task.errors, MAX_POLL_ERRORS, err
+                        );
+                        running.push(task);
+                        continue;
+                    }
+                    Err(err) => {
+                        let reason = format!("query aria2 task {} failed, err: {:#}", gid, err);
+                        error!("{}", reason);
+                        outcomes.push(fail(&mut journal, task.index, reason, 0, task.start));
+                        continue;
+                    }
+                };
+                task.errors = 0;
+                let bytes = status.completed_length.parse().unwrap_or_default();
+                if status.running() {
+                    running.push(task);
+                    continue;
+                }
+                if status.status == "complete" {
+                    let entry = &mut journal.entries[task.index];
+                    entry.state = EntryState::Done;
+                    entry.bytes_done = bytes;
+                    journal.save_throttled()?;
+                    let entry = &journal.entries[task.index];
+                    info!("aria2 download complete: {}", entry.remote_path);
+                    outcomes.push(Outcome::new(
+                        &entry.remote_id,
+                        &entry.remote_path,
+                        &entry.local_path,
+                        OutcomeStatus::Downloaded,
+                        String::new(),
+                        bytes,
+                        task.start.elapsed(),
+                    ));
+                    continue;
+                }
+
+                let reason = format!(
+                    "aria2 task {}: {} {}",
+                    status.status, status.error_code, status.error_message
+                );
+                aria2.remove_result(&gid).await;
+                if status.status == "error" && task.resubmits < MAX_RESUBMIT {
+                    // 多数情况是链接过期, 重新获取链接
+                    warn!(
+                        "{}, resubmit {} with a fresh link",
+                        reason, journal.entries[task.index].remote_path
+                    );
+                    task.resubmits += 1;
+                    match self.submit(aria2, quality, &mut journal, task.index).await {
+                        Result::Ok(()) => running.push(task),
+                        Err(err) => outcomes.push(fail(
+                            &mut journal,
+                            task.index,
+                            format!("{:#}", err),
+                            bytes,
+                            task.start,
+                        )),
+                    }
+                    continue;
+                }
+                error!("{}", reason);
+                outcomes.push(fail(&mut journal, task.index, reason, bytes, task.start));
+            }
+            tracking = running;
+        }
+
+        journal.flush()?;
+        Ok(Summary::new(&journal.id, outcomes, start.elapsed()))
+    }
+}
+
+fn fail(
+    journal: &mut Journal,
+    index: usize,
+    reason: String,
+    bytes: u64,
+    start: Instant,
+) -> Outcome {
+    let entry = &mut journal.entries[index];
+    entry.state = EntryState::Failed;
+    entry.error = reason.clone();
+    if let Err(err) = journal.save_throttled() {
+        error!("save job journal failed, err: {:#?}", err);
+    }
+    let entry = &journal.entries[index];
+    Outcome::new(
+        &entry.remote_id,
+        &entry.remote_path,
+        &entry.local_path,
+        OutcomeStatus::Failed,
+        reason,
+        bytes,
+        start.elapsed(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::download::DownloadOptions;
+    use crate::pikpak::mock::{MockAria2, MockServer};
+
+    #[tokio::test]
+    async fn test_aria2_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        let aria2 = MockAria2::start(Some("secret".into())).await;
+        // 第一次提交的任务失败, 模拟链接过期, 偶尔查询状态失败时继续跟踪
+        aria2.state().fail_first = 1;
+        aria2.state().status_errors = 2;
+        let mut client = server.client();
+        client.login().await?;
+
+        let dir = tempfile::tempdir()?;
+        let opts = DownloadOptions {
+            output: "/data".into(),
+            job_dir: dir.path().join("jobs"),
+            aria2: Some(
+                Aria2::new(aria2.url(), Some("secret".into()))
+                    .poll_interval(Duration::from_millis(10)),
+            ),
+            ..Default::default()
+        };
+        client.download(vec!["/My Pack".into()], opts).await?;
+
+        let state = aria2.state();
+        let uris: Vec<_> = state.added.iter().map(|x| x.0.as_str()).collect();
+        let a = format!("{}/download/file-a", server.url());
+        let b = format!("{}/download/file-b", server.url());
+        assert_eq!(uris, vec![a.as_str(), b.as_str(), a.as_str()]);
+        let options = &state.added[1].1;
+        assert_eq!(options["dir"], "/data/My Pack/sub");
+        assert_eq!(options["out"], "b.bin");
+        assert_eq!(options["header"][0], format!("User-Agent: {}", USER_AGENT));
+        assert_eq!(state.removed.len(), 1);
+
+        let job = Journal::list(&dir.path().join("jobs"))?.remove(0);
+        assert!(job.unfinished().is_empty());
+        assert_eq!(job.entries[0].attempts, 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_aria2_status_errors_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        let aria2 = MockAria2::start(None).await;
+        aria2.state().status_errors = usize::MAX;
+        let mut client = server.client();
+        client.login().await?;
+
+        let dir = tempfile::tempdir()?;
+        let opts = DownloadOptions {
+            output: "/data".into(),
+            job_dir: dir.path().join("jobs"),
+            aria2: Some(Aria2::new(aria2.url(), None).poll_interval(Duration::from_millis(10))),
+            ..Default::default()
+        };
+        assert!(client
+            .download(vec!["/My Pack".into()], opts)
+            .await
+            .is_err());
+
+        let job = Journal::list(&dir.path().join("jobs"))?.remove(0);
+        assert!(job.entries.iter().all(|x| x.state == EntryState::Failed));
+        assert!(job.entries[0].error.contains("Internal error"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_aria2_unauthorized_mock() -> Result<()> {
+        let aria2 = MockAria2::start(Some("secret".into())).await;
+        let client = Aria2::new(aria2.url(), Some("wrong".into()));
+        assert!(client.tell_status("gid").await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,162 @@
+use anyhow::{Context, Result};
+use log::*;
+use tokio::io::AsyncWrite;
+
+use crate::args::Quality;
+use crate::pikpak::download::{stream_range, LinkRejected};
+use crate::pikpak::folder::FileIDType;
+use crate::pikpak::Client;
+use crate::utils::parse::ByteRange;
+
+// 要输出的范围, 与 ByteRange 不同, end 不包含在内
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CatRange {
+    All,
+    Range(ByteRange),
+    Head(u64),
+    Tail(u64),
+}
+
+impl CatRange {
+    fn resolve(self, size: u64) -> (u64, u64) {
+        match self {
+            CatRange::All => (0, size),
+            CatRange::Range(range) => (
+                range.start.min(size),
+                range.end.map_or(size, |x| x.saturating_add(1).min(size)),
+            ),
+            CatRange::Head(n) => (0, n.min(size)),
+            CatRange::Tail(n) => (size.saturating_sub(n), size),
+        }
+    }
+}
+
+impl Client {
+    pub async fn cat(mut self, path: String, range: CatRange, quality: Quality) -> Result<()> {
+        let mut stdout = tokio::io::stdout();
+        match self.cat_to(&path, range, quality, &mut stdout).await {
+            // 下游提前关闭管道, 如 | head
+            Err(err)
+                if err
+                    .downcast_ref::<std::io::Error>()
+                    .is_some_and(|x| x.kind() == std::io::ErrorKind::BrokenPipe) =>
+            {
+                debug!("stdout closed: {:#}", err);
+                Ok(())
+            }
+            res => res,
+        }
+    }
+
+    pub(super) async fn cat_to<W: AsyncWrite + Unpin>(
+        &mut self,
+        path: &str,
+        range: CatRange,
+        quality: Quality,
+        out: &mut W,
+    ) -> Result<()> {
+        let FileIDType::File(id) = self.get_path_id(path).await? else {
+            return Err(anyhow::anyhow!("[cat] {} is a directory", path));
+        };
+        let mut file = self.get_file_with_quality(id.clone(), quality).await?;
+        let size = file
+            .size
+            .parse::<u64>()
+            .with_context(|| format!("[cat] size of {} unknown", path))?;
+        let (mut start, end) = range.resolve(size);
+        debug!("cat {}, range: {}-{}, size: {}", path, start, end, size);
+        // 链接失效时重新获取并从已输出的位置继续, 同一位置只刷新一次
+        let mut refreshed_at = None;
+        while start < end {
+            let Err(err) = stream_range(&file, start, end, out, self.retry_times).await else {
+                break;
+            };
+            match err.downcast_ref::<LinkRejected>() {
+                Some(rejected) if refreshed_at != Some(rejected.pos) => {
+                    warn!("{}, refresh", rejected);
+                    start = rejected.pos;
+                    refreshed_at = Some(start);
+                    file = self.get_file_with_quality(id.clone(), quality).await?;
+                }
+                _ => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pikpak::mock::MockServer;
+
+    #[test]
+    fn test_resolve_range() {
+        let range = |start, end| CatRange::Range(ByteRange { start, end });
+        assert_eq!(CatRange::All.resolve(10), (0, 10));
+        assert_eq!(range(2, Some(4)).resolve(10), (2, 5));
+        assert_eq!(range(2, None).resolve(10), (2, 10));
+        assert_eq!(range(20, None).resolve(10), (10, 10));
+        assert_eq!(CatRange::Head(20).resolve(10), (0, 10));
+        assert_eq!(CatRange::Tail(3).resolve(10), (7, 10));
+    }
+
+    #[tokio::test]
+    async fn test_cat_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        let mut client = server.client();
+        client.login().await?;
+
+        let mut out = vec![];
+        client
+            .cat_to(
+                "/My Pack/a.txt",
+                CatRange::Tail(5),
+                Quality::Original,
+                &mut out,
+            )
+            .await?;
+        assert_eq!(out, b"world");
+
+        let mut out = vec![];
+        client
+            .cat_to("/readme.md", CatRange::All, Quality::Original, &mut out)
+            .await?;
+        assert_eq!(out, b"# readme");
+        assert!(client
+            .cat_to("/My Pack", CatRange::All, Quality::Original, &mut out)
+            .await
+            .is_err());
+
+        // 中断后重试时链接已失效, 刷新链接后从中断的位置继续
+        {
+            let mut state = server.state();
+            state.truncate_downloads = 1;
+            state.link_uses = 1;
+            state.download_ranges.clear();
+        }
+        client.retry_times = 1;
+        let file_requests = server.state().file_requests;
+        let mut out = vec![];
+        client
+            .cat_to(
+                "/My Pack/sub/b.bin",
+                CatRange::All,
+                Quality::Original,
+                &mut out,
+            )
+            .await?;
+        let state = server.state();
+        assert_eq!(out, state.file("file-b").unwrap().content);
+        assert_eq!(state.file_requests, file_requests + 2);
+        assert_eq!(
+            state.download_ranges,
+            vec![
+                None,
+                Some("bytes=2048-4095".to_string()),
+                Some("bytes=2048-4095".to_string())
+            ]
+        );
+        Ok(())
+    }
+}
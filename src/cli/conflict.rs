@@ -0,0 +1,275 @@
+// 本地已存在同名文件时的处理策略, 只根据本地文件和远程信息得出处理方式, 不修改本地文件
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+
+use crate::args::ConflictPolicy;
+use crate::cli::partial::sidecar_path;
+use crate::pikpak::file::FileType;
+use crate::utils::hash::{gcid_file, md5_file};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Plan {
+    // 本地不存在, 直接下载
+    Download(PathBuf),
+    // 存在下载 flag, 从中断的位置继续下载
+    Resume(PathBuf),
+    // 删除本地文件后重新下载
+    Overwrite(PathBuf, String),
+    Skip(String),
+}
+
+impl Plan {
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Plan::Download(path) | Plan::Resume(path) | Plan::Overwrite(path, _) => Some(path),
+            Plan::Skip(_) => None,
+        }
+    }
+}
+
+pub fn flag_path(output_path: &Path) -> PathBuf {
+    let mut flag = output_path.as_os_str().to_owned();
+    flag.push(".pikpakclidownload");
+    PathBuf::from(flag)
+}
+
+// 旧版本给没有扩展名的文件生成的 flag 多一个点, 如 Makefile..pikpakclidownload
+fn legacy_flag_path(output_path: &Path) -> Option<PathBuf> {
+    let mut flag = output_path.to_path_buf();
+    let ext = flag
+        .extension()
+        .and_then(|x| x.to_str())
+        .unwrap_or_default()
+        .to_string();
+    if !flag.set_extension(ext + ".pikpakclidownload") {
+        return None;
+    }
+    (flag != flag_path(output_path)).then_some(flag)
+}
+
+fn exists(path: &Path) -> Result<bool> {
+    path.try_exists()
+        .with_context(|| format!("[conflict] check {} exists failed", path.display()))
+}
+
+fn flag_exists(output_path: &Path) -> Result<bool> {
+    if exists(&flag_path(output_path))? {
+        return Ok(true);
+    }
+    match legacy_flag_path(output_path) {
+        Some(legacy) => exists(&legacy),
+        None => Ok(false),
+    }
+}
+
+// 下载完成后删除 flag, 包括之前非原子模式和旧版本留下的
+pub fn remove_flags(output_path: &Path) -> Result<()> {
+    for flag in std::iter::once(flag_path(output_path)).chain(legacy_flag_path(output_path)) {
+        match std::fs::remove_file(&flag) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                return Err(err)
+                    .with_context(|| format!("[conflict] remove {} failed", flag.display()));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// 把旧版本的 flag 改为现在的名称, 继续之前中断的下载
+pub fn migrate_legacy_flag(output_path: &Path) -> Result<()> {
+    let Some(legacy) = legacy_flag_path(output_path) else {
+        return Ok(());
+    };
+    if exists(&legacy)? && !exists(&flag_path(output_path))? {
+        std::fs::rename(&legacy, flag_path(output_path))
+            .with_context(|| format!("[conflict] rename {} failed", legacy.display()))?;
+    }
+    Ok(())
+}
+
+// a.txt -> a (1).txt
+fn rename_path(output_path: &Path) -> Result<PathBuf> {
+    let stem = output_path
+        .file_stem()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let ext = output_path
+        .extension()
+        .map(|x| format!(".{}", x.to_string_lossy()))
+        .unwrap_or_default();
+    for n in 1.. {
+        let path = output_path.with_file_name(format!("{} ({}){}", stem, n, ext));
+        if !exists(&path)? {
+            return Ok(path);
+        }
+    }
+    unreachable!()
+}
+
+fn local_modified(metadata: &std::fs::Metadata) -> Result<DateTime<Utc>> {
+    Ok(metadata
+        .modified()
+        .context("[conflict] get local modified time failed")?
+        .into())
+}
+
+// 优先比较 md5, 没有 md5 时比较 gcid
+pub fn checksum_matches(file: &FileType, path: &Path) -> Result<Option<bool>> {
+    if !file.md5_checksum.is_empty() {
+        return Ok(Some(
+            md5_file(path)?.eq_ignore_ascii_case(&file.md5_checksum),
+        ));
+    }
+    if !file.hash.is_empty() {
+        return Ok(Some(gcid_file(path)?.eq_ignore_ascii_case(&file.hash)));
+    }
+    Ok(None)
+}
+
+pub fn plan_download(file: &FileType, output_path: &Path, policy: ConflictPolicy) -> Result<Plan> {
+    if flag_exists(output_path)? || exists(&sidecar_path(output_path))? {
+        return Ok(Plan::Resume(output_path.to_path_buf()));
+    }
+    if !exists(output_path)? {
+        return Ok(Plan::Download(output_path.to_path_buf()));
+    }
+
+    let metadata = std::fs::metadata(output_path).context("[conflict] get metadata failed")?;
+    let local_size = metadata.len();
+    // 转码后的视频可能拿不到大小
+    let remote_size = file.size.parse::<u64>().ok();
+    let overwrite = |reason: String| Ok(Plan::Overwrite(output_path.to_path_buf(), reason));
+
+    match policy {
+        ConflictPolicy::Skip => Ok(Plan::Skip("file exists".into())),
+        ConflictPolicy::Overwrite => overwrite("overwrite existing file".into()),
+        ConflictPolicy::Rename => Ok(Plan::Download(rename_path(output_path)?)),
+        ConflictPolicy::Newer => {
+            let Some(remote) = file.modified() else {
+                return Ok(Plan::Skip("remote modified time unknown".into()));
+            };
+            let local = local_modified(&metadata)?;
+            if remote > local {
+                overwrite(format!("remote is newer: {} > {}", remote, local))
+            } else {
+                Ok(Plan::Skip("local file is up to date".into()))
+            }
+        }
+        ConflictPolicy::SizeMismatch => match remote_size {
+            None => Ok(Plan::Skip("remote size unknown".into())),
+            Some(remote_size) if local_size != remote_size => overwrite(format!(
+                "size mismatch: local {}, remote {}",
+                local_size, remote_size
+            )),
+            Some(_) => Ok(Plan::Skip("same size".into())),
+        },
+        ConflictPolicy::Checksum => {
+            // 大小不同时不需要计算 hash
+            if let Some(remote_size) = remote_size.filter(|x| *x != local_size) {
+                return overwrite(format!(
+                    "size mismatch: local {}, remote {}",
+                    local_size, remote_size
+                ));
+            }
+            match checksum_matches(file, output_path)? {
+                Some(true) => Ok(Plan::Skip("same checksum".into())),
+                Some(false) => overwrite("checksum mismatch".into()),
+                None => Ok(Plan::Skip("same size, remote checksum unknown".into())),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remote(content: &[u8], modified_time: &str) -> FileType {
+        FileType {
+            size: content.len().to_string(),
+            md5_checksum: format!("{:x}", md5::compute(content)),
+            modified_time: modified_time.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_plan_download() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("a.txt");
+        let old = "2000-01-01T00:00:00.000+08:00";
+        let new = "2099-01-01T00:00:00.000+08:00";
+        let plan = |file: &FileType, policy| plan_download(file, &path, policy);
+
+        let file = remote(b"hello world", old);
+        assert_eq!(
+            plan(&file, ConflictPolicy::Skip)?,
+            Plan::Download(path.clone())
+        );
+
+        std::fs::write(&path, b"hello world")?;
+        assert!(matches!(plan(&file, ConflictPolicy::Skip)?, Plan::Skip(_)));
+        assert!(matches!(
+            plan(&file, ConflictPolicy::Overwrite)?,
+            Plan::Overwrite(..)
+        ));
+        assert!(matches!(plan(&file, ConflictPolicy::Newer)?, Plan::Skip(_)));
+        assert!(matches!(
+            plan(&remote(b"hello world", new), ConflictPolicy::Newer)?,
+            Plan::Overwrite(..)
+        ));
+        assert!(matches!(
+            plan(&file, ConflictPolicy::SizeMismatch)?,
+            Plan::Skip(_)
+        ));
+        assert!(matches!(
+            plan(&remote(b"hello", old), ConflictPolicy::SizeMismatch)?,
+            Plan::Overwrite(..)
+        ));
+        assert!(matches!(
+            plan(&file, ConflictPolicy::Checksum)?,
+            Plan::Skip(_)
+        ));
+        assert!(matches!(
+            plan(&remote(b"hello WORLD", old), ConflictPolicy::Checksum)?,
+            Plan::Overwrite(..)
+        ));
+
+        std::fs::write(dir.path().join("a (1).txt"), b"")?;
+        assert_eq!(
+            plan(&file, ConflictPolicy::Rename)?,
+            Plan::Download(dir.path().join("a (2).txt"))
+        );
+
+        std::fs::write(flag_path(&path), b"")?;
+        assert_eq!(
+            plan(&file, ConflictPolicy::Overwrite)?,
+            Plan::Resume(path.clone())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_legacy_flag() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("Makefile");
+        let legacy = dir.path().join("Makefile..pikpakclidownload");
+        assert_eq!(legacy_flag_path(&path), Some(legacy.clone()));
+        assert_eq!(legacy_flag_path(&dir.path().join("a.txt")), None);
+
+        std::fs::write(&path, b"all")?;
+        std::fs::write(&legacy, b"")?;
+        let file = FileType::default();
+        assert_eq!(
+            plan_download(&file, &path, ConflictPolicy::Skip)?,
+            Plan::Resume(path.clone())
+        );
+        migrate_legacy_flag(&path)?;
+        assert!(!legacy.exists());
+        assert!(flag_path(&path).exists());
+        Ok(())
+    }
+}
@@ -1,113 +1,529 @@
 use std::path::Path;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
+use crate::args::{ConflictPolicy, Quality};
+use crate::cli::aria2::Aria2;
+use crate::cli::conflict::{flag_path, migrate_legacy_flag, plan_download, remove_flags, Plan};
+use crate::cli::filter::DownloadFilter;
+use crate::cli::journal::{EntryState, JobEntry, Journal};
+use crate::cli::partial::{self, partial_path, sidecar_path};
+use crate::cli::quality::variant_path;
+use crate::cli::sanitize::Sanitizer;
+use crate::cli::summary::{Outcome, OutcomeStatus, Summary};
+use crate::cli::thumbnail::{save_thumbnail, ThumbnailOptions};
+use crate::config::DEFAULT_JOB_DIR;
 use crate::pikpak::download::download_with_file;
-use crate::pikpak::file::FileType;
+use crate::pikpak::file::{FileStatus, FileType};
+use crate::pikpak::folder::FileIDType;
 use crate::pikpak::Client;
-use crate::utils::file::create_dir_if_not_exists;
+use crate::utils::file::{create_dir_if_not_exists, set_modified, set_xattrs};
 use crate::utils::path::slash;
 use anyhow::Context;
 use anyhow::Ok;
 use anyhow::Result;
 use async_recursion::async_recursion;
+use humansize::{format_size, DECIMAL};
 use itertools::Itertools;
 use log::*;
 use tokio::fs;
-use tokio::fs::File;
 use tokio::sync::Semaphore;
 use tokio::time::sleep;
 
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    pub output: String,
+    pub parallel: usize,
+    pub job_dir: PathBuf,
+    pub report: Option<PathBuf>,
+    pub on_conflict: ConflictPolicy,
+    pub dry_run: bool,
+    pub xattrs: bool,
+    pub atomic: bool,
+    pub sanitizer: Sanitizer,
+    pub aria2: Option<Aria2>,
+    pub quality: Quality,
+    // 下载完成后同时保存缩略图
+    pub thumbnails: Option<ThumbnailOptions>,
+    pub filter: DownloadFilter,
+}
+
+// 要下载的文件, 直接指定的单个文件没有列目录得到的信息, status 中只有 id 和文件名
+pub(super) struct RemoteFile {
+    pub entry: JobEntry,
+    pub status: FileStatus,
+    pub listed: bool,
+}
+
+// 遍历远程目录时的上下文
+struct Walk<'a> {
+    root: PathBuf,
+    local_root: PathBuf,
+    filter: &'a DownloadFilter,
+    sanitizer: &'a Sanitizer,
+    tasks: Vec<(JobEntry, FileStatus)>,
+    failures: Vec<Outcome>,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        DownloadOptions {
+            output: "./".into(),
+            parallel: 4,
+            job_dir: DEFAULT_JOB_DIR.into(),
+            report: None,
+            on_conflict: ConflictPolicy::Skip,
+            dry_run: false,
+            xattrs: false,
+            atomic: false,
+            sanitizer: Sanitizer::default(),
+            aria2: None,
+            quality: Quality::Original,
+            thumbnails: None,
+            filter: DownloadFilter::default(),
+        }
+    }
+}
+
 impl Client {
-    pub async fn download(
-        mut self,
-        paths: Vec<String>,
-        output: String,
-        parallel: usize,
-    ) -> Result<()> {
-        let output_dir = Path::new(&output);
-        create_dir_if_not_exists(output_dir)?;
-        let mut tasks: Vec<(String, PathBuf)> = Vec::new();
+    pub async fn download(mut self, paths: Vec<String>, opts: DownloadOptions) -> Result<()> {
         if paths.is_empty() {
             info!("please input path");
             return Ok(());
         }
+        let start = Instant::now();
+        let (entries, failures) = self.build_entries(paths, &opts).await?;
+        debug!("tasks: {:#?}", entries);
+        if opts.dry_run {
+            let planned = self
+                .plan_entries(entries, opts.on_conflict, opts.quality)
+                .await?;
+            print_plan(&planned, &failures);
+            return Ok(());
+        }
+
+        let journal = Journal::create(&opts.job_dir, opts.output.clone(), entries)?;
+        info!(
+            "created download job: {}, files: {}",
+            journal.id,
+            journal.entries.len()
+        );
+        let summary = self.run_job(journal, &opts, failures, start).await?;
+        finish(summary, &opts)
+    }
+
+    pub async fn resume_download(self, job: &str, opts: DownloadOptions) -> Result<()> {
+        let start = Instant::now();
+        let journal = Journal::load(&opts.job_dir, job)?;
+        info!(
+            "resuming download job: {}, unfinished files: {}",
+            journal.id,
+            journal.unfinished().len()
+        );
+        let summary = self.run_job(journal, &opts, vec![], start).await?;
+        finish(summary, &opts)
+    }
+
+    // 重新下载报告中失败的文件, 远程目录遍历失败的路径会重新遍历
+    pub async fn retry_download(mut self, report: &Path, opts: DownloadOptions) -> Result<()> {
+        let start = Instant::now();
+        let report = Summary::load(report)?;
+        let mut entries = vec![];
+        let mut paths = vec![];
+        for item in report.failed_items() {
+            if item.remote_id.is_empty() {
+                paths.push(item.remote_path.clone());
+            } else {
+                entries.push(JobEntry::new(
+                    item.remote_id.clone(),
+                    item.remote_path.clone(),
+                    item.local_path.clone(),
+                    0,
+                ));
+            }
+        }
+        let (list_entries, failures) = self.build_entries(paths, &opts).await?;
+        entries.extend(list_entries);
+        if entries.is_empty() && failures.is_empty() {
+            info!("no failed items in report");
+            return Ok(());
+        }
+
+        let journal = Journal::create(&opts.job_dir, opts.output.clone(), entries)?;
+        info!(
+            "created retry job: {}, files: {}",
+            journal.id,
+            journal.entries.len()
+        );
+        let summary = self.run_job(journal, &opts, failures, start).await?;
+        finish(summary, &opts)
+    }
+
+    pub(super) async fn build_entries(
+        &mut self,
+        paths: Vec<String>,
+        opts: &DownloadOptions,
+    ) -> Result<(Vec<JobEntry>, Vec<Outcome>)> {
+        let (files, failures) = self.build_files(paths, opts).await?;
+        Ok((files.into_iter().map(|x| x.entry).collect(), failures))
+    }
+
+    // 同时返回列目录时得到的文件信息, 直接指定的单个文件只有 id 和文件名
+    pub(super) async fn build_files(
+        &mut self,
+        paths: Vec<String>,
+        opts: &DownloadOptions,
+    ) -> Result<(Vec<RemoteFile>, Vec<Outcome>)> {
+        let output_dir = Path::new(&opts.output);
+        let mut tasks = Vec::new();
+        let mut failures = Vec::new();
         for path in paths {
             debug!("finding path: {}", path);
-            let id = self.get_path_id(&path).await?;
+            // 找不到的路径记为失败, 继续处理其他路径, 重试时会重新查找
+            let id = match self.get_path_id(&path).await {
+                Result::Ok(id) => id,
+                Err(err) => {
+                    error!("find {} failed, err: {:#}", path, err);
+                    failures.push(Outcome::new(
+                        "",
+                        &path,
+                        Path::new(""),
+                        OutcomeStatus::Failed,
+                        format!("find path failed: {:#}", err),
+                        0,
+                        Default::default(),
+                    ));
+                    continue;
+                }
+            };
             match id {
-                crate::pikpak::folder::FileIDType::File(id) => {
-                    let path = slash(&path)?;
-                    tasks.push((id, output_dir.join(path)))
+                FileIDType::File(id) => {
+                    let local_path =
+                        output_dir.join(opts.sanitizer.path(Path::new(&slash(&path)?)));
+                    let status = FileStatus {
+                        id: id.clone(),
+                        name: path.rsplit('/').next().unwrap_or_default().to_string(),
+                        ..Default::default()
+                    };
+                    tasks.push(RemoteFile {
+                        entry: JobEntry::new(id, path, local_path, 0),
+                        status,
+                        listed: false,
+                    })
                 }
-                crate::pikpak::folder::FileIDType::Folder(id) => {
-                    self.recursive_get_file(&mut tasks, id, PathBuf::from(path), output_dir)
-                        .await?
+                FileIDType::Folder(id) => {
+                    let mut walk = Walk {
+                        local_root: output_dir.join(opts.sanitizer.path(Path::new(&slash(&path)?))),
+                        root: PathBuf::from(path),
+                        filter: &opts.filter,
+                        sanitizer: &opts.sanitizer,
+                        tasks: vec![],
+                        failures: vec![],
+                    };
+                    let local_dir = walk.local_root.clone();
+                    self.recursive_get_file(&mut walk, id, PathBuf::new(), local_dir, 0)
+                        .await?;
+                    tasks.extend(walk.tasks.into_iter().map(|(entry, status)| RemoteFile {
+                        entry,
+                        status,
+                        listed: true,
+                    }));
+                    failures.extend(walk.failures);
                 }
             }
         }
 
-        tasks = tasks.into_iter().unique_by(|x| x.0.clone()).collect();
+        let tasks = tasks
+            .into_iter()
+            .unique_by(|x| x.entry.remote_id.clone())
+            .collect();
+        Ok((tasks, failures))
+    }
+
+    // 只检查本地文件, 不创建目录和 flag 文件
+    async fn plan_entries(
+        &mut self,
+        entries: Vec<JobEntry>,
+        policy: ConflictPolicy,
+        quality: Quality,
+    ) -> Result<Vec<(JobEntry, Plan)>> {
+        let mut planned = vec![];
+        for mut entry in entries {
+            // 本地文件不存在时不需要远程的详细信息, 转码视频的大小和扩展名与原始文件不同
+            let file_info =
+                if entry.size == 0 || entry.local_path.exists() || quality.height().is_some() {
+                    let file_info = self
+                        .get_file_with_quality(entry.remote_id.clone(), quality)
+                        .await?;
+                    entry.size = file_info.size.parse().unwrap_or(entry.size);
+                    entry.local_path = variant_path(&entry.local_path, &file_info);
+                    file_info
+                } else {
+                    FileType {
+                        size: entry.size.to_string(),
+                        ..Default::default()
+                    }
+                };
+            let plan = plan_download(&file_info, &entry.local_path, policy)?;
+            planned.push((entry, plan));
+        }
+        Ok(planned)
+    }
 
-        debug!("tasks: {:#?}", tasks);
+    async fn run_job(
+        mut self,
+        journal: Journal,
+        opts: &DownloadOptions,
+        failures: Vec<Outcome>,
+        start: Instant,
+    ) -> Result<Summary> {
+        // 推送到 aria2 时由 aria2 创建目录
+        if let Some(aria2) = &opts.aria2 {
+            return self
+                .run_aria2(journal, aria2, opts.quality, failures, start)
+                .await;
+        }
+        create_dir_if_not_exists(Path::new(&journal.output))?;
+        let unfinished = journal.unfinished();
+        let mut outcomes = failures;
+        outcomes.extend(finished_outcomes(&journal));
+        let outcomes = Arc::new(Mutex::new(outcomes));
+        let journal = Arc::new(Mutex::new(journal));
 
-        let semaphore = Arc::new(Semaphore::new(parallel));
+        let semaphore = Arc::new(Semaphore::new(opts.parallel));
         let mut threads = vec![];
-        for task in tasks {
+        for index in unfinished {
             let permit = semaphore.clone().acquire_owned().await?;
 
-            let file_info = self.get_file_by_id(task.0).await?;
-            let retry_times = self.retry_times;
+            let task_start = Instant::now();
+            let remote_id = lock(&journal).entries[index].remote_id.clone();
+            let file_info = match self.get_file_with_quality(remote_id, opts.quality).await {
+                Result::Ok(file_info) => file_info,
+                Err(err) => {
+                    error!("get file info failed, err: {:#?}", err);
+                    let outcome = update_entry(&journal, index, |entry| {
+                        entry.attempts += 1;
+                        entry.state = EntryState::Failed;
+                        entry.error = format!("{:#}", err);
+                        Outcome::new(
+                            &entry.remote_id,
+                            &entry.remote_path,
+                            &entry.local_path,
+                            OutcomeStatus::Failed,
+                            entry.error.clone(),
+                            0,
+                            task_start.elapsed(),
+                        )
+                    });
+                    outcomes.lock().expect("lock outcomes failed").push(outcome);
+                    continue;
+                }
+            };
+            let output_path = update_entry(&journal, index, |entry| {
+                entry.attempts += 1;
+                entry.state = EntryState::Downloading;
+                entry.size = file_info.size.parse().unwrap_or(entry.size);
+                entry.local_path = variant_path(&entry.local_path, &file_info);
+                entry.local_path.clone()
+            });
+            let (retry_times, retry_interval) = (self.retry_times, self.retry_interval);
+            let journal = journal.clone();
+            let outcomes = outcomes.clone();
+
+            let policy = opts.on_conflict;
+            let xattrs = opts.xattrs;
+            let atomic = opts.atomic;
+            let thumbnails = opts.thumbnails.clone();
+            let thumbnail_link = file_info.thumbnail_link.clone();
 
             threads.push(tokio::spawn(async move {
                 let _permit = permit;
-                if let Err(err) = download_file(file_info, task.1, retry_times).await {
-                    error!("download file failed, err: {:#?}", err);
+                let mut path = output_path.clone();
+                let mut size_before = 0;
+                let res = async {
+                    let planned = output_path.clone();
+                    let (file_info, plan) = tokio::task::spawn_blocking(move || {
+                        let plan = plan_download(&file_info, &planned, policy);
+                        (file_info, plan)
+                    })
+                    .await?;
+                    let plan = plan?;
+                    let Some(plan_path) = plan.path() else {
+                        return Ok(plan);
+                    };
+                    if plan_path != output_path {
+                        info!("local file exists, download as: {}", plan_path.display());
+                        path = plan_path.to_path_buf();
+                        update_entry(&journal, index, |entry| entry.local_path = path.clone());
+                    }
+                    if let Plan::Overwrite(_, reason) = &plan {
+                        info!("overwrite {}: {}", path.display(), reason);
+                        // 原子模式下重命名时直接替换
+                        if !atomic {
+                            fs::remove_file(&path)
+                                .await
+                                .context("[download] failed to remove existing file")?;
+                        }
+                    }
+                    size_before = local_size(&download_path(&path, atomic));
+                    download_file(
+                        &file_info,
+                        path.clone(),
+                        retry_times,
+                        retry_interval,
+                        atomic,
+                    )
+                    .await?;
+                    apply_metadata(&file_info, &path, xattrs);
+                    Ok(plan)
                 }
+                .await;
+
+                // 跳过的文件已经在本地, 同样保存缩略图
+                if let (Some(thumbnails), Result::Ok(_)) = (&thumbnails, &res) {
+                    if let Err(err) = save_thumbnail(&thumbnail_link, &path, thumbnails).await {
+                        warn!(
+                            "save thumbnail of {} failed, err: {:#}",
+                            path.display(),
+                            err
+                        );
+                    }
+                }
+
+                let bytes_done = match res {
+                    Err(_) => local_size(&download_path(&path, atomic)),
+                    _ => local_size(&path),
+                };
+                let outcome = update_entry(&journal, index, |entry| {
+                    entry.bytes_done = bytes_done;
+                    let (status, reason) = match res {
+                        Result::Ok(Plan::Skip(reason)) => {
+                            info!("skip {}: {}", path.display(), reason);
+                            entry.state = EntryState::Done;
+                            entry.error.clear();
+                            (OutcomeStatus::Skipped, reason)
+                        }
+                        Result::Ok(_) => {
+                            entry.state = EntryState::Done;
+                            entry.error.clear();
+                            (OutcomeStatus::Downloaded, String::new())
+                        }
+                        Err(err) => {
+                            error!("download file failed, err: {:#?}", err);
+                            entry.state = EntryState::Failed;
+                            entry.error = format!("{:#}", err);
+                            (OutcomeStatus::Failed, entry.error.clone())
+                        }
+                    };
+                    Outcome::new(
+                        &entry.remote_id,
+                        &entry.remote_path,
+                        &entry.local_path,
+                        status,
+                        reason,
+                        bytes_done.saturating_sub(size_before),
+                        task_start.elapsed(),
+                    )
+                });
+                outcomes.lock().expect("lock outcomes failed").push(outcome);
             }));
         }
 
         futures::future::join_all(threads).await;
 
-        Ok(())
+        let mut journal = lock(&journal);
+        if let Err(err) = journal.flush() {
+            error!("save job journal failed, err: {:#?}", err);
+        }
+        let unfinished = journal.unfinished().len();
+        if unfinished == 0 {
+            info!("download job {} finished", journal.id);
+        } else {
+            warn!(
+                "download job {} has {} unfinished files, run `download --resume {}` to continue",
+                journal.id, unfinished, journal.id
+            );
+        }
+
+        let outcomes = std::mem::take(&mut *outcomes.lock().expect("lock outcomes failed"));
+        Ok(Summary::new(&journal.id, outcomes, start.elapsed()))
     }
 
     #[async_recursion(?Send)]
     async fn recursive_get_file(
         &mut self,
-        tasks: &mut Vec<(String, PathBuf)>,
+        walk: &mut Walk<'_>,
         parent_id: String,
-        parent_path: PathBuf,
-        output_dir: &Path,
+        rel_path: PathBuf,
+        local_dir: PathBuf,
+        depth: usize,
     ) -> Result<()> {
+        let parent_path = walk.root.join(&rel_path);
         debug!(
             "recursive get file, parent_id: {}, parent_path: {}",
             parent_id,
             parent_path.display()
         );
         let status_list = self.get_file_status_list_by_folder_id(&parent_id).await?;
+        let local_names = walk.sanitizer.dedupe(
+            &status_list
+                .iter()
+                .map(|x| (x.id.as_str(), x.name.as_str()))
+                .collect::<Vec<_>>(),
+        );
 
-        for status in status_list {
+        for (status, local_name) in status_list.into_iter().zip(local_names) {
+            let rel_file_path = rel_path.join(&status.name);
+            let rel = rel_file_path.to_string_lossy();
+            let remote_path = parent_path.join(&status.name);
+            let local_path = local_dir.join(&local_name);
+            if local_name != status.name {
+                info!(
+                    "rename local path: {} -> {}",
+                    remote_path.display(),
+                    local_path.display()
+                );
+            }
             if status.kind == "drive#folder" {
+                if !walk.filter.allow_folder(&rel, depth + 1) {
+                    debug!("skip folder by filter: {}", rel);
+                    continue;
+                }
                 if let Err(err) = self
-                    .recursive_get_file(tasks, status.id, parent_path.join(status.name), output_dir)
+                    .recursive_get_file(
+                        walk,
+                        status.id,
+                        rel_file_path.clone(),
+                        local_path,
+                        depth + 1,
+                    )
                     .await
                 {
                     error!("recursive get file failed, err: {:#?}", err);
+                    walk.failures.push(Outcome::new(
+                        "",
+                        &remote_path.to_string_lossy(),
+                        Path::new(""),
+                        OutcomeStatus::Failed,
+                        format!("list folder failed: {:#}", err),
+                        0,
+                        Default::default(),
+                    ));
                 }
             } else {
-                let parent_path = slash(
-                    parent_path
-                        .to_str()
-                        .ok_or(anyhow::anyhow!("slash parent path error"))?,
-                )?;
-                if parent_path.starts_with('/') {
-                    return Err(anyhow::anyhow!(
-                        "parent path starts with /, parent_path: {}",
-                        parent_path
-                    ));
+                if !walk.filter.allow_file(&rel, &status) {
+                    debug!("skip file by filter: {}", rel);
+                    continue;
                 }
-                tasks.push((status.id, output_dir.join(&parent_path).join(status.name)));
+                let entry = JobEntry::new(
+                    status.id.clone(),
+                    remote_path.to_string_lossy().to_string(),
+                    local_path,
+                    status.size.parse().unwrap_or_default(),
+                );
+                walk.tasks.push((entry, status));
             }
         }
 
@@ -115,7 +531,130 @@ impl Client {
     }
 }
 
-async fn download_file(file: FileType, output_path: PathBuf, retry_times: i8) -> Result<()> {
+// 打印汇总信息, 有失败的文件时返回错误
+fn finish(summary: Summary, opts: &DownloadOptions) -> Result<()> {
+    summary.print();
+    if let Some(report) = &opts.report {
+        summary.save(report)?;
+        info!("report saved: {}", report.display());
+    }
+    if summary.failed > 0 {
+        return Err(anyhow::anyhow!(
+            "{} items failed to download, see the summary above",
+            summary.failed
+        ));
+    }
+    Ok(())
+}
+
+fn print_plan(planned: &[(JobEntry, Plan)], failures: &[Outcome]) {
+    let mut total = 0;
+    for (entry, plan) in planned {
+        let (action, bytes, reason) = match plan {
+            Plan::Download(_) => ("download", entry.size, ""),
+            Plan::Resume(path) => {
+                let partial = if sidecar_path(path).exists() {
+                    partial_path(path)
+                } else {
+                    path.clone()
+                };
+                (
+                    "resume",
+                    entry.size.saturating_sub(local_size(&partial)),
+                    "",
+                )
+            }
+            Plan::Overwrite(_, reason) => ("overwrite", entry.size, reason.as_str()),
+            Plan::Skip(reason) => ("skip", 0, reason.as_str()),
+        };
+        total += bytes;
+        let local = plan.path().unwrap_or(&entry.local_path);
+        println!(
+            "{:<10} {:>10}  {} -> {}",
+            action,
+            format_size(bytes, DECIMAL),
+            entry.remote_path,
+            local.display()
+        );
+        if !reason.is_empty() {
+            println!("{:<10} {:>10}  ({})", "", "", reason);
+        }
+    }
+    for item in failures {
+        println!(
+            "{:<10} {:>10}  {}: {}",
+            "failed", "", item.remote_path, item.reason
+        );
+    }
+
+    let count = |f: fn(&Plan) -> bool| planned.iter().filter(|(_, x)| f(x)).count();
+    println!();
+    println!(
+        "dry run: {} to download, {} to resume, {} to overwrite, {} to skip, {} failed",
+        count(|x| matches!(x, Plan::Download(_))),
+        count(|x| matches!(x, Plan::Resume(_))),
+        count(|x| matches!(x, Plan::Overwrite(..))),
+        count(|x| matches!(x, Plan::Skip(_))),
+        failures.len()
+    );
+    println!("total to transfer: {}", format_size(total, DECIMAL));
+}
+
+// 任务中已经完成的文件, 恢复任务时不再下载
+pub(super) fn finished_outcomes(journal: &Journal) -> Vec<Outcome> {
+    journal
+        .entries
+        .iter()
+        .filter(|x| x.finished())
+        .map(|entry| {
+            Outcome::new(
+                &entry.remote_id,
+                &entry.remote_path,
+                &entry.local_path,
+                OutcomeStatus::Skipped,
+                "already downloaded in this job".into(),
+                0,
+                Default::default(),
+            )
+        })
+        .collect()
+}
+
+fn lock(journal: &Mutex<Journal>) -> std::sync::MutexGuard<'_, Journal> {
+    journal.lock().expect("lock journal failed")
+}
+
+// 更新任务状态, 日志按间隔写入, 任务结束时 flush
+fn update_entry<T>(
+    journal: &Mutex<Journal>,
+    index: usize,
+    f: impl FnOnce(&mut JobEntry) -> T,
+) -> T {
+    let mut journal = lock(journal);
+    let res = f(&mut journal.entries[index]);
+    if let Err(err) = journal.save_throttled() {
+        error!("save job journal failed, err: {:#?}", err);
+    }
+    res
+}
+
+// 实际写入的文件, 原子模式下为隐藏的临时文件
+fn download_path(output_path: &Path, atomic: bool) -> PathBuf {
+    if atomic {
+        partial_path(output_path)
+    } else {
+        output_path.to_path_buf()
+    }
+}
+
+// 下载到 output_path, 存在 flag 或临时文件时从中断的位置继续
+pub(super) async fn download_file(
+    file: &FileType,
+    output_path: PathBuf,
+    retry_times: i8,
+    retry_interval: Duration,
+    atomic: bool,
+) -> Result<()> {
     info!("downloading file: {:#?}", output_path);
 
     let file_dir = output_path
@@ -123,53 +662,535 @@ async fn download_file(file: FileType, output_path: PathBuf, retry_times: i8) ->
         .ok_or(anyhow::anyhow!("[download_file] failed to get parent dir"))?;
     create_dir_if_not_exists(file_dir)?;
 
-    let exist = output_path
-        .try_exists()
-        .context("[download_file] get file exists err")?;
+    let flag_path = flag_path(&output_path);
+    let target = if atomic {
+        partial::prepare(&output_path, file)?
+    } else {
+        migrate_legacy_flag(&output_path)?;
+        // flag 中记录下载的转码视频, 与这次不同时已下载的部分不能续传
+        let flag = flag_path
+            .try_exists()
+            .context("[download_file] get flag err")?;
+        if flag && fs::read_to_string(&flag_path).await.unwrap_or_default() != file.variant {
+            warn!(
+                "partial file {} is another variant, download again",
+                output_path.display()
+            );
+            if output_path.exists() {
+                fs::remove_file(&output_path)
+                    .await
+                    .context("[download_file] failed to remove partial file")?;
+            }
+        }
+        fs::write(&flag_path, &file.variant)
+            .await
+            .context("[download_file] failed to write flag file")?;
+        output_path.clone()
+    };
 
-    let mut flag_path = output_path.clone();
-    let ex = flag_path
-        .extension()
-        .and_then(|x| x.to_str())
-        .unwrap_or_default()
-        .to_string();
-    if !flag_path.set_extension(ex + ".pikpakclidownload") {
-        return Err(anyhow::anyhow!("[download_file] failed to set extension"));
+    let mut now = 0;
+    while let Err(err) = download_with_file(target.as_path(), file, retry_times).await {
+        // 下载失败时保留 flag, 下次可以继续下载
+        if retry_times >= 0 && now >= retry_times {
+            return Err(err.context(format!(
+                "[download_file] download failed after {} retries",
+                now
+            )));
+        }
+        error!(
+            "[download_file] download failed, err: {:#?}, retry: {} times",
+            err, now
+        );
+        sleep(retry_interval).await;
+        now += 1;
     }
-    let flag = flag_path
-        .try_exists()
-        .context("[download_file] get flag err")?;
 
-    if exist && !flag {
-        info!(
-            "[download_file] file exists and not download flag, skip: {}",
-            output_path.display()
-        );
-        return Ok(());
+    if atomic {
+        // 计算 hash 比较耗时, 不阻塞其他下载任务
+        let file = file.clone();
+        let output_path = output_path.clone();
+        tokio::task::spawn_blocking(move || partial::finalize(&output_path, &file)).await??;
     }
-    if !flag {
-        File::create(&flag_path)
-            .await
-            .context("[download_file] failed to create flag file")?;
+    remove_flags(&output_path).context("[download_file] failed to remove flag")?;
+    info!("file downloaded: {:#?}", output_path);
+    Ok(())
+}
+
+// 使用远程文件的修改时间, 可选写入文件 id 等扩展属性, 失败时不影响下载结果
+pub(super) fn apply_metadata(file: &FileType, path: &Path, xattrs: bool) {
+    if let Some(modified) = file.modified() {
+        if let Err(err) = set_modified(path, modified.into()) {
+            warn!("set modified time failed, err: {:#}", err);
+        }
+    }
+    if xattrs {
+        let attrs = [
+            ("pikpak.id", file.id.as_str()),
+            ("pikpak.hash", file.hash.as_str()),
+            ("pikpak.original_url", file.original_url.as_str()),
+        ];
+        if let Err(err) = set_xattrs(path, &attrs) {
+            warn!("set xattrs failed, err: {:#}", err);
+        }
     }
+}
 
-    let mut now = 0;
-    while now <= retry_times || retry_times < 0 {
-        if let Err(err) = download_with_file(output_path.as_path(), &file, retry_times).await {
-            error!(
-                "[download_file] download failed, err: {:#?}, retry: {} times",
-                err, now
+fn local_size(path: &Path) -> u64 {
+    std::fs::metadata(path).map(|x| x.len()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pikpak::mock::MockServer;
+
+    #[tokio::test]
+    async fn test_download_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        server.state().truncate_downloads = 1;
+        let mut client = server.client();
+        client.retry_times = 1;
+        client.retry_interval = Duration::ZERO;
+        client.login().await?;
+
+        let dir = tempfile::tempdir()?;
+        let output = dir.path().to_str().unwrap().to_string();
+        let opts = DownloadOptions {
+            output,
+            parallel: 2,
+            job_dir: dir.path().join("jobs"),
+            ..Default::default()
+        };
+        client
+            .download(vec!["/My Pack".into(), "readme.md".into()], opts.clone())
+            .await?;
+
+        let state = server.state();
+        for (path, id) in [
+            ("My Pack/a.txt", "file-a"),
+            ("My Pack/sub/b.bin", "file-b"),
+            ("readme.md", "file-readme"),
+        ] {
+            let path = dir.path().join(path);
+            assert_eq!(std::fs::read(&path)?, state.file(id).unwrap().content);
+            // 修改时间与远程文件一致
+            let modified: chrono::DateTime<chrono::Utc> =
+                std::fs::metadata(&path)?.modified()?.into();
+            assert_eq!(modified.to_rfc3339(), "2023-12-31T16:00:00+00:00");
+            let mut flag = path.into_os_string();
+            flag.push(".pikpakclidownload");
+            assert!(!PathBuf::from(flag).exists());
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resume_download_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        server.state().truncate_downloads = 1;
+        let mut client = server.client();
+        client.login().await?;
+
+        let dir = tempfile::tempdir()?;
+        let opts = DownloadOptions {
+            output: dir.path().to_str().unwrap().to_string(),
+            parallel: 1,
+            job_dir: dir.path().join("jobs"),
+            ..Default::default()
+        };
+        let res = client.download(vec!["/My Pack".into()], opts.clone()).await;
+        assert!(res.is_err());
+
+        let job = Journal::list(&opts.job_dir)?.remove(0);
+        assert_eq!(job.count(EntryState::Done), 1);
+        assert_eq!(job.count(EntryState::Failed), 1);
+        let failed = job
+            .entries
+            .iter()
+            .find(|x| x.state == EntryState::Failed)
+            .unwrap();
+        assert!(failed.bytes_done > 0 && failed.bytes_done < failed.size);
+
+        let list_requests = server.state().list_requests;
+        let mut client = server.client();
+        client.login().await?;
+        client.resume_download(&job.id, opts.clone()).await?;
+
+        let job = Journal::load(&opts.job_dir, &job.id)?;
+        assert!(job.unfinished().is_empty());
+        assert_eq!(job.entries.iter().map(|x| x.attempts).sum::<u32>(), 3);
+        // 恢复任务时不会重新遍历远程目录
+        assert_eq!(server.state().list_requests, list_requests);
+        let state = server.state();
+        for entry in job.entries {
+            assert_eq!(entry.bytes_done, entry.size);
+            assert_eq!(
+                std::fs::read(&entry.local_path)?,
+                state.file(&entry.remote_id).unwrap().content
             );
-            sleep(std::time::Duration::from_secs(10)).await;
-        } else {
-            break;
         }
-        now += 1;
+        Ok(())
     }
 
-    fs::remove_file(&flag_path)
-        .await
-        .context("[download_file] failed to remove flag")?;
-    info!("file downloaded: {:#?}", output_path);
-    Ok(())
+    #[tokio::test]
+    async fn test_report_and_retry_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        server.state().truncate_downloads = 1;
+        let mut client = server.client();
+        client.login().await?;
+
+        let dir = tempfile::tempdir()?;
+        let report = dir.path().join("report.json");
+        let opts = DownloadOptions {
+            output: dir.path().to_str().unwrap().to_string(),
+            parallel: 1,
+            job_dir: dir.path().join("jobs"),
+            report: Some(report.clone()),
+            ..Default::default()
+        };
+        let res = client
+            .download(vec!["/My Pack".into(), "readme.md".into()], opts.clone())
+            .await;
+        assert!(res.is_err());
+
+        let summary = Summary::load(&report)?;
+        assert_eq!(
+            (summary.downloaded, summary.skipped, summary.failed),
+            (2, 0, 1)
+        );
+        let failed: Vec<_> = summary.failed_items().collect();
+        assert_eq!(failed[0].remote_path, "/My Pack/a.txt");
+        assert!(!failed[0].reason.is_empty());
+
+        let mut client = server.client();
+        client.login().await?;
+        let retry_report = dir.path().join("retry.json");
+        let opts = DownloadOptions {
+            report: Some(retry_report.clone()),
+            ..opts
+        };
+        client.retry_download(&report, opts).await?;
+
+        let summary = Summary::load(&retry_report)?;
+        assert_eq!(
+            (summary.downloaded, summary.skipped, summary.failed),
+            (1, 0, 0)
+        );
+        // 从上次中断的位置继续下载
+        assert_eq!(summary.bytes, 11 - 11 / 2);
+        assert_eq!(
+            std::fs::read(dir.path().join("My Pack/a.txt"))?,
+            b"hello world"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_missing_path_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        let mut client = server.client();
+        client.login().await?;
+
+        let dir = tempfile::tempdir()?;
+        let report = dir.path().join("report.json");
+        let opts = DownloadOptions {
+            output: dir.path().to_str().unwrap().to_string(),
+            parallel: 1,
+            job_dir: dir.path().join("jobs"),
+            report: Some(report.clone()),
+            ..Default::default()
+        };
+        // 不存在的路径记为失败, 其他路径照常下载
+        let res = client
+            .download(vec!["/missing".into(), "readme.md".into()], opts)
+            .await;
+        assert!(res.is_err());
+        assert!(dir.path().join("readme.md").exists());
+
+        let summary = Summary::load(&report)?;
+        assert_eq!(
+            (summary.downloaded, summary.skipped, summary.failed),
+            (1, 0, 1)
+        );
+        let failed: Vec<_> = summary.failed_items().collect();
+        assert_eq!(failed[0].remote_path, "/missing");
+        assert!(failed[0].remote_id.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_filter_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        let mut client = server.client();
+        client.login().await?;
+
+        let dir = tempfile::tempdir()?;
+        let opts = DownloadOptions {
+            output: dir.path().to_str().unwrap().to_string(),
+            parallel: 1,
+            job_dir: dir.path().join("jobs"),
+            filter: DownloadFilter::new(&crate::args::FilterArgs {
+                exclude: vec!["sub".into()],
+                ..Default::default()
+            })?,
+            ..Default::default()
+        };
+        client.download(vec!["/My Pack".into()], opts).await?;
+
+        assert!(dir.path().join("My Pack/a.txt").exists());
+        assert!(!dir.path().join("My Pack/sub").exists());
+        // 只有查找路径和遍历 My Pack 的请求, 被排除的目录不会被遍历
+        assert_eq!(server.state().list_requests, 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_conflict_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        let mut client = server.client();
+        client.login().await?;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("My Pack/a.txt");
+        create_dir_if_not_exists(path.parent().unwrap())?;
+        std::fs::write(&path, b"hello WORLD")?;
+
+        let download = |policy| {
+            let mut client = server.client();
+            let opts = DownloadOptions {
+                output: dir.path().to_str().unwrap().to_string(),
+                parallel: 1,
+                job_dir: dir.path().join("jobs"),
+                on_conflict: policy,
+                filter: DownloadFilter::new(&crate::args::FilterArgs {
+                    include: vec!["a.txt".into()],
+                    ..Default::default()
+                })
+                .unwrap(),
+                ..Default::default()
+            };
+            async move {
+                client.login().await?;
+                client.download(vec!["/My Pack".into()], opts).await
+            }
+        };
+
+        // 大小相同, 按大小比较时不会覆盖
+        download(ConflictPolicy::SizeMismatch).await?;
+        assert_eq!(std::fs::read(&path)?, b"hello WORLD");
+
+        download(ConflictPolicy::Rename).await?;
+        assert_eq!(
+            std::fs::read(dir.path().join("My Pack/a (1).txt"))?,
+            b"hello world"
+        );
+        let job = Journal::list(&dir.path().join("jobs"))?.pop().unwrap();
+        assert_eq!(
+            job.entries[0].local_path,
+            dir.path().join("My Pack/a (1).txt")
+        );
+
+        download(ConflictPolicy::Checksum).await?;
+        assert_eq!(std::fs::read(&path)?, b"hello world");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_dry_run_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        let mut client = server.client();
+        client.login().await?;
+
+        let dir = tempfile::tempdir()?;
+        let output = dir.path().join("out");
+        let opts = DownloadOptions {
+            output: output.to_str().unwrap().to_string(),
+            job_dir: dir.path().join("jobs"),
+            dry_run: true,
+            ..Default::default()
+        };
+        let (entries, _) = client
+            .build_entries(vec!["/My Pack".into(), "readme.md".into()], &opts)
+            .await?;
+        create_dir_if_not_exists(&output.join("My Pack"))?;
+        std::fs::write(output.join("My Pack/a.txt"), b"hello world")?;
+        std::fs::write(output.join("readme.md"), b"# re")?;
+        std::fs::write(flag_path(&output.join("readme.md")), b"")?;
+
+        let planned = client
+            .plan_entries(entries, ConflictPolicy::Skip, Quality::Original)
+            .await?;
+        let planned: Vec<_> = planned
+            .into_iter()
+            .map(|(entry, plan)| (entry.remote_path, entry.size, plan))
+            .collect();
+        assert_eq!(
+            planned,
+            vec![
+                (
+                    "/My Pack/a.txt".into(),
+                    11,
+                    Plan::Skip("file exists".into())
+                ),
+                (
+                    "/My Pack/sub/b.bin".into(),
+                    4096,
+                    Plan::Download(output.join("My Pack/sub/b.bin"))
+                ),
+                (
+                    "readme.md".into(),
+                    8,
+                    Plan::Resume(output.join("readme.md"))
+                ),
+            ]
+        );
+
+        let mut client = server.client();
+        client.login().await?;
+        let downloads = server.state().download_ranges.len();
+        client.download(vec!["/My Pack".into()], opts).await?;
+        // 不会下载文件, 也不会创建目录和任务日志
+        assert_eq!(server.state().download_ranges.len(), downloads);
+        assert!(!output.join("My Pack/sub").exists());
+        assert!(!dir.path().join("jobs").exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_atomic_download_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        server.state().truncate_downloads = 1;
+        let mut client = server.client();
+        client.login().await?;
+
+        let dir = tempfile::tempdir()?;
+        let opts = DownloadOptions {
+            output: dir.path().to_str().unwrap().to_string(),
+            parallel: 1,
+            job_dir: dir.path().join("jobs"),
+            atomic: true,
+            ..Default::default()
+        };
+        let res = client
+            .download(vec!["readme.md".into()], opts.clone())
+            .await;
+        assert!(res.is_err());
+        // 未完成的文件不会出现在最终路径
+        let path = dir.path().join("readme.md");
+        assert!(!path.exists());
+        assert!(!flag_path(&path).exists());
+        assert_eq!(std::fs::read(partial_path(&path))?, b"# re");
+
+        let job = Journal::list(&opts.job_dir)?.remove(0);
+        assert_eq!(job.entries[0].bytes_done, 4);
+        let mut client = server.client();
+        client.login().await?;
+        client.resume_download(&job.id, opts).await?;
+
+        assert_eq!(std::fs::read(&path)?, b"# readme");
+        assert!(!partial_path(&path).exists());
+        assert!(!sidecar_path(&path).exists());
+        assert_eq!(
+            server.state().download_ranges.last().unwrap().as_deref(),
+            Some("bytes=4-")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_atomic_download_removes_flag_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        let mut client = server.client();
+        client.login().await?;
+
+        // 之前非原子模式中断留下的 flag
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("readme.md");
+        std::fs::write(&path, b"# re")?;
+        std::fs::write(flag_path(&path), b"")?;
+        let opts = DownloadOptions {
+            output: dir.path().to_str().unwrap().to_string(),
+            job_dir: dir.path().join("jobs"),
+            atomic: true,
+            ..Default::default()
+        };
+        client.download(vec!["readme.md".into()], opts).await?;
+
+        assert_eq!(std::fs::read(&path)?, b"# readme");
+        assert!(!flag_path(&path).exists());
+        assert!(matches!(
+            plan_download(&FileType::default(), &path, ConflictPolicy::Skip)?,
+            Plan::Skip(_)
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_sanitize_mock() -> Result<()> {
+        use crate::pikpak::mock::MockFile;
+
+        let server = MockServer::start_with_files(vec![
+            MockFile::folder("folder-a", "", "a:b"),
+            MockFile::file("file-1", "folder-a", "x?.txt", b"1"),
+            MockFile::file("file-2", "folder-a", "x_.txt", b"2"),
+            MockFile::file("file-3", "folder-a", "X_.txt", b"3"),
+        ])
+        .await;
+        let mut client = server.client();
+        client.login().await?;
+
+        let dir = tempfile::tempdir()?;
+        let opts = DownloadOptions {
+            output: dir.path().to_str().unwrap().to_string(),
+            job_dir: dir.path().join("jobs"),
+            sanitizer: Sanitizer::new(crate::args::SanitizeMode::Windows, '_', true)?,
+            ..Default::default()
+        };
+        client.download(vec!["/a:b".into()], opts).await?;
+
+        let local = dir.path().join("a_b");
+        assert_eq!(std::fs::read(local.join("x_.txt"))?, b"2");
+        assert_eq!(std::fs::read(local.join("X_~file-3.txt"))?, b"3");
+        assert_eq!(std::fs::read(local.join("x_~file-1.txt"))?, b"1");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_with_thumbnails_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        for file in server.state().files.iter_mut() {
+            file.thumbnail = file.id == "file-a";
+        }
+        let mut client = server.client();
+        client.login().await?;
+
+        let dir = tempfile::tempdir()?;
+        let opts = DownloadOptions {
+            output: dir.path().to_str().unwrap().to_string(),
+            job_dir: dir.path().join("jobs"),
+            thumbnails: Some(ThumbnailOptions {
+                output: dir.path().into(),
+                dir: Some(dir.path().join("thumbs")),
+            }),
+            ..Default::default()
+        };
+        client
+            .download(vec!["/My Pack".into()], opts.clone())
+            .await?;
+        assert!(dir.path().join("My Pack/a.txt").exists());
+        let thumbnail = dir.path().join("thumbs/My Pack/a.txt.jpg");
+        assert_eq!(
+            std::fs::read_to_string(&thumbnail)?,
+            "thumbnail of file-a in SIZE_MEDIUM"
+        );
+        assert!(!dir.path().join("thumbs/My Pack/sub").exists());
+
+        // 本地已有的文件跳过下载, 缩略图仍然保存
+        std::fs::remove_file(&thumbnail)?;
+        let mut client = server.client();
+        client.login().await?;
+        client.download(vec!["/My Pack".into()], opts).await?;
+        assert!(thumbnail.exists());
+        Ok(())
+    }
 }
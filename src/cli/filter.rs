@@ -0,0 +1,164 @@
+// 递归下载时的过滤条件, 在遍历远程目录时生效, 被排除的目录不会被遍历
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use regex::Regex;
+
+use crate::args::FilterArgs;
+use crate::pikpak::file::FileStatus;
+use crate::utils::category::{classify, FileCategory};
+
+#[derive(Debug, Clone, Default)]
+pub struct DownloadFilter {
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+    include_regex: Vec<Regex>,
+    exclude_regex: Vec<Regex>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    newer_than: Option<DateTime<Utc>>,
+    older_than: Option<DateTime<Utc>>,
+    categories: Vec<FileCategory>,
+    max_depth: Option<usize>,
+}
+
+fn build_globs(patterns: &[String]) -> Result<Option<GlobSet>> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern).with_context(|| format!("invalid glob: {}", pattern))?);
+    }
+    Ok(Some(builder.build()?))
+}
+
+fn build_regexes(patterns: &[String]) -> Result<Vec<Regex>> {
+    patterns
+        .iter()
+        .map(|x| Regex::new(x).with_context(|| format!("invalid regex: {}", x)))
+        .collect()
+}
+
+impl DownloadFilter {
+    pub fn new(args: &FilterArgs) -> Result<Self> {
+        Ok(DownloadFilter {
+            include: build_globs(&args.include)?,
+            exclude: build_globs(&args.exclude)?,
+            include_regex: build_regexes(&args.include_regex)?,
+            exclude_regex: build_regexes(&args.exclude_regex)?,
+            min_size: args.min_size,
+            max_size: args.max_size,
+            newer_than: args.newer_than,
+            older_than: args.older_than,
+            categories: args.category.clone(),
+            max_depth: args.max_depth,
+        })
+    }
+
+    fn excluded(&self, rel_path: &str) -> bool {
+        self.exclude.as_ref().is_some_and(|x| x.is_match(rel_path))
+            || self.exclude_regex.iter().any(|x| x.is_match(rel_path))
+    }
+
+    fn included(&self, rel_path: &str) -> bool {
+        if self.include.is_none() && self.include_regex.is_empty() {
+            return true;
+        }
+        self.include.as_ref().is_some_and(|x| x.is_match(rel_path))
+            || self.include_regex.iter().any(|x| x.is_match(rel_path))
+    }
+
+    // depth 为目录相对下载根目录的层级, 根目录的子目录为 1
+    pub fn allow_folder(&self, rel_path: &str, depth: usize) -> bool {
+        if self.max_depth.is_some_and(|x| depth > x) {
+            return false;
+        }
+        !self.excluded(rel_path)
+    }
+
+    pub fn allow_file(&self, rel_path: &str, file: &FileStatus) -> bool {
+        if self.excluded(rel_path) || !self.included(rel_path) {
+            return false;
+        }
+
+        let size = file.size.parse::<u64>().unwrap_or_default();
+        if self.min_size.is_some_and(|x| size < x) || self.max_size.is_some_and(|x| size > x) {
+            return false;
+        }
+
+        if self.newer_than.is_some() || self.older_than.is_some() {
+            let Ok(modified) = DateTime::parse_from_rfc3339(&file.modified_time) else {
+                return false;
+            };
+            let modified = modified.with_timezone(&Utc);
+            if self.newer_than.is_some_and(|x| modified < x)
+                || self.older_than.is_some_and(|x| modified > x)
+            {
+                return false;
+            }
+        }
+
+        if !self.categories.is_empty() {
+            let category = classify(&file.file_category, &file.mime_type, &file.name);
+            if !self.categories.contains(&category) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::parse::parse_time;
+
+    fn file(name: &str, size: u64, modified_time: &str) -> FileStatus {
+        FileStatus {
+            name: name.into(),
+            size: size.to_string(),
+            modified_time: modified_time.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_filter() -> Result<()> {
+        let filter = DownloadFilter::new(&FilterArgs {
+            include: vec!["*.mp4".into()],
+            include_regex: vec![r"\.mkv$".into()],
+            exclude: vec!["**/sample*".into()],
+            min_size: Some(10),
+            newer_than: Some(parse_time("2024-01-01")?),
+            max_depth: Some(1),
+            ..Default::default()
+        })?;
+
+        let t = "2024-01-10T12:03:58.456+08:00";
+        assert!(filter.allow_file("a/b.mp4", &file("b.mp4", 10, t)));
+        assert!(filter.allow_file("b.mkv", &file("b.mkv", 10, t)));
+        assert!(!filter.allow_file("b.txt", &file("b.txt", 10, t)));
+        assert!(!filter.allow_file("a/sample.mp4", &file("sample.mp4", 10, t)));
+        assert!(!filter.allow_file("b.mp4", &file("b.mp4", 9, t)));
+        assert!(!filter.allow_file("b.mp4", &file("b.mp4", 10, "2023-12-31T00:00:00Z")));
+
+        assert!(filter.allow_folder("a", 1));
+        assert!(!filter.allow_folder("a/b", 2));
+        assert!(!filter.allow_folder("a/sample", 1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_category() -> Result<()> {
+        let filter = DownloadFilter::new(&FilterArgs {
+            category: vec![FileCategory::Video, FileCategory::Image],
+            ..Default::default()
+        })?;
+        assert!(filter.allow_file("a.mp4", &file("a.mp4", 1, "")));
+        assert!(filter.allow_file("a.PNG", &file("a.PNG", 1, "")));
+        assert!(!filter.allow_file("a.txt", &file("a.txt", 1, "")));
+        Ok(())
+    }
+}
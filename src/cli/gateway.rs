@@ -0,0 +1,478 @@
+// 本地 http 网关, 将网盘目录以 http 的形式提供给局域网内的播放器等工具
+// 文件请求转发到下载链接, 支持 Range 和 HEAD, 链接过期时自动刷新
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use axum::body::{Body, StreamBody};
+use axum::extract::State;
+use axum::http::{header, HeaderMap, HeaderValue, Method, StatusCode, Uri};
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::Router;
+use chrono::{DateTime, Utc};
+use log::*;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+use crate::pikpak::download::get_download_client;
+use crate::pikpak::file::{FileStatus, FileType};
+use crate::pikpak::{Client, ErrResp, USER_AGENT};
+
+// 目录列表的缓存时间
+const DIR_CACHE_TTL: Duration = Duration::from_secs(30);
+// 链接在过期前多久刷新
+const LINK_REFRESH_MARGIN: i64 = 60;
+// 按 id 访问时向上查找父目录的最大层数
+const MAX_DEPTH: usize = 64;
+// 已确认在 root 下的目录 id 最多缓存的数量, 超过时清空
+const MAX_ALLOWED: usize = 10000;
+// 链接中不需要编码的字符
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+// 按文件 id 访问的路径前缀, 文件移动或改名后链接仍然有效
+pub const ID_PREFIX: &str = "/.id/";
+
+type DirCache = HashMap<String, (Instant, Arc<Vec<FileStatus>>)>;
+
+pub struct Gateway {
+    client: tokio::sync::Mutex<Client>,
+    root: String,
+    dirs: Mutex<DirCache>,
+    links: Mutex<HashMap<String, Arc<FileType>>>,
+    // 已确认在 root 下的目录 id
+    allowed: Mutex<HashSet<String>>,
+}
+
+pub fn is_folder(file: &FileStatus) -> bool {
+    file.kind == "drive#folder"
+}
+
+pub fn encode_path(path: &str) -> String {
+    path.split('/')
+        .map(|x| utf8_percent_encode(x, PATH_SEGMENT).to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+pub fn decode_path(uri: &Uri) -> String {
+    percent_decode_str(uri.path())
+        .decode_utf8_lossy()
+        .to_string()
+}
+
+pub fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// http 头中的时间格式
+pub fn http_date(time: &str) -> Option<String> {
+    DateTime::parse_from_rfc3339(time).ok().map(|x| {
+        x.with_timezone(&Utc)
+            .format("%a, %d %b %Y %H:%M:%S GMT")
+            .to_string()
+    })
+}
+
+fn link_valid(file: &FileType) -> bool {
+    DateTime::parse_from_rfc3339(&file.links.application_octet_stream.expire).is_ok_and(|x| {
+        x.with_timezone(&Utc) > Utc::now() + chrono::Duration::seconds(LINK_REFRESH_MARGIN)
+    })
+}
+
+impl Gateway {
+    pub fn new(client: Client, root: &str) -> Self {
+        Gateway {
+            client: tokio::sync::Mutex::new(client),
+            root: root.trim_matches('/').to_string(),
+            dirs: Mutex::new(HashMap::new()),
+            links: Mutex::new(HashMap::new()),
+            allowed: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub async fn client(&self) -> tokio::sync::MutexGuard<'_, Client> {
+        self.client.lock().await
+    }
+
+    // 目录内容变化后丢弃缓存的列表
+    pub fn invalidate(&self, folder_id: &str) {
+        self.dirs.lock().unwrap().remove(folder_id);
+    }
+
+    pub async fn list(&self, folder_id: &str) -> Result<Arc<Vec<FileStatus>>> {
+        if let Some((time, list)) = self.dirs.lock().unwrap().get(folder_id) {
+            if time.elapsed() < DIR_CACHE_TTL {
+                return Ok(list.clone());
+            }
+        }
+        let list = Arc::new(
+            self.client
+                .lock()
+                .await
+                .get_file_status_list_by_folder_id(folder_id)
+                .await?,
+        );
+        // 插入时清理过期的目录, 避免长时间运行时缓存一直增长
+        let mut dirs = self.dirs.lock().unwrap();
+        dirs.retain(|_, (time, _)| time.elapsed() < DIR_CACHE_TTL);
+        dirs.insert(folder_id.to_string(), (Instant::now(), list.clone()));
+        Ok(list)
+    }
+
+    // 根据路径查找文件, 相对于 root, 不存在时返回 None
+    pub async fn resolve(&self, path: &str) -> Result<Option<FileStatus>> {
+        let mut current = FileStatus {
+            kind: "drive#folder".into(),
+            ..Default::default()
+        };
+        let names = self.root.split('/').chain(path.split('/'));
+        for name in names.filter(|x| !x.is_empty()) {
+            if !is_folder(&current) {
+                return Ok(None);
+            }
+            let list = self.list(&current.id).await?;
+            let Some(file) = list.iter().find(|x| x.name == name) else {
+                return Ok(None);
+            };
+            current = file.clone();
+        }
+        Ok(Some(current))
+    }
+
+    pub async fn link(&self, file_id: &str) -> Result<Arc<FileType>> {
+        if let Some(file) = self.links.lock().unwrap().get(file_id) {
+            if link_valid(file) {
+                return Ok(file.clone());
+            }
+        }
+        debug!("refresh download link: {}", file_id);
+        let file = Arc::new(
+            self.client
+                .lock()
+                .await
+                .get_file_by_id(file_id.to_string())
+                .await?,
+        );
+        let mut links = self.links.lock().unwrap();
+        links.retain(|_, x| link_valid(x));
+        links.insert(file_id.to_string(), file.clone());
+        Ok(file)
+    }
+
+    // 按 id 访问时沿着父目录向上查找, 只允许访问 root 下的文件
+    pub async fn in_root(&self, file: &FileType) -> Result<bool> {
+        if self.root.is_empty() {
+            return Ok(true);
+        }
+        let Some(root) = self.resolve("").await? else {
+            return Ok(false);
+        };
+        let mut parent = file.parent_id.clone();
+        let mut visited = vec![];
+        let res = loop {
+            if parent == root.id || self.allowed.lock().unwrap().contains(&parent) {
+                break true;
+            }
+            if parent.is_empty() || visited.len() >= MAX_DEPTH {
+                break false;
+            }
+            visited.push(parent.clone());
+            parent = self
+                .client
+                .lock()
+                .await
+                .get_file_by_id(parent)
+                .await?
+                .parent_id;
+        };
+        if res {
+            let mut allowed = self.allowed.lock().unwrap();
+            if allowed.len() + visited.len() > MAX_ALLOWED {
+                allowed.clear();
+            }
+            allowed.extend(visited);
+        }
+        Ok(res)
+    }
+
+    fn file_headers(file: &FileStatus) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        let mime = if file.mime_type.is_empty() {
+            "application/octet-stream"
+        } else {
+            file.mime_type.as_str()
+        };
+        if let Ok(mime) = HeaderValue::from_str(mime) {
+            headers.insert(header::CONTENT_TYPE, mime);
+        }
+        if let Some(time) = http_date(&file.modified_time).and_then(|x| x.parse().ok()) {
+            headers.insert(header::LAST_MODIFIED, time);
+        }
+        headers
+    }
+
+    // 转发文件请求, HEAD 请求直接返回文件信息
+    pub async fn proxy(
+        &self,
+        file: &FileStatus,
+        method: &Method,
+        headers: &HeaderMap,
+    ) -> Result<Response> {
+        let mut resp_headers = Self::file_headers(file);
+        if method == Method::HEAD {
+            resp_headers.insert(header::CONTENT_LENGTH, HeaderValue::from_str(&file.size)?);
+            return Ok((StatusCode::OK, resp_headers).into_response());
+        }
+
+        let resp = self.fetch(&file.id, headers.get(header::RANGE)).await?;
+        for key in [header::CONTENT_LENGTH, header::CONTENT_RANGE] {
+            if let Some(value) = resp.headers().get(&key) {
+                resp_headers.insert(key, value.clone());
+            }
+        }
+        let status = resp.status();
+        let body = StreamBody::new(resp.bytes_stream());
+        Ok((status, resp_headers, body).into_response())
+    }
+
+    // 请求文件内容, 链接失效时刷新后重试一次
+    pub async fn fetch(
+        &self,
+        file_id: &str,
+        range: Option<&HeaderValue>,
+    ) -> Result<reqwest::Response> {
+        let mut refreshed = false;
+        loop {
+            let link = self.link(file_id).await?;
+            let mut req = get_download_client()
+                .get(&link.links.application_octet_stream.url)
+                .header(header::USER_AGENT, USER_AGENT);
+            if let Some(range) = range {
+                req = req.header(header::RANGE, range);
+            }
+            debug!("proxy req: {:?}", req);
+            let resp = req
+                .send()
+                .await
+                .context("[gateway] request upstream failed")?;
+            let status = resp.status();
+            if (status == StatusCode::FORBIDDEN || status == StatusCode::GONE) && !refreshed {
+                warn!("download link rejected: {}, refresh", status);
+                self.links.lock().unwrap().remove(file_id);
+                refreshed = true;
+                continue;
+            }
+            return Ok(resp);
+        }
+    }
+
+    fn index(path: &str, list: &[FileStatus]) -> Response {
+        let title = html_escape(&format!("Index of /{}", path.trim_start_matches('/')));
+        let mut body = format!(
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{0}</title></head>\n<body><h1>{0}</h1>\n<ul>\n<li><a href=\"../\">../</a></li>\n",
+            title
+        );
+        for file in list {
+            let slash = if is_folder(file) { "/" } else { "" };
+            body += &format!(
+                "<li><a href=\"{}{}\">{}{}</a></li>\n",
+                encode_path(&file.name),
+                slash,
+                html_escape(&file.name),
+                slash
+            );
+        }
+        body += "</ul></body></html>\n";
+        Html(body).into_response()
+    }
+
+    async fn handle(&self, method: Method, uri: Uri, headers: HeaderMap) -> Result<Response> {
+        if method != Method::GET && method != Method::HEAD {
+            return Ok(StatusCode::METHOD_NOT_ALLOWED.into_response());
+        }
+        let path = decode_path(&uri);
+        // /.id/<file_id>/<name>, 文件名只是为了方便播放器识别格式
+        if let Some(rest) = path.strip_prefix(ID_PREFIX) {
+            let id = rest.split('/').next().unwrap_or_default();
+            if id.is_empty() {
+                return Ok(StatusCode::NOT_FOUND.into_response());
+            }
+            // 网盘拒绝的 id 按不存在处理
+            let link = match self.link(id).await {
+                Result::Ok(link) => link,
+                Err(err) if err.downcast_ref::<ErrResp>().is_some() => {
+                    debug!("get file {} failed, err: {:#}", id, err);
+                    return Ok(StatusCode::NOT_FOUND.into_response());
+                }
+                Err(err) => return Err(err),
+            };
+            let file = FileStatus::from(link.as_ref());
+            if is_folder(&file) || !self.in_root(&link).await? {
+                return Ok(StatusCode::NOT_FOUND.into_response());
+            }
+            return self.proxy(&file, &method, &headers).await;
+        }
+        let Some(file) = self.resolve(&path).await? else {
+            return Ok(StatusCode::NOT_FOUND.into_response());
+        };
+        if !is_folder(&file) {
+            return self.proxy(&file, &method, &headers).await;
+        }
+        // 目录以 / 结尾, 页面中的相对链接才能正确解析
+        if !path.ends_with('/') {
+            return Ok(Redirect::permanent(&format!("{}/", uri.path())).into_response());
+        }
+        let list = self.list(&file.id).await?;
+        Ok(Self::index(&path, &list))
+    }
+}
+
+async fn http_handler(
+    State(gateway): State<Arc<Gateway>>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Response {
+    info!("{} {}", method, uri);
+    match gateway.handle(method, uri, headers).await {
+        Result::Ok(resp) => resp,
+        Err(err) => {
+            error!("handle request failed, err: {:#}", err);
+            (StatusCode::BAD_GATEWAY, format!("{:#}", err)).into_response()
+        }
+    }
+}
+
+pub fn http_router(gateway: Arc<Gateway>) -> Router<(), Body> {
+    Router::new().fallback(http_handler).with_state(gateway)
+}
+
+impl Client {
+    pub async fn serve_http(self, bind: SocketAddr, root: String) -> Result<()> {
+        let gateway = Arc::new(Gateway::new(self, &root));
+        info!("serving http on http://{}", bind);
+        axum::Server::try_bind(&bind)
+            .with_context(|| format!("[serve_http] bind {} failed", bind))?
+            .serve(http_router(gateway).into_make_service())
+            .await
+            .context("[serve_http] server error")
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::pikpak::mock::MockServer;
+
+    pub async fn start(router: Router<(), Body>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(router.into_make_service());
+        tokio::spawn(server);
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn test_http_gateway_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        let mut client = server.client();
+        client.login().await?;
+        let url = start(http_router(Arc::new(Gateway::new(client, "/")))).await;
+        let http = reqwest::Client::new();
+
+        let index = http.get(format!("{}/My%20Pack/", url)).send().await?;
+        assert_eq!(index.status(), 200);
+        let index = index.text().await?;
+        assert!(index.contains("<a href=\"a.txt\">a.txt</a>"));
+        assert!(index.contains("<a href=\"sub/\">sub/</a>"));
+
+        let resp = http
+            .get(format!("{}/My%20Pack/a.txt", url))
+            .header("Range", "bytes=6-")
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 206);
+        assert_eq!(resp.headers()["content-range"], "bytes 6-10/11");
+        assert_eq!(resp.text().await?, "world");
+
+        let resp = http.head(format!("{}/My%20Pack/a.txt", url)).send().await?;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["content-length"], "11");
+        assert_eq!(resp.headers()["accept-ranges"], "bytes");
+
+        // 目录列表和链接都被缓存
+        let (list_requests, file_requests) = {
+            let state = server.state();
+            (state.list_requests, state.file_requests)
+        };
+        let resp = http.get(format!("{}/My%20Pack/a.txt", url)).send().await?;
+        assert_eq!(resp.text().await?, "hello world");
+        assert_eq!(server.state().list_requests, list_requests);
+        assert_eq!(server.state().file_requests, file_requests);
+
+        let resp = http
+            .get(format!("{}/.id/file-b/b.bin", url))
+            .header("Range", "bytes=0-1")
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 206);
+        assert_eq!(resp.headers()["content-length"], "2");
+
+        let resp = http.get(format!("{}/missing", url)).send().await?;
+        assert_eq!(resp.status(), 404);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_http_gateway_refresh_link_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        server.state().link_expire = "2000-01-01T00:00:00.000+08:00".into();
+        let mut client = server.client();
+        client.login().await?;
+        let gateway = Arc::new(Gateway::new(client, "/My Pack"));
+        let url = start(http_router(gateway.clone())).await;
+
+        for _ in 0..2 {
+            let resp = reqwest::get(format!("{}/a.txt", url)).await?;
+            assert_eq!(resp.text().await?, "hello world");
+        }
+        // 链接已过期, 每次请求都重新获取
+        assert_eq!(server.state().file_requests, 2);
+        // 过期的链接在插入新链接时清理
+        let resp = reqwest::get(format!("{}/sub/b.bin", url)).await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(gateway.links.lock().unwrap().len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_http_gateway_id_outside_root_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        let mut client = server.client();
+        client.login().await?;
+        let url = start(http_router(Arc::new(Gateway::new(client, "/My Pack")))).await;
+
+        let resp = reqwest::get(format!("{}/.id/file-b/b.bin", url)).await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = reqwest::get(format!("{}/.id/file-readme/readme.md", url)).await?;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let resp = reqwest::get(format!("{}/.id/file-a/a.txt", url)).await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = reqwest::get(format!("{}/.id/missing/a.txt", url)).await?;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        Ok(())
+    }
+}
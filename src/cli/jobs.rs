@@ -0,0 +1,31 @@
+use std::path::Path;
+
+use anyhow::Result;
+use humansize::{format_size, DECIMAL};
+
+use super::journal::{EntryState, Journal};
+
+pub fn list(job_dir: &Path) -> Result<()> {
+    let jobs = Journal::list(job_dir)?;
+    if jobs.is_empty() {
+        println!("no download jobs");
+        return Ok(());
+    }
+    println!(
+        "{:<22} {:<8} {:<8} {:<8} {:<10} OUTPUT",
+        "ID", "DONE", "FAILED", "PENDING", "SIZE"
+    );
+    for job in jobs {
+        let size: u64 = job.entries.iter().map(|x| x.size).sum();
+        println!(
+            "{:<22} {:<8} {:<8} {:<8} {:<10} {}",
+            job.id,
+            job.count(EntryState::Done),
+            job.count(EntryState::Failed),
+            job.count(EntryState::Pending) + job.count(EntryState::Downloading),
+            format_size(size, DECIMAL),
+            job.output
+        );
+    }
+    Ok(())
+}
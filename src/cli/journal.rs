@@ -0,0 +1,225 @@
+// 下载任务日志, 记录每个文件的下载状态, 中断后可以直接从日志恢复而不需要重新遍历远程目录
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::file::create_dir_if_not_exists;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryState {
+    Pending,
+    Downloading,
+    Done,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobEntry {
+    pub remote_id: String,
+    pub remote_path: String,
+    pub local_path: PathBuf,
+    pub size: u64,
+    pub state: EntryState,
+    pub bytes_done: u64,
+    pub attempts: u32,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub error: String,
+    // 推送到 aria2 时的任务 id
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub gid: String,
+}
+
+impl JobEntry {
+    pub fn new(remote_id: String, remote_path: String, local_path: PathBuf, size: u64) -> Self {
+        JobEntry {
+            remote_id,
+            remote_path,
+            local_path,
+            size,
+            state: EntryState::Pending,
+            bytes_done: 0,
+            attempts: 0,
+            error: String::new(),
+            gid: String::new(),
+        }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.state == EntryState::Done
+    }
+}
+
+// 大任务每个文件都重写整个日志太慢, 状态变化后至多间隔这么久写一次, 结束时再写一次
+const SAVE_INTERVAL: Duration = Duration::from_secs(2);
+
+// 未写入的修改, 不参与比较
+#[derive(Debug, Clone, Default)]
+struct Pending {
+    dirty: bool,
+    last_saved: Option<Instant>,
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Journal {
+    pub id: String,
+    pub created_time: String,
+    pub output: String,
+    pub entries: Vec<JobEntry>,
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(skip)]
+    pending: Pending,
+}
+
+impl Journal {
+    pub fn create(dir: &Path, output: String, entries: Vec<JobEntry>) -> Result<Self> {
+        let now = Local::now();
+        let journal = Journal {
+            id: now.format("%Y%m%d-%H%M%S%3f").to_string(),
+            created_time: now.to_rfc3339(),
+            output,
+            entries,
+            path: PathBuf::new(),
+            pending: Pending::default(),
+        };
+        let path = dir.join(format!("{}.json", journal.id));
+        let mut journal = Journal { path, ..journal };
+        journal.save()?;
+        Ok(journal)
+    }
+
+    pub fn load(dir: &Path, id: &str) -> Result<Self> {
+        let path = dir.join(format!("{}.json", id));
+        let file = std::fs::File::open(&path)
+            .with_context(|| format!("[journal] job not found: {}", id))?;
+        let journal: Journal = serde_json::from_reader(file)
+            .with_context(|| format!("[journal] parse {} failed", path.display()))?;
+        Ok(Journal { path, ..journal })
+    }
+
+    pub fn list(dir: &Path) -> Result<Vec<Journal>> {
+        if !dir.exists() {
+            return Ok(vec![]);
+        }
+        let mut jobs = vec![];
+        for entry in std::fs::read_dir(dir).context("[journal] read job dir failed")? {
+            let path = entry?.path();
+            if path.extension().and_then(|x| x.to_str()) != Some("json") {
+                continue;
+            }
+            let Some(id) = path.file_stem().and_then(|x| x.to_str()) else {
+                continue;
+            };
+            // 一个日志损坏时不影响列出其他任务
+            match Self::load(dir, id) {
+                Ok(journal) => jobs.push(journal),
+                Err(err) => warn!("skip job {}: {:#}", id, err),
+            }
+        }
+        jobs.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(jobs)
+    }
+
+    // 先写临时文件再重命名, 避免进程被杀时日志损坏
+    pub fn save(&mut self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            create_dir_if_not_exists(dir)?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        let file = std::fs::File::create(&tmp).context("[journal] create file failed")?;
+        serde_json::to_writer_pretty(file, self).context("[journal] write file failed")?;
+        std::fs::rename(&tmp, &self.path).context("[journal] rename file failed")?;
+        self.pending = Pending {
+            dirty: false,
+            last_saved: Some(Instant::now()),
+        };
+        Ok(())
+    }
+
+    // 记录有修改, 距上次写入超过 SAVE_INTERVAL 时才写入
+    pub fn save_throttled(&mut self) -> Result<()> {
+        self.pending.dirty = true;
+        if self
+            .pending
+            .last_saved
+            .is_some_and(|x| x.elapsed() < SAVE_INTERVAL)
+        {
+            return Ok(());
+        }
+        self.save()
+    }
+
+    // 写入还没有保存的修改
+    pub fn flush(&mut self) -> Result<()> {
+        if self.pending.dirty {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    pub fn count(&self, state: EntryState) -> usize {
+        self.entries.iter().filter(|x| x.state == state).count()
+    }
+
+    pub fn unfinished(&self) -> Vec<usize> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| !x.finished())
+            .map(|(i, _)| i)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_journal() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut journal = Journal::create(
+            dir.path(),
+            "./".into(),
+            vec![
+                JobEntry::new("a".into(), "/a".into(), "a".into(), 1),
+                JobEntry::new("b".into(), "/b".into(), "b".into(), 2),
+            ],
+        )?;
+        journal.entries[0].state = EntryState::Done;
+        journal.save()?;
+
+        let loaded = Journal::load(dir.path(), &journal.id)?;
+        assert_eq!(loaded, journal);
+        assert_eq!(loaded.unfinished(), vec![1]);
+        assert_eq!(Journal::list(dir.path())?, vec![journal.clone()]);
+
+        // 刚写入过时只标记修改, flush 时再写入
+        journal.entries[1].state = EntryState::Done;
+        journal.save_throttled()?;
+        assert_eq!(
+            Journal::load(dir.path(), &journal.id)?.unfinished(),
+            vec![1]
+        );
+        journal.flush()?;
+        assert!(Journal::load(dir.path(), &journal.id)?
+            .unfinished()
+            .is_empty());
+
+        // 损坏的日志被跳过
+        std::fs::write(dir.path().join("broken.json"), b"{")?;
+        assert_eq!(Journal::list(dir.path())?.len(), 1);
+        Ok(())
+    }
+}
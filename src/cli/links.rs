@@ -0,0 +1,164 @@
+// 导出下载链接, 交给 aria2, curl 或 wget 下载
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::*;
+use serde::Serialize;
+
+use crate::args::LinkFormat;
+use crate::cli::download::DownloadOptions;
+use crate::cli::quality::variant_path;
+use crate::pikpak::{Client, USER_AGENT};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LinkItem {
+    pub path: String,
+    pub local_path: PathBuf,
+    pub url: String,
+    pub expire: String,
+    pub size: u64,
+    pub md5: String,
+}
+
+impl Client {
+    pub async fn links(
+        mut self,
+        paths: Vec<String>,
+        format: LinkFormat,
+        output: Option<PathBuf>,
+        opts: DownloadOptions,
+    ) -> Result<()> {
+        let items = self.get_links(paths, &opts).await?;
+        if let Some(expire) = items.iter().map(|x| x.expire.as_str()).min() {
+            info!("exported {} links, expire at: {}", items.len(), expire);
+        }
+        let content = render(format, &items);
+        match output {
+            Some(path) => std::fs::write(&path, content)
+                .with_context(|| format!("[links] write {} failed", path.display())),
+            None => Ok(std::io::stdout().write_all(content.as_bytes())?),
+        }
+    }
+
+    async fn get_links(
+        &mut self,
+        paths: Vec<String>,
+        opts: &DownloadOptions,
+    ) -> Result<Vec<LinkItem>> {
+        let (entries, failures) = self.build_entries(paths, opts).await?;
+        for item in failures {
+            error!("list {} failed: {}", item.remote_path, item.reason);
+        }
+        let mut items = vec![];
+        for entry in entries {
+            let file = self
+                .get_file_with_quality(entry.remote_id, opts.quality)
+                .await?;
+            let local_path = variant_path(&entry.local_path, &file);
+            let link = file.links.application_octet_stream;
+            items.push(LinkItem {
+                path: entry.remote_path,
+                local_path,
+                url: link.url,
+                expire: link.expire,
+                size: file.size.parse().unwrap_or_default(),
+                md5: file.md5_checksum,
+            });
+        }
+        Ok(items)
+    }
+}
+
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+fn parent(path: &Path) -> &Path {
+    path.parent().unwrap_or(Path::new("."))
+}
+
+pub fn render(format: LinkFormat, items: &[LinkItem]) -> String {
+    let mut res = String::new();
+    match format {
+        LinkFormat::Aria2 => {
+            // https://aria2.github.io/manual/en/html/aria2c.html#input-file
+            for item in items {
+                res += &format!("{}\n", item.url);
+                res += &format!("  dir={}\n", parent(&item.local_path).display());
+                if let Some(name) = item.local_path.file_name() {
+                    res += &format!("  out={}\n", name.to_string_lossy());
+                }
+                res += &format!("  header=User-Agent: {}\n", USER_AGENT);
+                if !item.md5.is_empty() {
+                    res += &format!("  checksum=md5={}\n", item.md5);
+                }
+            }
+        }
+        LinkFormat::Curl | LinkFormat::Wget => {
+            res += "#!/bin/sh\nset -e\n";
+            for item in items {
+                let dir = shell_quote(&parent(&item.local_path).to_string_lossy());
+                let path = shell_quote(&item.local_path.to_string_lossy());
+                let url = shell_quote(&item.url);
+                let agent = shell_quote(USER_AGENT);
+                res += &format!("\n# {}\nmkdir -p {}\n", item.path, dir);
+                if format == LinkFormat::Curl {
+                    res += &format!("curl -fL -C - -A {} -o {} {}\n", agent, path, url);
+                } else {
+                    res += &format!("wget -c -U {} -O {} {}\n", agent, path, url);
+                }
+            }
+        }
+        LinkFormat::Json => {
+            res = serde_json::to_string_pretty(items).unwrap_or_default();
+            res += "\n";
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pikpak::mock::MockServer;
+
+    #[tokio::test]
+    async fn test_links_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        let mut client = server.client();
+        client.login().await?;
+
+        let opts = DownloadOptions {
+            output: "/data".into(),
+            ..Default::default()
+        };
+        let items = client.get_links(vec!["/My Pack".into()], &opts).await?;
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].path, "/My Pack/a.txt");
+        assert_eq!(items[0].url, format!("{}/download/file-a", server.url()));
+        assert_eq!(items[0].size, 11);
+
+        let aria2 = render(LinkFormat::Aria2, &items[..1]);
+        assert_eq!(
+            aria2,
+            format!(
+                "{}/download/file-a\n  dir=/data/My Pack\n  out=a.txt\n  header=User-Agent: {}\n  checksum=md5=5eb63bbbe01eeed093cb22bb8f5acdc3\n",
+                server.url(),
+                USER_AGENT
+            )
+        );
+        let curl = render(LinkFormat::Curl, &items[..1]);
+        assert!(curl.contains("mkdir -p '/data/My Pack'\n"));
+        assert!(curl.contains("-o '/data/My Pack/a.txt'"));
+        let json: Vec<serde_json::Value> = serde_json::from_str(&render(LinkFormat::Json, &items))?;
+        assert_eq!(json[1]["path"], "/My Pack/sub/b.bin");
+        assert_eq!(json[1]["size"], 4096);
+        Ok(())
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+}
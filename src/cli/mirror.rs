@@ -0,0 +1,427 @@
+// 把本地目录变成远程目录的精确副本
+// 下载新增和变化的文件, 删除远程已不存在的本地文件, 或移到隔离目录
+// 要删除的文件超过阈值时不做任何改动, 防止远程目录被误删后清空本地备份
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use log::*;
+use tokio::sync::Semaphore;
+
+use crate::cli::conflict::checksum_matches;
+use crate::cli::download::download_file;
+use crate::cli::sync::{incomplete, is_folder, scan_local, LocalItem};
+use crate::pikpak::file::{FileStatus, FileType};
+use crate::pikpak::folder::FileIDType;
+use crate::pikpak::Client;
+use crate::utils::file::set_modified;
+use crate::utils::path::slash;
+
+#[derive(Debug, Clone, Default)]
+pub struct MirrorOptions {
+    // 不为空时把多余的本地文件移到这里, 而不是删除
+    pub quarantine: Option<PathBuf>,
+    pub max_delete: usize,
+    pub parallel: usize,
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct MirrorSummary {
+    pub downloaded: usize,
+    pub unchanged: usize,
+    pub removed: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+fn remote_modified(status: &FileStatus) -> Option<DateTime<chrono::Utc>> {
+    DateTime::parse_from_rfc3339(&status.modified_time)
+        .ok()
+        .map(|x| x.into())
+}
+
+// 大小和修改时间 (精确到秒) 一致时认为没有变化
+// 只有修改时间不同时比较校验值, 一致则只更新修改时间
+fn up_to_date(path: &Path, item: &LocalItem, status: &FileStatus) -> Result<bool> {
+    if item.folder || status.size != item.size.to_string() {
+        return Ok(false);
+    }
+    let modified = remote_modified(status);
+    if modified.is_some_and(|x| x.timestamp() == (item.mtime / 1000) as i64) {
+        return Ok(true);
+    }
+    let file = FileType {
+        md5_checksum: status.md5_checksum.clone(),
+        hash: status.hash.clone(),
+        ..Default::default()
+    };
+    if checksum_matches(&file, path)? != Some(true) {
+        return Ok(false);
+    }
+    if let Some(modified) = modified {
+        set_modified(path, modified.into())?;
+    }
+    Ok(true)
+}
+
+// 跨文件系统时不能直接重命名
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("[mirror] create {} failed", parent.display()))?;
+    }
+    if std::fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    std::fs::copy(from, to).with_context(|| format!("[mirror] copy {} failed", from.display()))?;
+    std::fs::remove_file(from).with_context(|| format!("[mirror] remove {} failed", from.display()))
+}
+
+impl Client {
+    pub async fn mirror(
+        mut self,
+        remote: String,
+        local: PathBuf,
+        opts: MirrorOptions,
+    ) -> Result<()> {
+        let summary = self.mirror_folder(&remote, &local, &opts).await?;
+        if opts.dry_run {
+            return Ok(());
+        }
+        println!(
+            "downloaded: {}, unchanged: {}, removed: {}, skipped: {}, failed: {}",
+            summary.downloaded, summary.unchanged, summary.removed, summary.skipped, summary.failed
+        );
+        if summary.failed > 0 {
+            return Err(anyhow::anyhow!(
+                "[mirror] {} files failed, run mirror again to retry",
+                summary.failed
+            ));
+        }
+        Ok(())
+    }
+
+    async fn mirror_folder(
+        &mut self,
+        remote: &str,
+        local: &Path,
+        opts: &MirrorOptions,
+    ) -> Result<MirrorSummary> {
+        let remote = format!("/{}", slash(remote)?);
+        let root_id = match self.get_path_id(&remote).await? {
+            FileIDType::Folder(id) => id,
+            FileIDType::File(_) => {
+                return Err(anyhow::anyhow!("[mirror] {} is not a folder", remote));
+            }
+        };
+        std::fs::create_dir_all(local)
+            .with_context(|| format!("[mirror] create {} failed", local.display()))?;
+
+        // 远程目录列出失败时直接退出, 避免删除没列出的文件
+        let mut remote_items: BTreeMap<String, FileStatus> = BTreeMap::new();
+        self.scan_remote(&root_id, "", &mut remote_items).await?;
+        let mut local_items = BTreeMap::new();
+        scan_local(local, "", &mut local_items)?;
+        // 隔离目录在本地目录下时不属于副本的内容
+        if let Some(quarantine) = &opts.quarantine {
+            let root = std::path::absolute(local)?;
+            if let Ok(rel) = std::path::absolute(quarantine)?.strip_prefix(&root) {
+                let rel = rel.to_string_lossy().replace('\\', "/");
+                let prefix = format!("{}/", rel);
+                local_items.retain(|k, _| *k != rel && !k.starts_with(&prefix));
+            }
+        }
+
+        // 远程不存在或类型不同的本地文件, 符号链接不属于副本, 总是移除
+        let extra: Vec<&String> = local_items
+            .iter()
+            .filter(|(k, v)| v.symlink || (!v.folder && remote_items.get(*k).is_none_or(is_folder)))
+            .map(|(k, _)| k)
+            .collect();
+        // 远程不存在或类型不同的本地目录, 从深到浅删除
+        let extra_dirs: Vec<&String> = local_items
+            .iter()
+            .rev()
+            .filter(|(k, v)| v.folder && remote_items.get(*k).is_none_or(|x| !is_folder(x)))
+            .map(|(k, _)| k)
+            .collect();
+        let mut downloads = vec![];
+        let mut summary = MirrorSummary::default();
+        for (path, status) in remote_items.iter().filter(|(_, x)| !is_folder(x)) {
+            // 上传中的文件这次不下载, 本地已有的副本也保留
+            if incomplete(status) {
+                info!("skip {}: remote file is not complete", path);
+                summary.skipped += 1;
+                continue;
+            }
+            match local_items.get(path) {
+                Some(item) if !item.symlink && up_to_date(&local.join(path), item, status)? => {
+                    summary.unchanged += 1
+                }
+                _ => downloads.push((path, status)),
+            }
+        }
+
+        if opts.dry_run {
+            for path in &extra {
+                match &opts.quarantine {
+                    Some(_) => println!("quarantine {}", path),
+                    None => println!("delete {}", path),
+                }
+            }
+            for path in &extra_dirs {
+                println!("delete folder {}", path);
+            }
+            for (path, _) in &downloads {
+                println!("download {}", path);
+            }
+            if extra.len() > opts.max_delete {
+                warn!(
+                    "{} files would be removed, more than --max-delete {}",
+                    extra.len(),
+                    opts.max_delete
+                );
+            }
+            return Ok(summary);
+        }
+        if extra.len() > opts.max_delete {
+            return Err(anyhow::anyhow!(
+                "[mirror] {} local files would be removed, more than --max-delete {}, nothing changed",
+                extra.len(),
+                opts.max_delete
+            ));
+        }
+
+        let quarantine = opts
+            .quarantine
+            .as_ref()
+            .map(|x| x.join(Local::now().format("%Y%m%d-%H%M%S").to_string()));
+        for path in extra {
+            let local_path = local.join(path);
+            let res = match &quarantine {
+                Some(dir) => {
+                    info!("quarantine {}", path);
+                    move_file(&local_path, &dir.join(path))
+                }
+                None => {
+                    info!("delete {}", path);
+                    std::fs::remove_file(&local_path)
+                        .with_context(|| format!("[mirror] remove {} failed", local_path.display()))
+                }
+            };
+            match res {
+                Result::Ok(_) => summary.removed += 1,
+                Err(err) => {
+                    error!("remove {} failed, err: {:#}", path, err);
+                    summary.failed += 1;
+                }
+            }
+        }
+
+        // 远程的空目录也要创建, 多余的目录非空时说明有文件删除失败
+        for (path, status) in remote_items.iter().filter(|(_, x)| is_folder(x)) {
+            let dir = local.join(path);
+            if !dir.is_dir() {
+                std::fs::create_dir_all(&dir)
+                    .with_context(|| format!("[mirror] create {} failed", dir.display()))?;
+                debug!("create {}, remote id: {}", path, status.id);
+            }
+        }
+        for path in extra_dirs {
+            if let Err(err) = std::fs::remove_dir(local.join(path)) {
+                warn!("remove folder {} failed, err: {}", path, err);
+            }
+        }
+
+        // 依次获取文件信息, 下载并行进行
+        let semaphore = Arc::new(Semaphore::new(opts.parallel.max(1)));
+        let mut tasks = vec![];
+        for (path, status) in downloads {
+            let permit = semaphore.clone().acquire_owned().await?;
+            let file = match self.get_file_by_id(status.id.clone()).await {
+                Result::Ok(file) => file,
+                Err(err) => {
+                    error!("download {} failed, err: {:#}", path, err);
+                    summary.failed += 1;
+                    continue;
+                }
+            };
+            let local_path = local.join(path);
+            let (retry_times, retry_interval) = (self.retry_times, self.retry_interval);
+            let modified = remote_modified(status);
+            let path = path.clone();
+            tasks.push(tokio::spawn(async move {
+                let _permit = permit;
+                info!("download: {}", local_path.display());
+                let res = async {
+                    download_file(&file, local_path.clone(), retry_times, retry_interval, true)
+                        .await?;
+                    if let Some(modified) = modified {
+                        set_modified(&local_path, modified.into())?;
+                    }
+                    Ok::<_, anyhow::Error>(())
+                }
+                .await;
+                (path, res)
+            }));
+        }
+        for task in futures::future::join_all(tasks).await {
+            match task? {
+                (_, Result::Ok(_)) => summary.downloaded += 1,
+                (path, Err(err)) => {
+                    error!("download {} failed, err: {:#}", path, err);
+                    summary.failed += 1;
+                }
+            }
+        }
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pikpak::mock::{MockFile, MockServer};
+
+    #[tokio::test]
+    async fn test_mirror_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        server
+            .state()
+            .files
+            .push(MockFile::folder("folder-empty", "folder-my-pack", "empty"));
+        let mut client = server.client();
+        client.login().await?;
+        let dir = tempfile::tempdir()?;
+        let local = dir.path().join("backup");
+        let opts = MirrorOptions {
+            max_delete: 1,
+            parallel: 2,
+            ..Default::default()
+        };
+
+        let summary = client.mirror_folder("/My Pack", &local, &opts).await?;
+        assert_eq!(summary.downloaded, 2);
+        assert!(local.join("empty").is_dir());
+        assert_eq!(std::fs::read(local.join("a.txt"))?, b"hello world");
+
+        let summary = client.mirror_folder("/My Pack", &local, &opts).await?;
+        assert_eq!(
+            summary,
+            MirrorSummary {
+                unchanged: 2,
+                ..Default::default()
+            }
+        );
+
+        // 本地修改和多余的文件
+        std::fs::write(local.join("a.txt"), b"local edit")?;
+        std::fs::create_dir_all(local.join("old"))?;
+        std::fs::write(local.join("old/x.txt"), b"x")?;
+        std::fs::write(local.join("y.txt"), b"y")?;
+
+        // 超过阈值时不做任何改动
+        assert!(client
+            .mirror_folder("/My Pack", &local, &opts)
+            .await
+            .is_err());
+        assert!(local.join("y.txt").exists());
+        assert_eq!(std::fs::read(local.join("a.txt"))?, b"local edit");
+
+        let dry_run = MirrorOptions {
+            dry_run: true,
+            ..opts.clone()
+        };
+        client.mirror_folder("/My Pack", &local, &dry_run).await?;
+        assert!(local.join("y.txt").exists());
+
+        let quarantine = local.join(".quarantine");
+        let opts = MirrorOptions {
+            quarantine: Some(quarantine.clone()),
+            max_delete: 2,
+            parallel: 2,
+            dry_run: false,
+        };
+        let summary = client.mirror_folder("/My Pack", &local, &opts).await?;
+        assert_eq!(
+            summary,
+            MirrorSummary {
+                downloaded: 1,
+                unchanged: 1,
+                removed: 2,
+                ..Default::default()
+            }
+        );
+        assert_eq!(std::fs::read(local.join("a.txt"))?, b"hello world");
+        assert!(!local.join("y.txt").exists());
+        assert!(!local.join("old").exists());
+        let batch = std::fs::read_dir(&quarantine)?.next().unwrap()?.path();
+        assert_eq!(std::fs::read(batch.join("old/x.txt"))?, b"x");
+
+        // 隔离目录不算多余的文件, 上传中的远程文件不会删除本地副本
+        server
+            .state()
+            .files
+            .iter_mut()
+            .find(|x| x.id == "file-a")
+            .unwrap()
+            .phase = "PHASE_TYPE_PENDING".into();
+        let summary = client.mirror_folder("/My Pack", &local, &opts).await?;
+        assert_eq!(summary.removed, 0);
+        assert_eq!(summary.skipped, 1);
+        assert!(local.join("a.txt").exists());
+        assert!(batch.join("y.txt").exists());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_mirror_symlink_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        let mut client = server.client();
+        client.login().await?;
+        let dir = tempfile::tempdir()?;
+        let local = dir.path().join("backup");
+        let target = dir.path().join("target.txt");
+        std::fs::write(&target, b"target")?;
+        let opts = MirrorOptions {
+            max_delete: 1,
+            parallel: 1,
+            ..Default::default()
+        };
+        client.mirror_folder("/My Pack", &local, &opts).await?;
+
+        // 多余的链接和替换了远程文件的链接都算作要移除的文件
+        std::os::unix::fs::symlink(&target, local.join("link.txt"))?;
+        std::fs::remove_file(local.join("a.txt"))?;
+        std::os::unix::fs::symlink(&target, local.join("a.txt"))?;
+        assert!(client
+            .mirror_folder("/My Pack", &local, &opts)
+            .await
+            .is_err());
+        assert!(local.join("link.txt").is_symlink());
+
+        let opts = MirrorOptions {
+            max_delete: 2,
+            ..opts
+        };
+        let summary = client.mirror_folder("/My Pack", &local, &opts).await?;
+        assert_eq!(
+            summary,
+            MirrorSummary {
+                downloaded: 1,
+                unchanged: 1,
+                removed: 2,
+                ..Default::default()
+            }
+        );
+        assert!(!local.join("link.txt").exists() && !local.join("link.txt").is_symlink());
+        assert!(!local.join("a.txt").is_symlink());
+        assert_eq!(std::fs::read(local.join("a.txt"))?, b"hello world");
+        assert_eq!(std::fs::read(&target)?, b"target");
+        Ok(())
+    }
+}
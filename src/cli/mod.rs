@@ -1,12 +1,47 @@
+use std::path::Path;
+
 use anyhow::Result;
 
-use crate::{args::Commands, pikpak::Client};
+use crate::{
+    args::{Commands, JobsCommands, ServeCommands},
+    config::get_config,
+    pikpak::Client,
+    utils::category::FileCategory,
+};
 
+mod aria2;
+mod cat;
+mod conflict;
 mod download;
+mod filter;
+mod gateway;
+mod jobs;
+mod journal;
+mod links;
 mod list;
+mod mirror;
+#[cfg(all(feature = "fuse", target_os = "linux"))]
+mod mount;
+mod partial;
+mod playlist;
+mod quality;
+mod sanitize;
+mod strm;
+mod summary;
+mod sync;
+mod thumbnail;
+mod webdav;
+
+pub async fn handle(cmd: Commands, retry_times: i8, interactive: bool) -> Result<()> {
+    if let Commands::Jobs { command } = &cmd {
+        let job_dir = Path::new(get_config().job_dir());
+        return match command {
+            JobsCommands::List => jobs::list(job_dir),
+        };
+    }
 
-pub async fn handle(cmd: Commands, retry_times: i8) -> Result<()> {
     let mut client = Client::new(retry_times)?;
+    client.interactive = interactive;
     client.login().await?;
 
     match cmd {
@@ -14,7 +49,193 @@ pub async fn handle(cmd: Commands, retry_times: i8) -> Result<()> {
             paths,
             output,
             parallel,
-        } => client.download(paths, output, parallel).await,
+            resume,
+            report,
+            retry_from,
+            on_conflict,
+            dry_run,
+            xattrs,
+            atomic,
+            sanitize,
+            replace_char,
+            case_insensitive,
+            aria2,
+            aria2_secret,
+            aria2_poll_interval,
+            quality,
+            with_thumbnails,
+            thumbnail_dir,
+            thumbnail_size,
+            filter,
+        } => {
+            client.thumbnail_size = thumbnail_size.into();
+            let thumbnails = with_thumbnails.then(|| thumbnail::ThumbnailOptions {
+                output: output.clone().into(),
+                dir: thumbnail_dir,
+            });
+            let opts = download::DownloadOptions {
+                output,
+                parallel,
+                job_dir: get_config().job_dir().into(),
+                report,
+                on_conflict,
+                dry_run,
+                xattrs,
+                atomic,
+                sanitizer: sanitize::Sanitizer::new(sanitize, replace_char, case_insensitive)?,
+                aria2: aria2
+                    .map(|x| aria2::Aria2::new(x, aria2_secret).poll_interval(aria2_poll_interval)),
+                quality,
+                thumbnails,
+                filter: filter::DownloadFilter::new(&filter)?,
+            };
+            if let Some(job) = resume {
+                client.resume_download(&job, opts).await
+            } else if let Some(retry_from) = retry_from {
+                client.retry_download(&retry_from, opts).await
+            } else {
+                client.download(paths, opts).await
+            }
+        }
+        Commands::Links {
+            paths,
+            format,
+            output,
+            dir,
+            quality,
+            filter,
+        } => {
+            let opts = download::DownloadOptions {
+                output: dir,
+                quality,
+                filter: filter::DownloadFilter::new(&filter)?,
+                ..Default::default()
+            };
+            client.links(paths, format, output, opts).await
+        }
+        Commands::Thumbnails {
+            paths,
+            output,
+            thumbnail_dir,
+            thumbnail_size,
+            filter,
+        } => {
+            client.thumbnail_size = thumbnail_size.into();
+            let thumbnail_opts = thumbnail::ThumbnailOptions {
+                output: output.clone().into(),
+                dir: thumbnail_dir,
+            };
+            let opts = download::DownloadOptions {
+                output,
+                filter: filter::DownloadFilter::new(&filter)?,
+                ..Default::default()
+            };
+            client.thumbnails(paths, opts, thumbnail_opts).await
+        }
+        Commands::Cat {
+            path,
+            range,
+            head,
+            tail,
+            quality,
+        } => {
+            let range = match (range, head, tail) {
+                (Some(range), _, _) => cat::CatRange::Range(range),
+                (_, Some(head), _) => cat::CatRange::Head(head),
+                (_, _, Some(tail)) => cat::CatRange::Tail(tail),
+                _ => cat::CatRange::All,
+            };
+            client.cat(path, range, quality).await
+        }
+        Commands::Serve { command } => match command {
+            ServeCommands::Http { bind, root } => client.serve_http(bind, root).await,
+            ServeCommands::Webdav {
+                bind,
+                root,
+                username,
+                password,
+            } => {
+                let auth = username
+                    .zip(password)
+                    .map(|(username, password)| webdav::BasicAuth { username, password });
+                client.serve_webdav(bind, root, auth).await
+            }
+        },
+        Commands::Strm {
+            path,
+            output,
+            gateway,
+            incremental,
+            mut filter,
+        } => {
+            // 默认只处理视频
+            if filter.category.is_empty() {
+                filter.category = vec![FileCategory::Video];
+            }
+            let opts = download::DownloadOptions {
+                output,
+                filter: filter::DownloadFilter::new(&filter)?,
+                ..Default::default()
+            };
+            client.strm(path, gateway, incremental, opts).await
+        }
+        Commands::Sync {
+            local,
+            remote,
+            dry_run,
+        } => client.sync(local, remote, dry_run).await,
+        Commands::Mirror {
+            remote,
+            local,
+            quarantine,
+            max_delete,
+            parallel,
+            dry_run,
+        } => {
+            let opts = mirror::MirrorOptions {
+                quarantine,
+                max_delete,
+                parallel,
+                dry_run,
+            };
+            client.mirror(remote, local, opts).await
+        }
+        Commands::Playlist {
+            path,
+            gateway,
+            output,
+            mut filter,
+        } => {
+            if filter.category.is_empty() {
+                filter.category = vec![FileCategory::Video, FileCategory::Audio];
+            }
+            let opts = download::DownloadOptions {
+                filter: filter::DownloadFilter::new(&filter)?,
+                ..Default::default()
+            };
+            client.playlist(path, gateway, output, opts).await
+        }
+        #[cfg(all(feature = "fuse", target_os = "linux"))]
+        Commands::Mount {
+            mountpoint,
+            root,
+            cache_dir,
+            chunk_size,
+            cache_size,
+            read_ahead,
+            allow_other,
+        } => {
+            let opts = mount::MountOptions {
+                root,
+                cache_dir: cache_dir.unwrap_or(std::env::temp_dir().join("pikpakcli-chunks")),
+                chunk_size,
+                cache_size,
+                read_ahead,
+                allow_other,
+            };
+            client.mount(mountpoint, opts).await
+        }
         Commands::List { long, human, path } => client.list(long, human, path).await,
+        Commands::Jobs { .. } => unreachable!(),
     }
 }
@@ -0,0 +1,576 @@
+// 只读挂载网盘, 目录和文件属性来自带缓存的目录列表
+// 读文件时按块请求下载链接, 块缓存在本地磁盘, 并在后台预读后面的块
+// 缓存超过上限时删除最久没有使用的块
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsStr;
+use std::os::raw::c_int;
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use axum::http::{HeaderValue, StatusCode};
+use chrono::DateTime;
+use fuser::{
+    FileAttr, FileType as NodeKind, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory,
+    ReplyEntry, Request,
+};
+use log::*;
+
+use crate::cli::gateway::{is_folder, Gateway};
+use crate::pikpak::file::FileStatus;
+use crate::pikpak::Client;
+
+// 内核缓存属性和目录项的时间, 与目录列表的缓存时间一致
+const ATTR_TTL: Duration = Duration::from_secs(30);
+const ROOT_INO: u64 = 1;
+
+type ChunkKey = (String, u64);
+type ChunkLocks = HashMap<ChunkKey, Arc<tokio::sync::Mutex<()>>>;
+
+pub struct MountOptions {
+    pub root: String,
+    pub cache_dir: PathBuf,
+    pub chunk_size: u64,
+    pub cache_size: u64,
+    pub read_ahead: u64,
+    pub allow_other: bool,
+}
+
+struct Node {
+    file: FileStatus,
+    parent: u64,
+}
+
+#[derive(Default)]
+struct Inodes {
+    nodes: Vec<Node>,
+    ids: HashMap<String, u64>,
+}
+
+impl Inodes {
+    fn get(&self, ino: u64) -> Option<&Node> {
+        self.nodes.get(ino.checked_sub(1)? as usize)
+    }
+
+    // 同一个文件始终对应同一个 inode, 重新列目录时更新文件信息
+    fn insert(&mut self, file: &FileStatus, parent: u64) -> u64 {
+        let node = Node {
+            file: file.clone(),
+            parent,
+        };
+        if let Some(&ino) = self.ids.get(&file.id) {
+            self.nodes[ino as usize - 1] = node;
+            return ino;
+        }
+        self.nodes.push(node);
+        let ino = self.nodes.len() as u64;
+        self.ids.insert(file.id.clone(), ino);
+        ino
+    }
+}
+
+fn file_size(file: &FileStatus) -> u64 {
+    file.size.parse().unwrap_or_default()
+}
+
+// 按最近使用的顺序记录缓存的块
+#[derive(Default)]
+struct ChunkLru {
+    tick: u64,
+    total: u64,
+    used: BTreeMap<u64, ChunkKey>,
+    // 最近使用的 tick 和块大小
+    chunks: HashMap<ChunkKey, (u64, u64)>,
+}
+
+impl ChunkLru {
+    fn touch(&mut self, key: &ChunkKey, size: u64) {
+        self.tick += 1;
+        if let Some((tick, old)) = self.chunks.insert(key.clone(), (self.tick, size)) {
+            self.used.remove(&tick);
+            self.total -= old;
+        }
+        self.used.insert(self.tick, key.clone());
+        self.total += size;
+    }
+
+    // 返回要删除的块, 刚使用的块总是保留
+    fn evict(&mut self, limit: u64) -> Vec<ChunkKey> {
+        let mut res = vec![];
+        while self.total > limit && self.used.len() > 1 {
+            let Some((_, key)) = self.used.pop_first() else {
+                break;
+            };
+            if let Some((_, size)) = self.chunks.remove(&key) {
+                self.total -= size;
+            }
+            res.push(key);
+        }
+        res
+    }
+}
+
+struct ChunkCache {
+    dir: PathBuf,
+    chunk_size: u64,
+    limit: u64,
+    // 同一个块同时只请求一次, 读取和预读互相等待
+    locks: Mutex<ChunkLocks>,
+    lru: Mutex<ChunkLru>,
+}
+
+impl ChunkCache {
+    fn new(dir: PathBuf, chunk_size: u64, limit: u64) -> Self {
+        let cache = ChunkCache {
+            dir,
+            chunk_size: chunk_size.max(1),
+            limit,
+            locks: Mutex::new(HashMap::new()),
+            lru: Mutex::new(ChunkLru::default()),
+        };
+        cache.scan();
+        cache
+    }
+
+    fn path(&self, file_id: &str, index: u64) -> PathBuf {
+        self.dir.join(file_id).join(index.to_string())
+    }
+
+    // 上次挂载留下的块按修改时间加入记录, 超过上限的部分删除
+    fn scan(&self) {
+        let mut chunks = vec![];
+        for dir in std::fs::read_dir(&self.dir).into_iter().flatten().flatten() {
+            let file_id = dir.file_name().to_string_lossy().to_string();
+            for entry in std::fs::read_dir(dir.path())
+                .into_iter()
+                .flatten()
+                .flatten()
+            {
+                let Some(index) = entry.file_name().to_str().and_then(|x| x.parse().ok()) else {
+                    continue;
+                };
+                if let Result::Ok(meta) = entry.metadata() {
+                    let modified = meta.modified().unwrap_or(UNIX_EPOCH);
+                    chunks.push((modified, (file_id.clone(), index), meta.len()));
+                }
+            }
+        }
+        chunks.sort();
+        let mut lru = self.lru.lock().unwrap();
+        for (_, key, size) in chunks {
+            lru.touch(&key, size);
+        }
+        let evicted = lru.evict(self.limit);
+        drop(lru);
+        self.remove(evicted);
+    }
+
+    fn used(&self, key: &ChunkKey, size: u64) {
+        let evicted = {
+            let mut lru = self.lru.lock().unwrap();
+            lru.touch(key, size);
+            lru.evict(self.limit)
+        };
+        self.remove(evicted);
+    }
+
+    fn remove(&self, chunks: Vec<ChunkKey>) {
+        for (file_id, index) in chunks {
+            debug!("evict chunk {} of {}", index, file_id);
+            let path = self.path(&file_id, index);
+            if let Err(err) = std::fs::remove_file(&path) {
+                debug!("remove {} failed, err: {}", path.display(), err);
+            }
+        }
+    }
+
+    async fn chunk(&self, gateway: &Gateway, file: &FileStatus, index: u64) -> Result<Vec<u8>> {
+        let start = index * self.chunk_size;
+        let end = (start + self.chunk_size).min(file_size(file));
+        if start >= end {
+            return Ok(vec![]);
+        }
+        let key = (file.id.clone(), index);
+        let lock = self
+            .locks
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        let _guard = lock.lock().await;
+        let res = self.load(gateway, file, index, start, end).await;
+        self.locks.lock().unwrap().remove(&key);
+        if let Result::Ok(data) = &res {
+            self.used(&key, data.len() as u64);
+        }
+        res
+    }
+
+    async fn load(
+        &self,
+        gateway: &Gateway,
+        file: &FileStatus,
+        index: u64,
+        start: u64,
+        end: u64,
+    ) -> Result<Vec<u8>> {
+        let path = self.path(&file.id, index);
+        if let Result::Ok(data) = tokio::fs::read(&path).await {
+            if data.len() as u64 == end - start {
+                return Ok(data);
+            }
+        }
+
+        debug!("fetch chunk {} of {}", index, file.name);
+        let range = HeaderValue::from_str(&format!("bytes={}-{}", start, end - 1))?;
+        let resp = gateway.fetch(&file.id, Some(&range)).await?;
+        let status = resp.status();
+        if !status.is_success() {
+            return Err(anyhow::anyhow!(
+                "[mount] fetch chunk {} of {} failed: {}",
+                index,
+                file.name,
+                status
+            ));
+        }
+        let mut data = resp
+            .bytes()
+            .await
+            .with_context(|| format!("[mount] read chunk {} of {} failed", index, file.name))?
+            .to_vec();
+        // 服务器忽略了 Range, 返回了整个文件
+        if status == StatusCode::OK && data.len() as u64 == file_size(file) {
+            data = data[start as usize..end as usize].to_vec();
+        }
+        if data.len() as u64 != end - start {
+            return Err(anyhow::anyhow!(
+                "[mount] chunk {} of {} size mismatch, expect {}, got {}",
+                index,
+                file.name,
+                end - start,
+                data.len()
+            ));
+        }
+
+        let tmp = path.with_extension("part");
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&tmp, &data).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(data)
+    }
+
+    async fn read(
+        self: &Arc<Self>,
+        gateway: &Arc<Gateway>,
+        file: &FileStatus,
+        offset: u64,
+        size: u64,
+        read_ahead: u64,
+    ) -> Result<Vec<u8>> {
+        let total = file_size(file);
+        let end = (offset + size).min(total);
+        let mut res = vec![];
+        if offset >= end {
+            return Ok(res);
+        }
+        let first = offset / self.chunk_size;
+        let last = (end - 1) / self.chunk_size;
+        for index in first..=last {
+            let chunk = self.chunk(gateway, file, index).await?;
+            let chunk_start = index * self.chunk_size;
+            let from = offset.saturating_sub(chunk_start) as usize;
+            let to = ((end - chunk_start) as usize).min(chunk.len());
+            res.extend_from_slice(&chunk[from..to]);
+        }
+
+        let chunks = total.div_ceil(self.chunk_size);
+        for index in last + 1..(last + 1 + read_ahead).min(chunks) {
+            if self.path(&file.id, index).exists() {
+                continue;
+            }
+            let (cache, gateway, file) = (self.clone(), gateway.clone(), file.clone());
+            tokio::spawn(async move {
+                if let Err(err) = cache.chunk(&gateway, &file, index).await {
+                    warn!("read ahead failed, err: {:#}", err);
+                }
+            });
+        }
+        Ok(res)
+    }
+}
+
+struct DriveFs {
+    runtime: tokio::runtime::Handle,
+    gateway: Arc<Gateway>,
+    cache: Arc<ChunkCache>,
+    inodes: Inodes,
+    read_ahead: u64,
+    uid: u32,
+    gid: u32,
+}
+
+fn io_error(err: anyhow::Error) -> c_int {
+    error!("fuse request failed, err: {:#}", err);
+    libc::EIO
+}
+
+fn system_time(time: &str) -> SystemTime {
+    DateTime::parse_from_rfc3339(time).map_or(UNIX_EPOCH, SystemTime::from)
+}
+
+impl DriveFs {
+    fn attr(&self, ino: u64) -> Option<FileAttr> {
+        let file = &self.inodes.get(ino)?.file;
+        let folder = ino == ROOT_INO || is_folder(file);
+        let size = if folder { 0 } else { file_size(file) };
+        let mtime = system_time(&file.modified_time);
+        Some(FileAttr {
+            ino,
+            size,
+            blocks: size.div_ceil(512),
+            atime: mtime,
+            mtime,
+            ctime: mtime,
+            crtime: system_time(&file.created_time),
+            kind: if folder {
+                NodeKind::Directory
+            } else {
+                NodeKind::RegularFile
+            },
+            perm: if folder { 0o555 } else { 0o444 },
+            nlink: if folder { 2 } else { 1 },
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: 4096,
+            flags: 0,
+        })
+    }
+
+    fn children(&mut self, ino: u64) -> Result<Vec<u64>, c_int> {
+        let node = self.inodes.get(ino).ok_or(libc::ENOENT)?;
+        if ino != ROOT_INO && !is_folder(&node.file) {
+            return Err(libc::ENOTDIR);
+        }
+        let list = self
+            .runtime
+            .block_on(self.gateway.list(&node.file.id))
+            .map_err(io_error)?;
+        Ok(list.iter().map(|x| self.inodes.insert(x, ino)).collect())
+    }
+
+    fn lookup_name(&mut self, parent: u64, name: &OsStr) -> Result<u64, c_int> {
+        let children = self.children(parent)?;
+        children
+            .into_iter()
+            .find(|&x| {
+                self.inodes
+                    .get(x)
+                    .is_some_and(|x| name == x.file.name.as_str())
+            })
+            .ok_or(libc::ENOENT)
+    }
+
+    fn read_file(&self, ino: u64, offset: u64, size: u64) -> Result<Vec<u8>, c_int> {
+        let file = &self.inodes.get(ino).ok_or(libc::ENOENT)?.file;
+        if ino == ROOT_INO || is_folder(file) {
+            return Err(libc::EISDIR);
+        }
+        self.runtime
+            .block_on(
+                self.cache
+                    .read(&self.gateway, file, offset, size, self.read_ahead),
+            )
+            .map_err(io_error)
+    }
+}
+
+impl Filesystem for DriveFs {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        match self.lookup_name(parent, name) {
+            Result::Ok(ino) => match self.attr(ino) {
+                Some(attr) => reply.entry(&ATTR_TTL, &attr, 0),
+                None => reply.error(libc::ENOENT),
+            },
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+        match self.attr(ino) {
+            Some(attr) => reply.attr(&ATTR_TTL, &attr),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let children = match self.children(ino) {
+            Result::Ok(x) => x,
+            Err(err) => return reply.error(err),
+        };
+        let parent = self.inodes.get(ino).map_or(ROOT_INO, |x| x.parent);
+        let mut entries = vec![
+            (ino, NodeKind::Directory, ".".to_string()),
+            (parent, NodeKind::Directory, "..".to_string()),
+        ];
+        for child in children {
+            if let (Some(node), Some(attr)) = (self.inodes.get(child), self.attr(child)) {
+                entries.push((child, attr.kind, node.file.name.clone()));
+            }
+        }
+        for (i, (ino, kind, name)) in entries.into_iter().enumerate().skip(offset as usize) {
+            // 缓冲区已满, 内核会带着新的 offset 再次请求
+            if reply.add(ino, i as i64 + 1, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        match self.read_file(ino, offset.max(0) as u64, size as u64) {
+            Result::Ok(data) => reply.data(&data),
+            Err(err) => reply.error(err),
+        }
+    }
+}
+
+impl DriveFs {
+    async fn new(client: Client, opts: &MountOptions, uid: u32, gid: u32) -> Result<Self> {
+        let gateway = Arc::new(Gateway::new(client, &opts.root));
+        let root = gateway
+            .resolve("")
+            .await?
+            .filter(is_folder)
+            .ok_or(anyhow::anyhow!("[mount] {} is not a folder", opts.root))?;
+        let mut inodes = Inodes::default();
+        inodes.insert(&root, ROOT_INO);
+        Ok(DriveFs {
+            runtime: tokio::runtime::Handle::current(),
+            gateway,
+            cache: Arc::new(ChunkCache::new(
+                opts.cache_dir.clone(),
+                opts.chunk_size,
+                opts.cache_size,
+            )),
+            inodes,
+            read_ahead: opts.read_ahead,
+            uid,
+            gid,
+        })
+    }
+}
+
+impl Client {
+    pub async fn mount(self, mountpoint: PathBuf, opts: MountOptions) -> Result<()> {
+        let meta = std::fs::metadata(&mountpoint)
+            .with_context(|| format!("[mount] invalid mountpoint {}", mountpoint.display()))?;
+        if !meta.is_dir() {
+            return Err(anyhow::anyhow!(
+                "[mount] {} is not a directory",
+                mountpoint.display()
+            ));
+        }
+        std::fs::create_dir_all(&opts.cache_dir).with_context(|| {
+            format!(
+                "[mount] create cache dir {} failed",
+                opts.cache_dir.display()
+            )
+        })?;
+        // 文件属于挂载点的所有者
+        let fs = DriveFs::new(self, &opts, meta.uid(), meta.gid()).await?;
+
+        let mut options = vec![
+            MountOption::RO,
+            MountOption::FSName("pikpak".into()),
+            MountOption::Subtype("pikpakcli".into()),
+        ];
+        if opts.allow_other {
+            options.push(MountOption::AllowOther);
+            options.push(MountOption::AutoUnmount);
+        }
+        info!(
+            "mounting {} on {}, chunk cache: {}",
+            opts.root,
+            mountpoint.display(),
+            opts.cache_dir.display()
+        );
+        tokio::task::spawn_blocking(move || fuser::mount2(fs, &mountpoint, &options))
+            .await?
+            .context("[mount] fuse session failed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pikpak::mock::MockServer;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_mount_fs_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        let mut client = server.client();
+        client.login().await?;
+        let dir = tempfile::tempdir()?;
+        let opts = MountOptions {
+            root: "/My Pack".into(),
+            cache_dir: dir.path().to_path_buf(),
+            chunk_size: 4,
+            cache_size: 8,
+            read_ahead: 1,
+            allow_other: false,
+        };
+        let fs = DriveFs::new(client, &opts, 0, 0).await?;
+
+        // 文件系统的回调运行在阻塞线程上
+        let (fs, data, again) = tokio::task::spawn_blocking(move || {
+            let mut fs = fs;
+            let ino = fs.lookup_name(ROOT_INO, OsStr::new("a.txt")).unwrap();
+            assert_eq!(fs.attr(ino).unwrap().size, 11);
+            assert_eq!(
+                fs.lookup_name(ROOT_INO, OsStr::new("missing")),
+                Err(libc::ENOENT)
+            );
+            let data = fs.read_file(ino, 2, 7).unwrap();
+            let again = fs.read_file(ino, 0, 100).unwrap();
+            (fs, data, again)
+        })
+        .await?;
+        assert_eq!(data, b"llo wor");
+        assert_eq!(again, b"hello world");
+        let sub = fs.inodes.ids["folder-sub"];
+        assert_eq!(fs.attr(sub).unwrap().kind, NodeKind::Directory);
+        // 超过缓存上限时删除最久没有使用的块
+        assert!(!dir.path().join("file-a").join("0").exists());
+        assert!(dir.path().join("file-a").join("1").exists());
+        assert!(dir.path().join("file-a").join("2").exists());
+
+        // 重新挂载时按上限清理上次留下的块
+        ChunkCache::new(dir.path().to_path_buf(), 4, 4);
+        assert!(!dir.path().join("file-a").join("1").exists());
+        assert!(dir.path().join("file-a").join("2").exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+// 原子下载模式: 先下载到隐藏的临时文件, 校验通过后再重命名为最终文件名
+// 续传需要的信息保存在临时文件旁的 json 中, 远程文件变化时重新下载
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::cli::conflict::checksum_matches;
+use crate::pikpak::file::FileType;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Sidecar {
+    remote_id: String,
+    size: String,
+    md5_checksum: String,
+    hash: String,
+    // 转码视频的名称, 不同清晰度的临时文件不能续传
+    #[serde(default)]
+    variant: String,
+}
+
+impl Sidecar {
+    fn new(file: &FileType) -> Self {
+        Sidecar {
+            remote_id: file.id.clone(),
+            size: file.size.clone(),
+            md5_checksum: file.md5_checksum.clone(),
+            hash: file.hash.clone(),
+            variant: file.variant.clone(),
+        }
+    }
+}
+
+fn hidden_path(output_path: &Path, suffix: &str) -> PathBuf {
+    let name = output_path
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+    output_path.with_file_name(format!(".{}{}", name, suffix))
+}
+
+// a.txt -> .a.txt.pikpakpart
+pub fn partial_path(output_path: &Path) -> PathBuf {
+    hidden_path(output_path, ".pikpakpart")
+}
+
+pub fn sidecar_path(output_path: &Path) -> PathBuf {
+    hidden_path(output_path, ".pikpakpart.json")
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            Err(err).with_context(|| format!("[partial] remove {} failed", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+// 返回临时文件路径, 已有的临时文件不属于当前的远程文件时丢弃
+pub fn prepare(output_path: &Path, file: &FileType) -> Result<PathBuf> {
+    let partial = partial_path(output_path);
+    let sidecar_path = sidecar_path(output_path);
+    let sidecar = Sidecar::new(file);
+
+    let saved = std::fs::read(&sidecar_path)
+        .ok()
+        .and_then(|x| serde_json::from_slice::<Sidecar>(&x).ok());
+    if saved.as_ref() == Some(&sidecar) {
+        return Ok(partial);
+    }
+    if saved.is_some() {
+        warn!(
+            "remote file changed, discard partial file: {}",
+            partial.display()
+        );
+    }
+    remove_if_exists(&partial)?;
+    let content = serde_json::to_vec_pretty(&sidecar)?;
+    std::fs::write(&sidecar_path, content).context("[partial] write sidecar failed")?;
+    Ok(partial)
+}
+
+// 校验大小和 hash, 写入磁盘后重命名为最终文件
+pub fn finalize(output_path: &Path, file: &FileType) -> Result<()> {
+    let partial = partial_path(output_path);
+    let size = std::fs::metadata(&partial)
+        .context("[partial] get partial file metadata failed")?
+        .len();
+    let remote_size = file.size.parse::<u64>().unwrap_or(size);
+    let mismatch = if size != remote_size {
+        Some(format!(
+            "size mismatch: local {}, remote {}",
+            size, remote_size
+        ))
+    } else if checksum_matches(file, &partial)? == Some(false) {
+        Some("checksum mismatch".to_string())
+    } else {
+        None
+    };
+    if let Some(reason) = mismatch {
+        // 临时文件已损坏, 续传也无法修复
+        remove_if_exists(&partial)?;
+        remove_if_exists(&sidecar_path(output_path))?;
+        return Err(anyhow::anyhow!(
+            "[partial] verify {} failed: {}",
+            output_path.display(),
+            reason
+        ));
+    }
+
+    std::fs::File::open(&partial)
+        .and_then(|x| x.sync_all())
+        .context("[partial] sync partial file failed")?;
+    std::fs::rename(&partial, output_path).context("[partial] rename partial file failed")?;
+    #[cfg(unix)]
+    if let Some(dir) = output_path.parent() {
+        std::fs::File::open(dir)
+            .and_then(|x| x.sync_all())
+            .context("[partial] sync dir failed")?;
+    }
+    remove_if_exists(&sidecar_path(output_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remote(id: &str, content: &[u8]) -> FileType {
+        FileType {
+            id: id.into(),
+            size: content.len().to_string(),
+            md5_checksum: format!("{:x}", md5::compute(content)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_partial() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let output = dir.path().join("a.txt");
+        let file = remote("a", b"hello world");
+
+        let partial = prepare(&output, &file)?;
+        assert_eq!(partial, dir.path().join(".a.txt.pikpakpart"));
+        std::fs::write(&partial, b"hello")?;
+        // 同一个远程文件保留已下载的部分
+        prepare(&output, &file)?;
+        assert!(partial.exists());
+        // 远程文件变化时丢弃
+        prepare(&output, &remote("a", b"hello WORLD"))?;
+        assert!(!partial.exists());
+
+        std::fs::write(&partial, b"hello world")?;
+        assert!(finalize(&output, &remote("a", b"hello WORLD")).is_err());
+        assert!(!partial.exists() && !output.exists());
+
+        prepare(&output, &file)?;
+        std::fs::write(&partial, b"hello world")?;
+        finalize(&output, &file)?;
+        assert_eq!(std::fs::read(&output)?, b"hello world");
+        assert!(!partial.exists());
+        assert!(!sidecar_path(&output).exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,159 @@
+// 导出远程媒体目录为扩展 M3U 播放列表
+// 默认使用有过期时间的下载链接, 指定网关时使用按文件 id 访问的稳定链接
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use log::*;
+
+use crate::cli::download::{DownloadOptions, RemoteFile};
+use crate::cli::gateway::{encode_path, ID_PREFIX};
+use crate::pikpak::Client;
+use crate::utils::sort::natural_cmp;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlaylistItem {
+    pub title: String,
+    pub duration: Option<f64>,
+    pub url: String,
+}
+
+impl Client {
+    pub async fn playlist(
+        mut self,
+        path: String,
+        gateway: Option<String>,
+        output: Option<PathBuf>,
+        opts: DownloadOptions,
+    ) -> Result<()> {
+        let (items, expire) = self.get_playlist(path, gateway.as_deref(), &opts).await?;
+        if let Some(expire) = &expire {
+            info!(
+                "exported {} items, links expire at: {}",
+                items.len(),
+                expire
+            );
+        }
+        let content = render(&items, expire.as_deref());
+        match output {
+            Some(path) => std::fs::write(&path, content)
+                .with_context(|| format!("[playlist] write {} failed", path.display())),
+            None => Ok(std::io::stdout().write_all(content.as_bytes())?),
+        }
+    }
+
+    // 返回播放列表和最早的链接过期时间
+    async fn get_playlist(
+        &mut self,
+        path: String,
+        gateway: Option<&str>,
+        opts: &DownloadOptions,
+    ) -> Result<(Vec<PlaylistItem>, Option<String>)> {
+        let (mut files, failures) = self.build_files(vec![path], opts).await?;
+        if files.is_empty() {
+            if let Some(item) = failures.first() {
+                return Err(anyhow::anyhow!("[playlist] {}", item.reason));
+            }
+        }
+        for item in failures {
+            error!("list {} failed: {}", item.remote_path, item.reason);
+        }
+        files.sort_by(|a, b| natural_cmp(&a.entry.remote_path, &b.entry.remote_path));
+
+        let mut items = vec![];
+        let mut expire: Option<String> = None;
+        for RemoteFile { entry, status, .. } in files {
+            let title = match status.name.rsplit_once('.') {
+                Some((stem, _)) if !stem.is_empty() => stem.to_string(),
+                _ => status.name.clone(),
+            };
+            let url = match gateway {
+                Some(gateway) => format!(
+                    "{}{}{}/{}",
+                    gateway.trim_end_matches('/'),
+                    ID_PREFIX,
+                    entry.remote_id,
+                    encode_path(&status.name)
+                ),
+                None => {
+                    let file = self.get_file_by_id(entry.remote_id).await?;
+                    let link = file.links.application_octet_stream;
+                    if expire.as_ref().is_none_or(|x| link.expire < *x) {
+                        expire = Some(link.expire);
+                    }
+                    link.url
+                }
+            };
+            items.push(PlaylistItem {
+                title,
+                duration: status.duration(),
+                url,
+            });
+        }
+        Ok((items, expire))
+    }
+}
+
+pub fn render(items: &[PlaylistItem], expire: Option<&str>) -> String {
+    let mut res = String::from("#EXTM3U\n");
+    if let Some(expire) = expire {
+        res += &format!("# links expire at {}\n", expire);
+    }
+    for item in items {
+        // 时长未知时为 -1
+        let duration = item.duration.map_or(-1, |x| x.round() as i64);
+        // 标题中的换行会破坏格式
+        let title = item.title.replace(['\r', '\n'], " ");
+        res += &format!("#EXTINF:{},{}\n{}\n", duration, title, item.url);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::args::FilterArgs;
+    use crate::cli::filter::DownloadFilter;
+    use crate::pikpak::mock::{MockFile, MockServer};
+    use crate::utils::category::FileCategory;
+
+    #[tokio::test]
+    async fn test_playlist_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        let mut ep10 = MockFile::file("file-ep10", "folder-my-pack", "ep10.mp4", b"10");
+        ep10.params = json!({ "duration": "1432.6" });
+        server.state().files.extend([
+            ep10,
+            MockFile::file("file-ep2", "folder-my-pack", "ep2.mp4", b"2"),
+            MockFile::file("file-song", "folder-sub", "song.mp3", b"song"),
+        ]);
+        let mut client = server.client();
+        client.login().await?;
+
+        let opts = DownloadOptions {
+            filter: DownloadFilter::new(&FilterArgs {
+                category: vec![FileCategory::Video, FileCategory::Audio],
+                ..Default::default()
+            })?,
+            ..Default::default()
+        };
+        let (items, expire) = client
+            .get_playlist("/My Pack".into(), Some("http://nas:8080"), &opts)
+            .await?;
+        assert!(expire.is_none());
+        let titles: Vec<_> = items.iter().map(|x| x.title.as_str()).collect();
+        assert_eq!(titles, vec!["ep2", "ep10", "song"]);
+        assert_eq!(
+            render(&items[1..2], None),
+            "#EXTM3U\n#EXTINF:1433,ep10\nhttp://nas:8080/.id/file-ep10/ep10.mp4\n"
+        );
+
+        let (items, expire) = client.get_playlist("/My Pack".into(), None, &opts).await?;
+        assert_eq!(items[0].url, format!("{}/download/file-ep2", server.url()));
+        assert_eq!(items[0].duration, None);
+        assert!(expire.is_some());
+        Ok(())
+    }
+}
@@ -0,0 +1,245 @@
+// 选择转码后的视频代替原始文件
+// 没有指定的清晰度时选择较低的里最接近的, 再没有时选择较高的, 都没有时使用原始文件
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use log::*;
+
+use crate::args::Quality;
+use crate::pikpak::download::content_info;
+use crate::pikpak::file::{FileType, Media};
+use crate::pikpak::Client;
+
+// 清晰度名称中的数字, 如 1080P, 没有时使用视频高度
+fn level(media: &Media) -> Option<u64> {
+    let name = if media.resolution_name.is_empty() {
+        &media.media_name
+    } else {
+        &media.resolution_name
+    };
+    if name.to_uppercase().contains("4K") {
+        return Some(2160);
+    }
+    let digits: String = name.chars().filter(|x| x.is_ascii_digit()).collect();
+    digits
+        .parse()
+        .ok()
+        .or(Some(media.video.height))
+        .filter(|x| *x > 0)
+}
+
+pub fn select(file: &FileType, height: u64) -> Option<(u64, &Media)> {
+    let variants: Vec<_> = file
+        .medias
+        .iter()
+        .filter(|x| !x.is_origin && !x.link.url.is_empty())
+        .filter_map(|x| Some((level(x)?, x)))
+        .collect();
+    let below = variants
+        .iter()
+        .filter(|(x, _)| *x <= height)
+        .max_by_key(|(x, _)| *x);
+    let above = variants
+        .iter()
+        .filter(|(x, _)| *x > height)
+        .min_by_key(|(x, _)| *x);
+    below.or(above).copied()
+}
+
+// 转码后视频的容器格式, 先看 Content-Type, 再看链接中的扩展名
+fn container(content_type: Option<&str>, url: &str) -> Option<String> {
+    let mime = content_type
+        .and_then(|x| x.split(';').next())
+        .map(|x| x.trim().to_lowercase());
+    let ext = match mime.as_deref() {
+        Some("video/mp4") => Some("mp4"),
+        Some("video/x-matroska") => Some("mkv"),
+        Some("video/webm") => Some("webm"),
+        Some("video/quicktime") => Some("mov"),
+        Some("video/mp2t") => Some("ts"),
+        Some("video/x-flv") => Some("flv"),
+        _ => None,
+    };
+    if let Some(ext) = ext {
+        return Some(ext.to_string());
+    }
+    let url = reqwest::Url::parse(url).ok()?;
+    let ext = Path::new(url.path()).extension()?.to_str()?.to_lowercase();
+    (ext.len() <= 5 && ext.chars().all(|x| x.is_ascii_alphanumeric())).then_some(ext)
+}
+
+// 下载转码视频时本地文件使用转码后的扩展名
+pub fn variant_path(path: &Path, file: &FileType) -> PathBuf {
+    match Path::new(&file.name).extension() {
+        Some(ext) if !file.variant.is_empty() => path.with_extension(ext),
+        _ => path.to_path_buf(),
+    }
+}
+
+impl Client {
+    // 获取文件信息, 并把下载链接替换为对应清晰度的视频
+    pub async fn get_file_with_quality(
+        &mut self,
+        file_id: String,
+        quality: Quality,
+    ) -> Result<FileType> {
+        let mut file = self.get_file_by_id(file_id).await?;
+        let Some(height) = quality.height() else {
+            return Ok(file);
+        };
+        let Some((level, media)) = select(&file, height) else {
+            warn!("{} has no transcoded variant, use the original", file.name);
+            return Ok(file);
+        };
+        if level != height {
+            info!("{} has no {}p variant, use {}p", file.name, height, level);
+        }
+        let (link, variant) = (media.link.clone(), media.media_name.clone());
+        debug!("use variant {} of {}", variant, file.name);
+
+        // 原始文件的大小和校验值不适用于转码后的视频
+        let (size, content_type) = match content_info(&link.url).await {
+            Result::Ok(info) => info,
+            Err(err) => {
+                warn!("get size of {} failed, err: {:#}", file.name, err);
+                (None, None)
+            }
+        };
+        file.size = size.map(|x| x.to_string()).unwrap_or_default();
+        match container(content_type.as_deref(), &link.url) {
+            Some(ext) => {
+                file.name = Path::new(&file.name)
+                    .with_extension(&ext)
+                    .to_string_lossy()
+                    .to_string();
+                file.file_extension = format!(".{}", ext);
+            }
+            None => warn!(
+                "unknown container of {} variant, keep the original extension: {}",
+                variant, file.name
+            ),
+        }
+        file.variant = variant;
+        file.md5_checksum.clear();
+        file.hash.clear();
+        file.links.application_octet_stream = link;
+        Ok(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::cat::CatRange;
+    use crate::cli::download::DownloadOptions;
+    use crate::pikpak::mock::{MockFile, MockServer};
+
+    #[test]
+    fn test_select() {
+        let media = |name: &str, height| Media {
+            resolution_name: name.into(),
+            media_name: name.into(),
+            video: crate::pikpak::file::MediaVideo {
+                height,
+                ..Default::default()
+            },
+            link: crate::pikpak::file::ApplicationOctetStream {
+                url: format!("http://{}", name),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let file = FileType {
+            medias: vec![
+                Media {
+                    is_origin: true,
+                    ..media("Original", 0)
+                },
+                media("720P", 720),
+                // 宽屏视频的高度小于清晰度名称
+                media("1080P", 800),
+                media("", 480),
+            ],
+            ..Default::default()
+        };
+        let level = |height| select(&file, height).map(|(x, _)| x);
+        assert_eq!(level(1080), Some(1080));
+        assert_eq!(level(2160), Some(1080));
+        assert_eq!(level(720), Some(720));
+        assert_eq!(level(360), Some(480));
+        assert_eq!(select(&FileType::default(), 720).map(|(x, _)| x), None);
+    }
+
+    #[tokio::test]
+    async fn test_quality_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        {
+            let mut state = server.state();
+            let mut movie = MockFile::file("file-movie", "", "movie.mkv", b"original movie");
+            movie.medias = vec![("720P".into(), 720, "file-movie-720".into())];
+            state.files.extend([
+                movie,
+                MockFile::file("file-movie-720", "hidden", "movie.mp4", b"720p"),
+            ]);
+        }
+        let mut client = server.client();
+        client.login().await?;
+
+        let file = client
+            .get_file_with_quality("file-movie".into(), Quality::P1080)
+            .await?;
+        assert_eq!(
+            file.links.application_octet_stream.url,
+            format!("{}/download/file-movie-720", server.url())
+        );
+        assert_eq!(file.size, "4");
+        assert_eq!(file.name, "movie.mp4");
+        assert_eq!(file.variant, "720P");
+        assert!(file.md5_checksum.is_empty());
+
+        // 没有转码的文件使用原始文件
+        let file = client
+            .get_file_with_quality("file-a".into(), Quality::P720)
+            .await?;
+        assert_eq!(file.size, "11");
+
+        let mut out = vec![];
+        client
+            .cat_to("/movie.mkv", CatRange::All, Quality::P720, &mut out)
+            .await?;
+        assert_eq!(out, b"720p");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_quality_download_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        {
+            let mut state = server.state();
+            let mut movie = MockFile::file("file-movie", "", "movie.mkv", b"original movie");
+            movie.medias = vec![("720P".into(), 720, "file-movie-720".into())];
+            state.files.extend([
+                movie,
+                MockFile::file("file-movie-720", "hidden", "movie.mp4", b"720p"),
+            ]);
+        }
+        let mut client = server.client();
+        client.login().await?;
+
+        // 原始文件中断后留下的部分内容不能用于转码视频的续传
+        let dir = tempfile::tempdir()?;
+        let local = dir.path().join("movie.mp4");
+        std::fs::write(&local, b"or")?;
+        std::fs::write(crate::cli::conflict::flag_path(&local), b"")?;
+        let opts = DownloadOptions {
+            output: dir.path().to_str().unwrap().into(),
+            job_dir: dir.path().join("jobs"),
+            quality: Quality::P720,
+            ..Default::default()
+        };
+        client.download(vec!["/movie.mkv".into()], opts).await?;
+        assert_eq!(std::fs::read(&local)?, b"720p");
+        assert!(!dir.path().join("movie.mkv").exists());
+        Ok(())
+    }
+}
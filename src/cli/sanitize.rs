@@ -0,0 +1,198 @@
+// 远程文件名转换为本地文件名, 替换目标文件系统不支持的字符, 并为重名的文件加上 id 后缀
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::args::SanitizeMode;
+
+const MAX_NAME_BYTES: usize = 255;
+const WINDOWS_INVALID_CHARS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+// 重名时追加的 id 长度
+const SHORT_ID_LEN: usize = 6;
+
+#[derive(Debug, Clone)]
+pub struct Sanitizer {
+    pub mode: SanitizeMode,
+    pub replacement: char,
+    pub case_insensitive: bool,
+}
+
+impl Default for Sanitizer {
+    fn default() -> Self {
+        Sanitizer {
+            mode: SanitizeMode::Unix,
+            replacement: '_',
+            case_insensitive: false,
+        }
+    }
+}
+
+// a.txt -> (a, .txt), 以 . 开头的隐藏文件没有扩展名
+fn split_ext(name: &str) -> (&str, &str) {
+    match name.rfind('.') {
+        Some(i) if i > 0 && name.len() - i <= 32 => name.split_at(i),
+        _ => (name, ""),
+    }
+}
+
+// 截断 stem 使总长度不超过限制, 保留扩展名
+fn truncate(stem: &str, suffix: &str) -> String {
+    let max = MAX_NAME_BYTES.saturating_sub(suffix.len());
+    let mut end = stem.len().min(max);
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", &stem[..end], suffix)
+}
+
+impl Sanitizer {
+    pub fn new(mode: SanitizeMode, replacement: char, case_insensitive: bool) -> Result<Self> {
+        let sanitizer = Sanitizer {
+            mode,
+            replacement,
+            case_insensitive,
+        };
+        if mode != SanitizeMode::Off && sanitizer.invalid(replacement) {
+            return Err(anyhow::anyhow!(
+                "invalid replacement character: {:?}",
+                replacement
+            ));
+        }
+        Ok(sanitizer)
+    }
+
+    fn invalid(&self, c: char) -> bool {
+        c == '/'
+            || c == '\0'
+            || (self.mode == SanitizeMode::Windows
+                && (WINDOWS_INVALID_CHARS.contains(&c) || c.is_control()))
+    }
+
+    pub fn name(&self, name: &str) -> String {
+        if self.mode == SanitizeMode::Off {
+            return name.to_string();
+        }
+        let windows = self.mode == SanitizeMode::Windows;
+        let mut res: String = name
+            .chars()
+            .map(|c| if self.invalid(c) { self.replacement } else { c })
+            .collect();
+        if windows {
+            res = res.trim_end_matches(['.', ' ']).to_string();
+            let (stem, _) = split_ext(&res);
+            if WINDOWS_RESERVED_NAMES.contains(&stem.to_uppercase().as_str()) {
+                res.insert(stem.len(), self.replacement);
+            }
+        }
+        if res.is_empty() || res == "." || res == ".." {
+            res = self.replacement.to_string();
+        }
+        if res.len() > MAX_NAME_BYTES {
+            let (stem, ext) = split_ext(&res);
+            res = truncate(stem, ext);
+        }
+        res
+    }
+
+    // 逐级转换相对路径
+    pub fn path(&self, path: &Path) -> PathBuf {
+        path.components()
+            .filter_map(|x| match x {
+                Component::Normal(name) => Some(self.name(&name.to_string_lossy())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn key(&self, name: &str) -> String {
+        if self.case_insensitive {
+            name.to_lowercase()
+        } else {
+            name.to_string()
+        }
+    }
+
+    // 转换同一目录下的所有文件名, items 为 (id, name)
+    // 名字未被修改的文件优先保留原名, 其余按 id 排序, 保证每次的结果一致
+    pub fn dedupe(&self, items: &[(&str, &str)]) -> Vec<String> {
+        let mut names: Vec<String> = items.iter().map(|(_, name)| self.name(name)).collect();
+        let mut order: Vec<usize> = (0..items.len()).collect();
+        order.sort_by_key(|&i| (names[i] != items[i].1, items[i].0));
+
+        let mut used = HashSet::new();
+        for i in order {
+            let (id, _) = items[i];
+            if used.contains(&self.key(&names[i])) {
+                let (stem, ext) = split_ext(&names[i]);
+                let short = id
+                    .char_indices()
+                    .rev()
+                    .nth(SHORT_ID_LEN - 1)
+                    .map_or(id, |(x, _)| &id[x..]);
+                let mut name = truncate(stem, &format!("~{}{}", short, ext));
+                if used.contains(&self.key(&name)) {
+                    name = truncate(stem, &format!("~{}{}", id, ext));
+                }
+                names[i] = name;
+            }
+            used.insert(self.key(&names[i]));
+        }
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_name() {
+        let unix = Sanitizer::default();
+        assert_eq!(unix.name("a:b?.txt"), "a:b?.txt");
+        assert_eq!(unix.name(".."), "_");
+
+        let windows = Sanitizer {
+            mode: SanitizeMode::Windows,
+            ..Default::default()
+        };
+        assert_eq!(windows.name("a:b?.txt"), "a_b_.txt");
+        assert_eq!(windows.name("dir.. "), "dir");
+        assert_eq!(windows.name("con.txt"), "con_.txt");
+
+        let long = format!("{}.mp4", "好".repeat(100));
+        let name = unix.name(&long);
+        assert!(name.len() <= MAX_NAME_BYTES);
+        assert!(name.ends_with("好.mp4"));
+    }
+
+    #[test]
+    fn test_dedupe() {
+        let sanitizer = Sanitizer {
+            mode: SanitizeMode::Windows,
+            case_insensitive: true,
+            ..Default::default()
+        };
+        let names = sanitizer.dedupe(&[
+            ("id-000002", "a?.txt"),
+            ("id-000003", "A.txt"),
+            ("id-000001", "a_.txt"),
+            ("id-000004", "b.txt"),
+        ]);
+        assert_eq!(names, vec!["a_~000002.txt", "A.txt", "a_.txt", "b.txt"]);
+
+        let names = sanitizer.dedupe(&[("id-000001", "a.txt"), ("id-000002", "a.TXT")]);
+        assert_eq!(names, vec!["a.txt", "a~000002.TXT"]);
+    }
+
+    #[test]
+    fn test_invalid_replacement() {
+        assert!(Sanitizer::new(SanitizeMode::Unix, '/', false).is_err());
+        assert!(Sanitizer::new(SanitizeMode::Windows, ':', false).is_err());
+        assert!(Sanitizer::new(SanitizeMode::Unix, ':', false).is_ok());
+    }
+}
@@ -0,0 +1,222 @@
+// 为 Jellyfin/Emby 生成 .strm 媒体库, 每个视频对应一个指向本地网关的 .strm 文件
+// 链接使用文件 id, 远程文件移动或改名后仍然可以播放
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::cli::download::DownloadOptions;
+use crate::cli::gateway::{encode_path, ID_PREFIX};
+use crate::pikpak::Client;
+use crate::utils::file::write_atomic;
+
+// 记录上次生成的 .strm 文件, 用于增量更新和清理
+const STATE_FILE: &str = ".pikpakcli-strm.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StrmState {
+    // 相对输出目录的路径 -> 文件内容
+    entries: BTreeMap<PathBuf, String>,
+}
+
+impl StrmState {
+    fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("[strm] read {} failed", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("[strm] parse {} failed", path.display()))
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        write_atomic(path, serde_json::to_string_pretty(self)?.as_bytes())
+            .with_context(|| format!("[strm] write {} failed", path.display()))
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct StrmSummary {
+    pub added: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    pub removed: usize,
+}
+
+// 删除文件后向上清理空目录
+fn remove_file(output: &Path, path: &Path) -> Result<()> {
+    if path.exists() {
+        std::fs::remove_file(path)
+            .with_context(|| format!("[strm] remove {} failed", path.display()))?;
+    }
+    let mut dir = path.parent();
+    while let Some(x) = dir {
+        if x == output || !x.starts_with(output) || std::fs::remove_dir(x).is_err() {
+            break;
+        }
+        dir = x.parent();
+    }
+    Ok(())
+}
+
+impl Client {
+    pub async fn strm(
+        mut self,
+        path: String,
+        gateway: String,
+        incremental: bool,
+        opts: DownloadOptions,
+    ) -> Result<()> {
+        let summary = self
+            .generate_strm(path, &gateway, incremental, &opts)
+            .await?;
+        println!(
+            "added: {}, updated: {}, unchanged: {}, removed: {}",
+            summary.added, summary.updated, summary.unchanged, summary.removed
+        );
+        Ok(())
+    }
+
+    async fn generate_strm(
+        &mut self,
+        path: String,
+        gateway: &str,
+        incremental: bool,
+        opts: &DownloadOptions,
+    ) -> Result<StrmSummary> {
+        let output = Path::new(&opts.output);
+        let (entries, failures) = self.build_entries(vec![path], opts).await?;
+        for item in &failures {
+            error!("list {} failed: {}", item.remote_path, item.reason);
+        }
+
+        let mut used = HashSet::new();
+        let mut state = StrmState::default();
+        for entry in entries {
+            let mut strm = entry.local_path.with_extension("strm");
+            // a.mkv 和 a.mp4 会生成同一个 a.strm, 重名时保留原来的扩展名
+            if !used.insert(strm.clone()) {
+                strm = PathBuf::from(format!("{}.strm", entry.local_path.display()));
+                used.insert(strm.clone());
+            }
+            let name = entry.remote_path.rsplit('/').next().unwrap_or_default();
+            let url = format!(
+                "{}{}{}/{}\n",
+                gateway.trim_end_matches('/'),
+                ID_PREFIX,
+                entry.remote_id,
+                encode_path(name)
+            );
+            let rel = strm.strip_prefix(output).unwrap_or(&strm).to_path_buf();
+            state.entries.insert(rel, url);
+        }
+
+        let state_path = output.join(STATE_FILE);
+        let old = StrmState::load(&state_path)?;
+        let mut summary = StrmSummary::default();
+        for (rel, content) in &state.entries {
+            let path = output.join(rel);
+            match old.entries.get(rel) {
+                Some(x) if incremental && x == content && path.exists() => {
+                    summary.unchanged += 1;
+                    continue;
+                }
+                Some(_) => summary.updated += 1,
+                None => summary.added += 1,
+            }
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)
+                    .with_context(|| format!("[strm] create {} failed", parent.display()))?;
+            }
+            std::fs::write(&path, content)
+                .with_context(|| format!("[strm] write {} failed", path.display()))?;
+            debug!("write {}", path.display());
+        }
+
+        for (rel, content) in old.entries {
+            if state.entries.contains_key(&rel) {
+                continue;
+            }
+            // 有目录列出失败时不删除, 留到下次再处理
+            if !failures.is_empty() {
+                state.entries.insert(rel, content);
+                continue;
+            }
+            info!("remove {}", rel.display());
+            remove_file(output, &output.join(&rel))?;
+            summary.removed += 1;
+        }
+        if !failures.is_empty() {
+            warn!("some folders failed to list, stale .strm files are kept");
+        }
+
+        std::fs::create_dir_all(output)?;
+        state.save(&state_path)?;
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::FilterArgs;
+    use crate::cli::filter::DownloadFilter;
+    use crate::pikpak::mock::{MockFile, MockServer};
+    use crate::utils::category::FileCategory;
+
+    #[tokio::test]
+    async fn test_strm_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        server.state().files.extend([
+            MockFile::file("file-movie", "folder-my-pack", "movie.mkv", b"movie"),
+            MockFile::file("file-clip", "folder-sub", "clip 1.mp4", b"clip"),
+        ]);
+        let mut client = server.client();
+        client.login().await?;
+
+        let dir = tempfile::tempdir()?;
+        let opts = DownloadOptions {
+            output: dir.path().to_string_lossy().to_string(),
+            filter: DownloadFilter::new(&FilterArgs {
+                category: vec![FileCategory::Video],
+                ..Default::default()
+            })?,
+            ..Default::default()
+        };
+        let gateway = "http://127.0.0.1:8080/";
+        let summary = client
+            .generate_strm("/My Pack".into(), gateway, true, &opts)
+            .await?;
+        assert_eq!(summary.added, 2);
+        let clip = dir.path().join("My Pack/sub/clip 1.strm");
+        assert_eq!(
+            std::fs::read_to_string(&clip)?,
+            "http://127.0.0.1:8080/.id/file-clip/clip%201.mp4\n"
+        );
+        assert!(!dir.path().join("My Pack/a.strm").exists());
+
+        let summary = client
+            .generate_strm("/My Pack".into(), gateway, true, &opts)
+            .await?;
+        assert_eq!(
+            summary,
+            StrmSummary {
+                unchanged: 2,
+                ..Default::default()
+            }
+        );
+
+        server.state().files.retain(|x| x.id != "file-clip");
+        let summary = client
+            .generate_strm("/My Pack".into(), gateway, true, &opts)
+            .await?;
+        assert_eq!(summary.removed, 1);
+        assert!(!clip.exists());
+        assert!(!dir.path().join("My Pack/sub").exists());
+        assert!(dir.path().join("My Pack/movie.strm").exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+// 下载结束后的汇总报告
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use humansize::{format_size, DECIMAL};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutcomeStatus {
+    Downloaded,
+    Skipped,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Outcome {
+    #[serde(default)]
+    pub remote_id: String,
+    pub remote_path: String,
+    #[serde(default)]
+    pub local_path: PathBuf,
+    pub status: OutcomeStatus,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub reason: String,
+    pub bytes: u64,
+    pub duration_ms: u64,
+}
+
+impl Outcome {
+    pub fn new(
+        remote_id: &str,
+        remote_path: &str,
+        local_path: &Path,
+        status: OutcomeStatus,
+        reason: String,
+        bytes: u64,
+        duration: Duration,
+    ) -> Self {
+        Outcome {
+            remote_id: remote_id.to_string(),
+            remote_path: remote_path.to_string(),
+            local_path: local_path.to_path_buf(),
+            status,
+            reason,
+            bytes,
+            duration_ms: duration.as_millis() as u64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Summary {
+    #[serde(default)]
+    pub job: String,
+    pub downloaded: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub bytes: u64,
+    pub duration_ms: u64,
+    pub items: Vec<Outcome>,
+}
+
+impl Summary {
+    pub fn new(job: &str, mut items: Vec<Outcome>, duration: Duration) -> Self {
+        items.sort_by(|a, b| a.remote_path.cmp(&b.remote_path));
+        let count = |status| items.iter().filter(|x| x.status == status).count();
+        Summary {
+            job: job.to_string(),
+            downloaded: count(OutcomeStatus::Downloaded),
+            skipped: count(OutcomeStatus::Skipped),
+            failed: count(OutcomeStatus::Failed),
+            bytes: items.iter().map(|x| x.bytes).sum(),
+            duration_ms: duration.as_millis() as u64,
+            items,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("[summary] open {} failed", path.display()))?;
+        serde_json::from_reader(file)
+            .with_context(|| format!("[summary] parse {} failed", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("[summary] create {} failed", path.display()))?;
+        serde_json::to_writer_pretty(file, self).context("[summary] write report failed")
+    }
+
+    pub fn failed_items(&self) -> impl Iterator<Item = &Outcome> {
+        self.items
+            .iter()
+            .filter(|x| x.status == OutcomeStatus::Failed)
+    }
+
+    pub fn print(&self) {
+        let secs = (self.duration_ms as f64 / 1000.0).max(0.001);
+        println!();
+        println!("Download summary:");
+        println!("  downloaded: {}", self.downloaded);
+        println!("  skipped:    {}", self.skipped);
+        println!("  failed:     {}", self.failed);
+        println!(
+            "  transferred {} in {:.1}s ({}/s)",
+            format_size(self.bytes, DECIMAL),
+            secs,
+            format_size((self.bytes as f64 / secs) as u64, DECIMAL)
+        );
+        if self.failed > 0 {
+            println!("Failed items:");
+            for item in self.failed_items() {
+                println!("  {}: {}", item.remote_path, item.reason);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() -> Result<()> {
+        let items = vec![
+            Outcome::new(
+                "b",
+                "/b",
+                Path::new("b"),
+                OutcomeStatus::Failed,
+                "timeout".into(),
+                5,
+                Duration::from_millis(10),
+            ),
+            Outcome::new(
+                "a",
+                "/a",
+                Path::new("a"),
+                OutcomeStatus::Downloaded,
+                "".into(),
+                10,
+                Duration::from_millis(10),
+            ),
+        ];
+        let summary = Summary::new("job", items, Duration::from_secs(1));
+        assert_eq!(
+            (summary.downloaded, summary.skipped, summary.failed),
+            (1, 0, 1)
+        );
+        assert_eq!(summary.bytes, 15);
+        assert_eq!(summary.items[0].remote_path, "/a");
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("report.json");
+        summary.save(&path)?;
+        let loaded = Summary::load(&path)?;
+        assert_eq!(loaded, summary);
+        assert_eq!(loaded.failed_items().count(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,1037 @@
+// 本地目录和远程目录的双向同步
+// 状态文件记录上次同步后两边的版本, 用来区分两边的新增, 修改和删除
+// 两边都修改过的文件保留两份, 本地版本加上 conflict 后缀后上传, 不覆盖任何一边
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::{Context, Result};
+use async_recursion::async_recursion;
+use chrono::Local;
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::cli::conflict::checksum_matches;
+use crate::cli::download::{apply_metadata, download_file};
+use crate::pikpak::file::{FileStatus, FileType};
+use crate::pikpak::folder::FileIDType;
+use crate::pikpak::Client;
+use crate::utils::file::write_atomic;
+use crate::utils::hash::gcid_file;
+use crate::utils::path::slash;
+
+const STATE_FILE: &str = ".pikpakcli-sync.json";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct SyncEntry {
+    #[serde(default)]
+    folder: bool,
+    remote_id: String,
+    #[serde(default)]
+    size: u64,
+    // 本地修改时间, 毫秒
+    #[serde(default)]
+    mtime: u64,
+    #[serde(default)]
+    remote_modified: String,
+    // 内容的 gcid, 本地只有修改时间变化时用来确认内容是否改变
+    #[serde(default)]
+    hash: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SyncState {
+    remote: String,
+    // 相对路径 (用 / 分隔) -> 上次同步后的版本
+    entries: BTreeMap<String, SyncEntry>,
+}
+
+impl SyncState {
+    fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("[sync] read {} failed", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("[sync] parse {} failed", path.display()))
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        write_atomic(path, serde_json::to_string_pretty(self)?.as_bytes())
+            .with_context(|| format!("[sync] write {} failed", path.display()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct LocalItem {
+    pub folder: bool,
+    // 符号链接本身, 不跟随到目标
+    pub symlink: bool,
+    pub size: u64,
+    pub mtime: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Action {
+    Download,
+    Upload,
+    DeleteLocal,
+    TrashRemote,
+    // 两边都修改过, 保留两份
+    Conflict,
+    // 两边一致, 只更新状态
+    Record,
+    // 两边都已删除
+    Forget,
+    // 远程文件还没有上传完成
+    Skip,
+    CreateLocalDir,
+    CreateRemoteDir,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Step {
+    path: String,
+    folder: bool,
+    action: Action,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct SyncSummary {
+    pub downloaded: usize,
+    pub uploaded: usize,
+    pub deleted: usize,
+    pub trashed: usize,
+    pub conflicts: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+// 列目录和计划时用到的两边的当前状态
+struct Snapshot {
+    root: PathBuf,
+    local: BTreeMap<String, LocalItem>,
+    remote: BTreeMap<String, FileStatus>,
+}
+
+pub(super) fn join(rel: &str, name: &str) -> String {
+    if rel.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", rel, name)
+    }
+}
+
+fn split(path: &str) -> (&str, &str) {
+    path.rsplit_once('/').unwrap_or(("", path))
+}
+
+pub(super) fn is_folder(status: &FileStatus) -> bool {
+    status.kind == "drive#folder"
+}
+
+// 上传中的文件还不能下载, 但也不能当作已删除, 这次同步跳过
+pub(super) fn incomplete(status: &FileStatus) -> bool {
+    !is_folder(status) && !status.phase.is_empty() && status.phase != "PHASE_TYPE_COMPLETE"
+}
+
+fn local_item(metadata: &std::fs::Metadata) -> LocalItem {
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |x| x.as_millis() as u64);
+    LocalItem {
+        folder: metadata.is_dir(),
+        symlink: metadata.file_type().is_symlink(),
+        size: if metadata.is_dir() { 0 } else { metadata.len() },
+        mtime,
+    }
+}
+
+// 跳过状态文件和未完成的下载
+fn ignored(name: &str) -> bool {
+    name == STATE_FILE || (name.starts_with('.') && name.contains(".pikpakpart"))
+}
+
+// 符号链接也会列出, 但不进入链接到的目录
+pub(super) fn scan_local(
+    root: &Path,
+    rel: &str,
+    items: &mut BTreeMap<String, LocalItem>,
+) -> Result<()> {
+    let dir = root.join(rel);
+    for entry in
+        std::fs::read_dir(&dir).with_context(|| format!("[sync] read {} failed", dir.display()))?
+    {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if ignored(&name) {
+            continue;
+        }
+        let path = join(rel, &name);
+        let item = local_item(&entry.metadata()?);
+        items.insert(path.clone(), item);
+        if item.folder {
+            scan_local(root, &path, items)?;
+        }
+    }
+    Ok(())
+}
+
+fn local_changed(path: &Path, item: &LocalItem, entry: &SyncEntry) -> Result<bool> {
+    if item.folder != entry.folder || item.size != entry.size {
+        return Ok(true);
+    }
+    if item.mtime == entry.mtime {
+        return Ok(false);
+    }
+    if entry.hash.is_empty() {
+        return Ok(true);
+    }
+    Ok(!gcid_file(path)?.eq_ignore_ascii_case(&entry.hash))
+}
+
+fn remote_changed(status: &FileStatus, entry: &SyncEntry) -> bool {
+    if is_folder(status) != entry.folder
+        || status.id != entry.remote_id
+        || status.size != entry.size.to_string()
+    {
+        return true;
+    }
+    status.modified_time != entry.remote_modified
+        && (status.hash.is_empty() || !status.hash.eq_ignore_ascii_case(&entry.hash))
+}
+
+fn same_content(path: &Path, item: &LocalItem, status: &FileStatus) -> Result<bool> {
+    if status.size != item.size.to_string() {
+        return Ok(false);
+    }
+    let file = FileType {
+        md5_checksum: status.md5_checksum.clone(),
+        hash: status.hash.clone(),
+        ..Default::default()
+    };
+    Ok(checksum_matches(&file, path)? == Some(true))
+}
+
+// 目录下还有保留的文件或目录
+fn has_children(after: &BTreeSet<String>, path: &str) -> bool {
+    let prefix = format!("{}/", path);
+    after
+        .range(prefix.clone()..)
+        .next()
+        .is_some_and(|x| x.starts_with(&prefix))
+}
+
+fn plan_file(snapshot: &Snapshot, path: &str, entry: Option<&SyncEntry>) -> Result<Option<Action>> {
+    let local_path = snapshot.root.join(path);
+    let local = snapshot.local.get(path);
+    let remote = snapshot.remote.get(path);
+    let action = match (local, remote, entry) {
+        (Some(l), Some(r), Some(s)) => {
+            let lc = local_changed(&local_path, l, s)?;
+            let rc = remote_changed(r, s);
+            match (lc, rc) {
+                (false, false) if l.mtime != s.mtime || r.modified_time != s.remote_modified => {
+                    Some(Action::Record)
+                }
+                (false, false) => None,
+                (true, false) => Some(Action::Upload),
+                (false, true) => Some(Action::Download),
+                (true, true) if same_content(&local_path, l, r)? => Some(Action::Record),
+                (true, true) => Some(Action::Conflict),
+            }
+        }
+        (Some(l), Some(r), None) if same_content(&local_path, l, r)? => Some(Action::Record),
+        (Some(_), Some(_), None) => Some(Action::Conflict),
+        (Some(l), None, Some(s)) => {
+            if local_changed(&local_path, l, s)? {
+                // 远程已删除但本地修改过, 重新上传而不是丢弃修改
+                warn!("{} was deleted remotely but changed locally", path);
+                Some(Action::Upload)
+            } else {
+                Some(Action::DeleteLocal)
+            }
+        }
+        (Some(_), None, None) => Some(Action::Upload),
+        (None, Some(r), Some(s)) => {
+            if remote_changed(r, s) {
+                warn!("{} was deleted locally but changed remotely", path);
+                Some(Action::Download)
+            } else {
+                Some(Action::TrashRemote)
+            }
+        }
+        (None, Some(_), None) => Some(Action::Download),
+        (None, None, Some(_)) => Some(Action::Forget),
+        (None, None, None) => None,
+    };
+    Ok(action)
+}
+
+fn plan(snapshot: &Snapshot, state: &SyncState) -> Result<Vec<Step>> {
+    let local_dirs: BTreeSet<_> = snapshot
+        .local
+        .iter()
+        .filter(|(_, x)| x.folder)
+        .map(|(k, _)| k.as_str())
+        .collect();
+    let remote_dirs: BTreeSet<_> = snapshot
+        .remote
+        .iter()
+        .filter(|(_, x)| is_folder(x))
+        .map(|(k, _)| k.as_str())
+        .collect();
+    let all: BTreeSet<&str> = snapshot
+        .local
+        .keys()
+        .chain(snapshot.remote.keys())
+        .chain(state.entries.keys())
+        .map(|x| x.as_str())
+        .collect();
+    let (dirs, files): (Vec<&str>, Vec<&str>) = all.into_iter().partition(|x| {
+        local_dirs.contains(x)
+            || remote_dirs.contains(x)
+            || (!snapshot.local.contains_key(*x)
+                && !snapshot.remote.contains_key(*x)
+                && state.entries[*x].folder)
+    });
+
+    let mut steps = vec![];
+    let mut local_after = BTreeSet::new();
+    let mut remote_after = BTreeSet::new();
+    for path in files {
+        if snapshot.remote.get(path).is_some_and(incomplete) {
+            if snapshot.local.contains_key(path) {
+                local_after.insert(path.to_string());
+            }
+            remote_after.insert(path.to_string());
+            steps.push(Step {
+                path: path.to_string(),
+                folder: false,
+                action: Action::Skip,
+            });
+            continue;
+        }
+        let action = plan_file(snapshot, path, state.entries.get(path))?;
+        let local = snapshot.local.contains_key(path);
+        let remote = snapshot.remote.contains_key(path);
+        let keep_local = match action {
+            Some(Action::Download | Action::Conflict) => true,
+            Some(Action::DeleteLocal) => false,
+            _ => local,
+        };
+        let keep_remote = match action {
+            Some(Action::Upload | Action::Conflict) => true,
+            Some(Action::TrashRemote) => false,
+            _ => remote,
+        };
+        if keep_local {
+            local_after.insert(path.to_string());
+        }
+        if keep_remote {
+            remote_after.insert(path.to_string());
+        }
+        if let Some(action) = action {
+            steps.push(Step {
+                path: path.to_string(),
+                folder: false,
+                action,
+            });
+        }
+    }
+
+    // 子目录在父目录之前处理, 父目录据此判断是否还有保留的内容
+    for path in dirs.into_iter().rev() {
+        let local = snapshot.local.contains_key(path);
+        let remote = snapshot.remote.contains_key(path);
+        // 一边是文件另一边是目录时不处理
+        if local && remote && local_dirs.contains(path) != remote_dirs.contains(path) {
+            warn!("skip {}: file on one side and folder on the other", path);
+            local_after.insert(path.to_string());
+            remote_after.insert(path.to_string());
+            continue;
+        }
+        let known = state.entries.contains_key(path);
+        let action = match (local, remote, known) {
+            (true, true, true) => None,
+            (true, true, false) => Some(Action::Record),
+            (true, false, true) if !has_children(&local_after, path) => Some(Action::DeleteLocal),
+            (true, false, _) => Some(Action::CreateRemoteDir),
+            (false, true, true) if !has_children(&remote_after, path) => Some(Action::TrashRemote),
+            (false, true, _) => Some(Action::CreateLocalDir),
+            (false, false, _) => Some(Action::Forget),
+        };
+        if !matches!(
+            action,
+            Some(Action::DeleteLocal | Action::TrashRemote | Action::Forget)
+        ) {
+            local_after.insert(path.to_string());
+            remote_after.insert(path.to_string());
+        }
+        if let Some(action) = action {
+            steps.push(Step {
+                path: path.to_string(),
+                folder: true,
+                action,
+            });
+        }
+    }
+    Ok(steps)
+}
+
+// a.txt -> a (conflict 2024-01-01 120000).txt
+fn conflict_path(path: &str, time: &str, taken: impl Fn(&str) -> bool) -> String {
+    let (dir, name) = split(path);
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, format!(".{}", ext)),
+        _ => (name, String::new()),
+    };
+    for n in 1.. {
+        let suffix = if n == 1 {
+            String::new()
+        } else {
+            format!(" {}", n)
+        };
+        let res = join(
+            dir,
+            &format!("{} (conflict {}{}){}", stem, time, suffix, ext),
+        );
+        if !taken(&res) {
+            return res;
+        }
+    }
+    unreachable!()
+}
+
+// 按同步后的本地文件和远程文件生成状态
+fn file_entry(local_path: &Path, remote: &FileType) -> Result<SyncEntry> {
+    let item = local_item(
+        &std::fs::metadata(local_path)
+            .with_context(|| format!("[sync] get metadata of {} failed", local_path.display()))?,
+    );
+    let hash = if remote.hash.is_empty() {
+        gcid_file(local_path)?
+    } else {
+        remote.hash.clone()
+    };
+    Ok(SyncEntry {
+        folder: false,
+        remote_id: remote.id.clone(),
+        size: item.size,
+        mtime: item.mtime,
+        remote_modified: remote.modified_time.clone(),
+        hash,
+    })
+}
+
+// 服务端自动重命名的副本: a(1).txt 或 a (1).txt -> a.txt
+fn renamed_from(name: &str) -> Option<String> {
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, format!(".{}", ext)),
+        _ => (name, String::new()),
+    };
+    let (base, n) = stem.strip_suffix(')')?.rsplit_once('(')?;
+    if n.is_empty() || !n.bytes().all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+    let base = base.strip_suffix(' ').unwrap_or(base);
+    if base.is_empty() {
+        return None;
+    }
+    Some(format!("{}{}", base, ext))
+}
+
+// 上次替换远程文件时中断: 新版本已上传为副本, 旧版本还没移到回收站或副本还没改名
+// 副本没有记录在状态里, 原名的文件还是记录的版本或已不存在, 且副本和本地文件内容一致
+fn interrupted_replaces(snapshot: &Snapshot, state: &SyncState) -> Result<Vec<(String, String)>> {
+    let mut res = vec![];
+    for (path, status) in &snapshot.remote {
+        if is_folder(status) || incomplete(status) || state.entries.contains_key(path) {
+            continue;
+        }
+        let (dir, name) = split(path);
+        let Some(original) = renamed_from(name).map(|x| join(dir, &x)) else {
+            continue;
+        };
+        let (Some(entry), Some(local)) =
+            (state.entries.get(&original), snapshot.local.get(&original))
+        else {
+            continue;
+        };
+        // 旧版本已经移走但还没改名时也继续
+        if entry.folder
+            || snapshot
+                .remote
+                .get(&original)
+                .is_some_and(|x| is_folder(x) || x.id != entry.remote_id)
+        {
+            continue;
+        }
+        if same_content(&snapshot.root.join(&original), local, status)? {
+            res.push((path.clone(), original));
+        }
+    }
+    Ok(res)
+}
+
+fn status_file(status: &FileStatus) -> FileType {
+    FileType {
+        id: status.id.clone(),
+        modified_time: status.modified_time.clone(),
+        hash: status.hash.clone(),
+        ..Default::default()
+    }
+}
+
+impl Client {
+    pub async fn sync(mut self, local: PathBuf, remote: String, dry_run: bool) -> Result<()> {
+        let summary = self.sync_folder(&local, &remote, dry_run).await?;
+        if dry_run {
+            return Ok(());
+        }
+        println!(
+            "downloaded: {}, uploaded: {}, deleted local: {}, trashed remote: {}, conflicts: {}, skipped: {}, failed: {}",
+            summary.downloaded,
+            summary.uploaded,
+            summary.deleted,
+            summary.trashed,
+            summary.conflicts,
+            summary.skipped,
+            summary.failed
+        );
+        if summary.failed > 0 {
+            return Err(anyhow::anyhow!(
+                "[sync] {} changes failed, run sync again to retry",
+                summary.failed
+            ));
+        }
+        Ok(())
+    }
+
+    #[async_recursion(?Send)]
+    pub(super) async fn scan_remote(
+        &mut self,
+        folder_id: &str,
+        rel: &str,
+        items: &mut BTreeMap<String, FileStatus>,
+    ) -> Result<()> {
+        for status in self.get_file_status_list_by_folder_id(folder_id).await? {
+            let path = join(rel, &status.name);
+            if is_folder(&status) {
+                self.scan_remote(&status.id.clone(), &path, items).await?;
+            }
+            if items.insert(path.clone(), status).is_some() {
+                warn!("duplicate remote name, only one is synced: {}", path);
+            }
+        }
+        Ok(())
+    }
+
+    async fn sync_folder(
+        &mut self,
+        local: &Path,
+        remote: &str,
+        dry_run: bool,
+    ) -> Result<SyncSummary> {
+        let remote = format!("/{}", slash(remote)?);
+        let root_id = match self.get_path_id(&remote).await? {
+            FileIDType::Folder(id) => id,
+            FileIDType::File(_) => {
+                return Err(anyhow::anyhow!("[sync] {} is not a folder", remote));
+            }
+        };
+        std::fs::create_dir_all(local)
+            .with_context(|| format!("[sync] create {} failed", local.display()))?;
+        let state_path = local.join(STATE_FILE);
+        let mut state = SyncState::load(&state_path)?;
+        if !state.remote.is_empty() && state.remote != remote {
+            return Err(anyhow::anyhow!(
+                "[sync] {} is synced with {}, not {}",
+                local.display(),
+                state.remote,
+                remote
+            ));
+        }
+        state.remote = remote;
+
+        // 任何一边列出失败都直接退出, 避免把没列出的文件当作已删除
+        let mut snapshot = Snapshot {
+            root: local.to_path_buf(),
+            local: BTreeMap::new(),
+            remote: BTreeMap::new(),
+        };
+        scan_local(local, "", &mut snapshot.local)?;
+        snapshot.local.retain(|path, item| {
+            if item.symlink {
+                warn!("skip symlink: {}", local.join(path).display());
+            }
+            !item.symlink
+        });
+        self.scan_remote(&root_id, "", &mut snapshot.remote).await?;
+        for (copy, original) in interrupted_replaces(&snapshot, &state)? {
+            if dry_run {
+                println!("finish replace {} -> {}", copy, original);
+                continue;
+            }
+            // 失败时直接退出, 否则副本会被当作新文件下载
+            self.finish_replace(&mut snapshot, &copy, &original)
+                .await
+                .with_context(|| format!("[sync] finish replace of {} failed", original))?;
+        }
+        let steps = plan(&snapshot, &state)?;
+
+        if dry_run {
+            for step in &steps {
+                if !matches!(step.action, Action::Record | Action::Forget) {
+                    println!("{:?} {}", step.action, step.path);
+                }
+            }
+            return Ok(SyncSummary::default());
+        }
+
+        let mut ids: HashMap<String, String> = snapshot
+            .remote
+            .iter()
+            .filter(|(_, x)| is_folder(x))
+            .map(|(k, v)| (k.clone(), v.id.clone()))
+            .collect();
+        ids.insert(String::new(), root_id);
+
+        let mut summary = SyncSummary::default();
+        // 目录按从深到浅的顺序计划, 先从浅到深创建目录, 再处理文件, 最后从深到浅删除目录
+        let creates = steps.iter().rev().filter(|x| {
+            matches!(
+                x.action,
+                Action::CreateLocalDir | Action::CreateRemoteDir | Action::Record
+            ) && x.folder
+        });
+        let files = steps.iter().filter(|x| !x.folder);
+        let deletes = steps.iter().filter(|x| {
+            x.folder
+                && matches!(
+                    x.action,
+                    Action::DeleteLocal | Action::TrashRemote | Action::Forget
+                )
+        });
+        let ordered: Vec<_> = creates.chain(files).chain(deletes).collect();
+        for step in ordered {
+            debug!("sync step: {:?}", step);
+            if let Err(err) = self
+                .apply(&snapshot, &mut state, &mut ids, &mut summary, step)
+                .await
+            {
+                error!("{:?} {} failed, err: {:#}", step.action, step.path, err);
+                summary.failed += 1;
+            }
+            // 每次改动两边后都保存状态, 中断后下次同步不会把已完成的改动当作新的改动
+            if !matches!(step.action, Action::Record | Action::Forget | Action::Skip) {
+                state.save(&state_path)?;
+            }
+        }
+
+        state.save(&state_path)?;
+        Ok(summary)
+    }
+
+    // 把旧版本移到回收站, 副本改回原来的名字, 之后按内容一致记录状态
+    async fn finish_replace(
+        &mut self,
+        snapshot: &mut Snapshot,
+        copy: &str,
+        original: &str,
+    ) -> Result<()> {
+        warn!("finish interrupted replace: {} -> {}", copy, original);
+        if let Some(old) = snapshot.remote.remove(original) {
+            self.trash(std::slice::from_ref(&old.id)).await?;
+        }
+        let mut status = snapshot
+            .remote
+            .remove(copy)
+            .context("[sync] copy not found")?;
+        let file = self.rename_file(&status.id, split(original).1).await?;
+        status.name = file.name;
+        snapshot.remote.insert(original.to_string(), status);
+        Ok(())
+    }
+
+    async fn apply(
+        &mut self,
+        snapshot: &Snapshot,
+        state: &mut SyncState,
+        ids: &mut HashMap<String, String>,
+        summary: &mut SyncSummary,
+        step: &Step,
+    ) -> Result<()> {
+        let path = step.path.as_str();
+        let local_path = snapshot.root.join(path);
+        let remote = snapshot.remote.get(path);
+        let (parent, name) = split(path);
+        let parent_id = || {
+            ids.get(parent)
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("[sync] remote folder of {} not found", path))
+        };
+
+        match (step.action, step.folder) {
+            (Action::Record, true) | (Action::CreateLocalDir, _) => {
+                let remote = remote.context("[sync] remote folder not found")?;
+                std::fs::create_dir_all(&local_path)
+                    .with_context(|| format!("[sync] create {} failed", local_path.display()))?;
+                state.entries.insert(
+                    path.to_string(),
+                    SyncEntry {
+                        folder: true,
+                        remote_id: remote.id.clone(),
+                        ..Default::default()
+                    },
+                );
+            }
+            (Action::CreateRemoteDir, _) => {
+                let folder = self.create_folder(&parent_id()?, name).await?;
+                info!("create remote folder: {}", path);
+                ids.insert(path.to_string(), folder.id.clone());
+                state.entries.insert(
+                    path.to_string(),
+                    SyncEntry {
+                        folder: true,
+                        remote_id: folder.id,
+                        ..Default::default()
+                    },
+                );
+            }
+            (Action::Record, false) => {
+                let remote = remote.context("[sync] remote file not found")?;
+                let entry = file_entry(&local_path, &status_file(remote))?;
+                state.entries.insert(path.to_string(), entry);
+            }
+            (Action::Download, _) => {
+                let remote = remote.context("[sync] remote file not found")?;
+                self.download_to(&local_path, &remote.id).await?;
+                let entry = file_entry(&local_path, &status_file(remote))?;
+                state.entries.insert(path.to_string(), entry);
+                summary.downloaded += 1;
+            }
+            (Action::Upload, _) => {
+                let file = self.upload_to(&parent_id()?, name, &local_path).await?;
+                let file = match remote {
+                    Some(old) => self.replace_remote(old, file, name).await?,
+                    None => file,
+                };
+                state
+                    .entries
+                    .insert(path.to_string(), file_entry(&local_path, &file)?);
+                summary.uploaded += 1;
+            }
+            (Action::Conflict, _) => {
+                let remote = remote.context("[sync] remote file not found")?;
+                let time = Local::now().format("%Y-%m-%d %H%M%S").to_string();
+                let copy = conflict_path(path, &time, |x| {
+                    snapshot.root.join(x).exists() || snapshot.remote.contains_key(x)
+                });
+                let copy_path = snapshot.root.join(&copy);
+                warn!("conflict: {}, local version kept as {}", path, copy);
+                std::fs::rename(&local_path, &copy_path)
+                    .with_context(|| format!("[sync] rename {} failed", local_path.display()))?;
+                self.download_to(&local_path, &remote.id).await?;
+                let entry = file_entry(&local_path, &status_file(remote))?;
+                state.entries.insert(path.to_string(), entry);
+                let file = self
+                    .upload_to(&parent_id()?, split(&copy).1, &copy_path)
+                    .await?;
+                if file.name != split(&copy).1 {
+                    warn!("{} was uploaded as {}", copy, file.name);
+                }
+                state.entries.insert(copy, file_entry(&copy_path, &file)?);
+                summary.conflicts += 1;
+            }
+            (Action::DeleteLocal, folder) => {
+                info!("delete local: {}", path);
+                let res = if folder {
+                    std::fs::remove_dir(&local_path)
+                } else {
+                    std::fs::remove_file(&local_path)
+                };
+                match res {
+                    Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                        return Err(err).with_context(|| {
+                            format!("[sync] remove {} failed", local_path.display())
+                        });
+                    }
+                    _ => {}
+                }
+                state.entries.remove(path);
+                summary.deleted += 1;
+            }
+            (Action::TrashRemote, _) => {
+                let remote = remote.context("[sync] remote file not found")?;
+                info!("trash remote: {}", path);
+                self.trash(std::slice::from_ref(&remote.id)).await?;
+                state.entries.remove(path);
+                summary.trashed += 1;
+            }
+            (Action::Forget, _) => {
+                state.entries.remove(path);
+            }
+            (Action::Skip, _) => {
+                info!("skip {}: remote file is not complete", path);
+                summary.skipped += 1;
+            }
+        }
+        Ok(())
+    }
+
+    // 先下载到临时文件, 校验后替换本地文件
+    pub(super) async fn download_to(&mut self, local_path: &Path, remote_id: &str) -> Result<()> {
+        let file = self.get_file_by_id(remote_id.to_string()).await?;
+        info!("download: {}", local_path.display());
+        download_file(
+            &file,
+            local_path.to_path_buf(),
+            self.retry_times,
+            self.retry_interval,
+            true,
+        )
+        .await?;
+        apply_metadata(&file, local_path, false);
+        Ok(())
+    }
+
+    async fn upload_to(
+        &mut self,
+        parent_id: &str,
+        name: &str,
+        local_path: &Path,
+    ) -> Result<FileType> {
+        info!("upload: {}", local_path.display());
+        let file = self.upload_file(parent_id, name, local_path).await?;
+        if file.phase != "PHASE_TYPE_COMPLETE" {
+            return Err(anyhow::anyhow!(
+                "[sync] upload of {} not complete, phase: {}",
+                name,
+                file.phase
+            ));
+        }
+        Ok(file)
+    }
+
+    // 新版本上传完成后再把旧版本移到回收站, 上传失败时远程仍保留旧版本
+    // 同名时服务端会自动重命名新版本, 旧版本移走后改回原来的名字
+    pub(super) async fn replace_remote(
+        &mut self,
+        old: &FileStatus,
+        file: FileType,
+        name: &str,
+    ) -> Result<FileType> {
+        if let Err(err) = self.trash(std::slice::from_ref(&old.id)).await {
+            // 两个版本同时存在时下次同步会误判, 撤销这次上传
+            if let Err(err) = self.trash(std::slice::from_ref(&file.id)).await {
+                warn!("trash uploaded {} failed, err: {:#}", file.name, err);
+            }
+            return Err(err.context(format!("[sync] trash old version of {} failed", name)));
+        }
+        if file.name == name {
+            return Ok(file);
+        }
+        self.rename_file(&file.id, name).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pikpak::mock::{MockFile, MockServer};
+
+    #[test]
+    fn test_conflict_path() {
+        let time = "2024-01-01 120000";
+        assert_eq!(
+            conflict_path("dir/a.txt", time, |_| false),
+            "dir/a (conflict 2024-01-01 120000).txt"
+        );
+        assert_eq!(
+            conflict_path("Makefile", time, |x| x
+                == "Makefile (conflict 2024-01-01 120000)"),
+            "Makefile (conflict 2024-01-01 120000 2)"
+        );
+    }
+
+    #[test]
+    fn test_renamed_from() {
+        assert_eq!(renamed_from("a(1).txt").as_deref(), Some("a.txt"));
+        assert_eq!(renamed_from("a (12).txt").as_deref(), Some("a.txt"));
+        assert_eq!(renamed_from("Makefile(2)").as_deref(), Some("Makefile"));
+        assert_eq!(renamed_from("a(x).txt"), None);
+        assert_eq!(renamed_from("(1).txt"), None);
+        assert_eq!(renamed_from("a.txt"), None);
+    }
+
+    #[tokio::test]
+    async fn test_sync_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        let mut client = server.client();
+        client.login().await?;
+        let dir = tempfile::tempdir()?;
+        let local = dir.path().join("pack");
+
+        let summary = client.sync_folder(&local, "/My Pack", false).await?;
+        assert_eq!(summary.downloaded, 2);
+        assert_eq!(std::fs::read(local.join("a.txt"))?, b"hello world");
+        assert!(local.join("sub/b.bin").exists());
+
+        // 本地: 新增 new.txt, 修改 a.txt, 删除 sub
+        // 远程: 新增 remote.txt, 修改 a.txt
+        std::fs::write(local.join("new.txt"), b"new")?;
+        std::fs::write(local.join("a.txt"), b"local edit")?;
+        std::fs::remove_dir_all(local.join("sub"))?;
+        {
+            let mut state = server.state();
+            state.files.push(MockFile::file(
+                "file-remote",
+                "folder-my-pack",
+                "remote.txt",
+                b"remote",
+            ));
+            let a = state.files.iter_mut().find(|x| x.id == "file-a").unwrap();
+            a.content = b"remote edit".to_vec();
+            a.modified_time = "2024-02-01T00:00:00.000+08:00".into();
+        }
+
+        let dry_run = client.sync_folder(&local, "/My Pack", true).await?;
+        assert_eq!(dry_run, SyncSummary::default());
+        assert!(!local.join("remote.txt").exists());
+
+        let summary = client.sync_folder(&local, "/My Pack", false).await?;
+        assert_eq!(
+            summary,
+            SyncSummary {
+                downloaded: 1,
+                uploaded: 1,
+                trashed: 2,
+                conflicts: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(std::fs::read(local.join("a.txt"))?, b"remote edit");
+        assert_eq!(std::fs::read(local.join("remote.txt"))?, b"remote");
+        let copy = std::fs::read_dir(&local)?
+            .filter_map(|x| x.ok())
+            .map(|x| x.file_name().to_string_lossy().to_string())
+            .find(|x| x.starts_with("a (conflict "))
+            .unwrap();
+        assert_eq!(std::fs::read(local.join(&copy))?, b"local edit");
+        {
+            let state = server.state();
+            let names: BTreeSet<_> = state
+                .files
+                .iter()
+                .filter(|x| x.parent_id == "folder-my-pack")
+                .map(|x| x.name.clone())
+                .collect();
+            assert!(names.contains(&copy));
+            assert!(names.contains("new.txt"));
+            assert!(!names.contains("sub"));
+            assert!(state.trashed.contains(&"folder-sub".to_string()));
+        }
+
+        // 两边一致后没有任何改动
+        let summary = client.sync_folder(&local, "/My Pack", false).await?;
+        assert_eq!(summary, SyncSummary::default());
+
+        // 本地修改后先上传新版本, 再移走旧版本并改回原来的名字
+        let old_id = server
+            .state()
+            .files
+            .iter()
+            .find(|x| x.name == "new.txt")
+            .unwrap()
+            .id
+            .clone();
+        std::fs::write(local.join("new.txt"), b"new version")?;
+        let summary = client.sync_folder(&local, "/My Pack", false).await?;
+        assert_eq!(summary.uploaded, 1);
+        {
+            let state = server.state();
+            let new: Vec<_> = state
+                .files
+                .iter()
+                .filter(|x| x.name.starts_with("new"))
+                .collect();
+            assert_eq!(new.len(), 1);
+            assert_eq!(new[0].name, "new.txt");
+            assert_eq!(new[0].content, b"new version");
+            assert!(state.trashed.contains(&old_id));
+        }
+        let summary = client.sync_folder(&local, "/My Pack", false).await?;
+        assert_eq!(summary, SyncSummary::default());
+
+        // 上次替换时中断: 新版本已上传为副本, 旧版本还在, 这次完成替换
+        let old_id = server
+            .state()
+            .files
+            .iter()
+            .find(|x| x.name == "new.txt")
+            .unwrap()
+            .id
+            .clone();
+        std::fs::write(local.join("new.txt"), b"interrupted")?;
+        server.state().files.push(MockFile::file(
+            "file-copy",
+            "folder-my-pack",
+            "new(1).txt",
+            b"interrupted",
+        ));
+        let summary = client.sync_folder(&local, "/My Pack", false).await?;
+        assert_eq!(summary, SyncSummary::default());
+        {
+            let state = server.state();
+            let new: Vec<_> = state
+                .files
+                .iter()
+                .filter(|x| x.name.starts_with("new"))
+                .collect();
+            assert_eq!(new.len(), 1);
+            assert_eq!(new[0].id, "file-copy");
+            assert_eq!(new[0].name, "new.txt");
+            assert!(state.trashed.contains(&old_id));
+        }
+        assert_eq!(std::fs::read(local.join("new.txt"))?, b"interrupted");
+        let summary = client.sync_folder(&local, "/My Pack", false).await?;
+        assert_eq!(summary, SyncSummary::default());
+
+        // 上传中的远程文件既不下载也不当作已删除
+        server
+            .state()
+            .files
+            .iter_mut()
+            .find(|x| x.name == "new.txt")
+            .unwrap()
+            .phase = "PHASE_TYPE_PENDING".into();
+        let summary = client.sync_folder(&local, "/My Pack", false).await?;
+        assert_eq!(
+            summary,
+            SyncSummary {
+                skipped: 1,
+                ..Default::default()
+            }
+        );
+        assert!(local.join("new.txt").exists());
+        for file in server.state().files.iter_mut() {
+            file.phase = "PHASE_TYPE_COMPLETE".into();
+        }
+
+        // 远程删除未修改的文件时删除本地文件
+        server.state().files.retain(|x| x.name != "new.txt");
+        let summary = client.sync_folder(&local, "/My Pack", false).await?;
+        assert_eq!(summary.deleted, 1);
+        assert!(!local.join("new.txt").exists());
+
+        assert!(client
+            .sync_folder(&local, "/readme.md", false)
+            .await
+            .is_err());
+        Ok(())
+    }
+}
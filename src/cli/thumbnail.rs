@@ -0,0 +1,257 @@
+// 保存文件的缩略图, 默认放在文件旁边 (name-thumb.jpg, 媒体服务器可以识别)
+// 指定目录时按远程目录结构保存到单独的目录 (name.ext.jpg)
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::*;
+
+use crate::cli::download::{DownloadOptions, RemoteFile};
+use crate::pikpak::download::get_download_client;
+use crate::pikpak::{Client, USER_AGENT};
+
+const EXTENSIONS: &[&str] = &["jpg", "png", "webp", "gif"];
+
+#[derive(Debug, Clone, Default)]
+pub struct ThumbnailOptions {
+    // 下载目录, 用于计算在缩略图目录中的相对路径
+    pub output: PathBuf,
+    pub dir: Option<PathBuf>,
+}
+
+impl ThumbnailOptions {
+    // 不带扩展名的缩略图路径, 扩展名由图片格式决定
+    fn base_path(&self, local_path: &Path) -> PathBuf {
+        let name = local_path.file_name().unwrap_or_default();
+        let Some(dir) = &self.dir else {
+            let stem = local_path.file_stem().unwrap_or(name);
+            let mut base = OsString::from(stem);
+            base.push("-thumb");
+            return local_path.with_file_name(base);
+        };
+        let rel = local_path.strip_prefix(&self.output).unwrap_or(local_path);
+        let rel = rel.strip_prefix("/").unwrap_or(rel);
+        dir.join(rel)
+    }
+}
+
+fn with_extension(base: &Path, ext: &str) -> PathBuf {
+    let mut path = base.as_os_str().to_owned();
+    path.push(".");
+    path.push(ext);
+    PathBuf::from(path)
+}
+
+fn extension(content_type: &str) -> &'static str {
+    match content_type.split(';').next().unwrap_or_default().trim() {
+        "image/png" => "png",
+        "image/webp" => "webp",
+        "image/gif" => "gif",
+        _ => "jpg",
+    }
+}
+
+// 返回保存的路径, 已经存在时不重复下载
+pub async fn save_thumbnail(
+    url: &str,
+    local_path: &Path,
+    opts: &ThumbnailOptions,
+) -> Result<Option<PathBuf>> {
+    if url.is_empty() {
+        return Ok(None);
+    }
+    let base = opts.base_path(local_path);
+    if let Some(path) = EXTENSIONS
+        .iter()
+        .map(|x| with_extension(&base, x))
+        .find(|x| x.exists())
+    {
+        debug!("thumbnail exists: {}", path.display());
+        return Ok(Some(path));
+    }
+
+    let resp = get_download_client()
+        .get(url)
+        .header("User-Agent", USER_AGENT)
+        .send()
+        .await
+        .context("[save_thumbnail] request failed")?;
+    if !resp.status().is_success() {
+        return Err(anyhow::anyhow!(
+            "[save_thumbnail] unexpected status: {}",
+            resp.status()
+        ));
+    }
+    let ext = extension(
+        resp.headers()
+            .get("Content-Type")
+            .and_then(|x| x.to_str().ok())
+            .unwrap_or_default(),
+    );
+    let data = resp.bytes().await.context("[save_thumbnail] read failed")?;
+
+    let path = with_extension(&base, ext);
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .with_context(|| format!("[save_thumbnail] create {} failed", parent.display()))?;
+    }
+    tokio::fs::write(&path, data)
+        .await
+        .with_context(|| format!("[save_thumbnail] write {} failed", path.display()))?;
+    Ok(Some(path))
+}
+
+impl Client {
+    pub async fn thumbnails(
+        mut self,
+        paths: Vec<String>,
+        opts: DownloadOptions,
+        thumbnail_opts: ThumbnailOptions,
+    ) -> Result<()> {
+        let (saved, skipped, failed) = self.save_thumbnails(paths, &opts, &thumbnail_opts).await?;
+        println!(
+            "saved: {}, without thumbnail: {}, failed: {}",
+            saved, skipped, failed
+        );
+        if failed > 0 {
+            return Err(anyhow::anyhow!(
+                "[thumbnails] {} thumbnails failed to save",
+                failed
+            ));
+        }
+        Ok(())
+    }
+
+    // 返回保存的数量, 没有缩略图的文件数量和失败的数量
+    async fn save_thumbnails(
+        &mut self,
+        paths: Vec<String>,
+        opts: &DownloadOptions,
+        thumbnail_opts: &ThumbnailOptions,
+    ) -> Result<(usize, usize, usize)> {
+        let (files, failures) = self.build_files(paths, opts).await?;
+        for item in failures {
+            error!("list {} failed: {}", item.remote_path, item.reason);
+        }
+        let (mut saved, mut skipped, mut failed) = (0, 0, 0);
+        for RemoteFile {
+            entry,
+            status,
+            listed,
+        } in files
+        {
+            let res = async {
+                // 直接指定的文件没有列目录得到的信息
+                let url = match listed {
+                    true => status.thumbnail_link,
+                    false => {
+                        self.get_file_by_id(entry.remote_id.clone())
+                            .await?
+                            .thumbnail_link
+                    }
+                };
+                save_thumbnail(&url, &entry.local_path, thumbnail_opts).await
+            }
+            .await;
+            match res {
+                Result::Ok(Some(path)) => {
+                    info!("thumbnail of {}: {}", entry.remote_path, path.display());
+                    saved += 1;
+                }
+                Result::Ok(None) => skipped += 1,
+                Err(err) => {
+                    error!(
+                        "save thumbnail of {} failed, err: {:#}",
+                        entry.remote_path, err
+                    );
+                    failed += 1;
+                }
+            }
+        }
+        Ok((saved, skipped, failed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pikpak::file::ThumbnailSize;
+    use crate::pikpak::mock::MockServer;
+
+    #[test]
+    fn test_base_path() {
+        let next_to = ThumbnailOptions {
+            output: "/data".into(),
+            dir: None,
+        };
+        assert_eq!(
+            next_to.base_path(Path::new("/data/Shows/ep1.mkv")),
+            Path::new("/data/Shows/ep1-thumb")
+        );
+        let tree = ThumbnailOptions {
+            output: "/data".into(),
+            dir: Some("/thumbs".into()),
+        };
+        assert_eq!(
+            tree.base_path(Path::new("/data/Shows/ep1.mkv")),
+            Path::new("/thumbs/Shows/ep1.mkv")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_thumbnails_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        for file in server.state().files.iter_mut() {
+            file.thumbnail = file.id == "file-b" || file.id == "file-readme";
+        }
+        let mut client = server.client();
+        client.login().await?;
+        client.thumbnail_size = ThumbnailSize::Small;
+
+        let dir = tempfile::tempdir()?;
+        let opts = DownloadOptions {
+            output: dir.path().to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let thumbnail_opts = ThumbnailOptions {
+            output: dir.path().into(),
+            dir: None,
+        };
+        let res = client
+            .save_thumbnails(vec!["/My Pack".into()], &opts, &thumbnail_opts)
+            .await?;
+        assert_eq!(res, (1, 1, 0));
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("My Pack/sub/b-thumb.jpg"))?,
+            "thumbnail of file-b in SIZE_SMALL"
+        );
+
+        let thumbnail_opts = ThumbnailOptions {
+            output: dir.path().into(),
+            dir: Some(dir.path().join("thumbs")),
+        };
+        let res = client
+            .save_thumbnails(vec!["/readme.md".into()], &opts, &thumbnail_opts)
+            .await?;
+        assert_eq!(res, (1, 0, 0));
+        assert!(dir.path().join("thumbs/readme.md.jpg").exists());
+
+        // 缩略图目录不可写时返回错误
+        let blocked = dir.path().join("blocked");
+        std::fs::write(&blocked, b"")?;
+        let thumbnail_opts = ThumbnailOptions {
+            output: dir.path().into(),
+            dir: Some(blocked),
+        };
+        let res = client
+            .save_thumbnails(vec!["/readme.md".into()], &opts, &thumbnail_opts)
+            .await?;
+        assert_eq!(res, (0, 0, 1));
+        assert!(client
+            .thumbnails(vec!["/readme.md".into()], opts, thumbnail_opts)
+            .await
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,531 @@
+// WebDAV 服务, 基于 http 网关的目录缓存和链接刷新
+// 支持上传, 新建目录, 删除和同一目录内的重命名, 其他写操作返回 405
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use axum::body::Body;
+use axum::extract::{BodyStream, State};
+use axum::http::{header, HeaderMap, HeaderValue, Method, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::Router;
+use base64::Engine;
+use futures::StreamExt;
+use log::*;
+use sha1::{Digest, Sha1};
+use tokio::io::AsyncWriteExt;
+
+use crate::cli::gateway::{decode_path, encode_path, html_escape, http_date, is_folder, Gateway};
+use crate::pikpak::file::FileStatus;
+use crate::pikpak::Client;
+
+const ALLOW: &str = "OPTIONS, GET, HEAD, PROPFIND, PUT, MKCOL, MOVE, DELETE";
+
+// 上传的内容先写入临时文件, 计算 hash 后再上传
+static UPLOADS: AtomicU64 = AtomicU64::new(0);
+
+fn temp_path() -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "pikpakcli-webdav-{}-{}",
+        std::process::id(),
+        UPLOADS.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+// 拆分为父目录和文件名, root 没有文件名
+fn split_path(path: &str) -> Option<(&str, &str)> {
+    let path = path.trim_matches('/');
+    let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+    (!name.is_empty()).then_some((parent, name))
+}
+
+fn status(code: StatusCode) -> Result<Response> {
+    Ok(code.into_response())
+}
+
+// 先取摘要使长度一致, 再逐字节比较全部内容, 耗时与不同的位置无关
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let (a, b) = (Sha1::digest(a), Sha1::digest(b));
+    a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[derive(Debug, Clone)]
+pub struct BasicAuth {
+    pub username: String,
+    pub password: String,
+}
+
+impl BasicAuth {
+    fn check(&self, headers: &HeaderMap) -> bool {
+        let Some(value) = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.strip_prefix("Basic "))
+        else {
+            return false;
+        };
+        let Ok(decoded) = base64::engine::general_purpose::STANDARD.decode(value.trim()) else {
+            return false;
+        };
+        let expected = format!("{}:{}", self.username, self.password);
+        constant_time_eq(&decoded, expected.as_bytes())
+    }
+}
+
+struct WebDav {
+    gateway: Gateway,
+    auth: Option<BasicAuth>,
+}
+
+fn prop_response(href: &str, file: &FileStatus) -> String {
+    let mut props = format!("<D:displayname>{}</D:displayname>", html_escape(&file.name));
+    if is_folder(file) {
+        props += "<D:resourcetype><D:collection/></D:resourcetype>";
+    } else {
+        props += "<D:resourcetype/>";
+        props += &format!(
+            "<D:getcontentlength>{}</D:getcontentlength>",
+            file.size.parse::<u64>().unwrap_or_default()
+        );
+        if !file.mime_type.is_empty() {
+            props += &format!(
+                "<D:getcontenttype>{}</D:getcontenttype>",
+                html_escape(&file.mime_type)
+            );
+        }
+        let etag = if file.hash.is_empty() {
+            &file.id
+        } else {
+            &file.hash
+        };
+        props += &format!("<D:getetag>\"{}\"</D:getetag>", html_escape(etag));
+    }
+    if let Some(time) = http_date(&file.modified_time) {
+        props += &format!("<D:getlastmodified>{}</D:getlastmodified>", time);
+    }
+    if !file.created_time.is_empty() {
+        props += &format!(
+            "<D:creationdate>{}</D:creationdate>",
+            html_escape(&file.created_time)
+        );
+    }
+    format!(
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>{}</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n",
+        html_escape(href),
+        props
+    )
+}
+
+impl WebDav {
+    async fn propfind(&self, path: &str, headers: &HeaderMap) -> Result<Response> {
+        let Some(file) = self.gateway.resolve(path).await? else {
+            return Ok(StatusCode::NOT_FOUND.into_response());
+        };
+        let base = format!("/{}", path.trim_matches('/'));
+        let href = |base: &str, folder: bool| {
+            let href = encode_path(base);
+            if folder && !href.ends_with('/') {
+                href + "/"
+            } else {
+                href
+            }
+        };
+
+        let mut body = String::from(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n",
+        );
+        body += &prop_response(&href(&base, is_folder(&file)), &file);
+        // Depth: infinity 按 1 处理, 避免遍历整个网盘
+        let depth = headers.get("Depth").and_then(|x| x.to_str().ok());
+        if is_folder(&file) && depth != Some("0") {
+            for child in self.gateway.list(&file.id).await?.iter() {
+                let child_path = format!("{}/{}", base.trim_end_matches('/'), child.name);
+                body += &prop_response(&href(&child_path, is_folder(child)), child);
+            }
+        }
+        body += "</D:multistatus>\n";
+
+        Ok((
+            StatusCode::MULTI_STATUS,
+            [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+            body,
+        )
+            .into_response())
+    }
+
+    async fn folder(&self, path: &str) -> Result<Option<FileStatus>> {
+        Ok(self.gateway.resolve(path).await?.filter(is_folder))
+    }
+
+    async fn mkcol(&self, path: &str) -> Result<Response> {
+        let Some((parent, name)) = split_path(path) else {
+            return status(StatusCode::METHOD_NOT_ALLOWED);
+        };
+        if self.gateway.resolve(path).await?.is_some() {
+            return status(StatusCode::METHOD_NOT_ALLOWED);
+        }
+        let Some(parent) = self.folder(parent).await? else {
+            return status(StatusCode::CONFLICT);
+        };
+        self.gateway
+            .client()
+            .await
+            .create_folder(&parent.id, name)
+            .await?;
+        self.gateway.invalidate(&parent.id);
+        status(StatusCode::CREATED)
+    }
+
+    async fn delete(&self, path: &str) -> Result<Response> {
+        if split_path(path).is_none() {
+            return status(StatusCode::FORBIDDEN);
+        }
+        let Some(file) = self.gateway.resolve(path).await? else {
+            return status(StatusCode::NOT_FOUND);
+        };
+        self.gateway
+            .client()
+            .await
+            .trash(std::slice::from_ref(&file.id))
+            .await?;
+        self.gateway.invalidate(&file.parent_id);
+        self.gateway.invalidate(&file.id);
+        status(StatusCode::NO_CONTENT)
+    }
+
+    // 客户端只有重命名的接口, 不能移动到其他目录
+    async fn r#move(&self, path: &str, headers: &HeaderMap) -> Result<Response> {
+        let Some(dest) = headers
+            .get("Destination")
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.parse::<Uri>().ok())
+        else {
+            return status(StatusCode::BAD_REQUEST);
+        };
+        let dest = decode_path(&dest);
+        let (Some((parent, _)), Some((dest_parent, name))) = (split_path(path), split_path(&dest))
+        else {
+            return status(StatusCode::FORBIDDEN);
+        };
+        if parent != dest_parent {
+            return Ok((
+                StatusCode::FORBIDDEN,
+                "only renaming within the same folder is supported",
+            )
+                .into_response());
+        }
+        let Some(file) = self.gateway.resolve(path).await? else {
+            return status(StatusCode::NOT_FOUND);
+        };
+        let existing = self.gateway.resolve(&dest).await?;
+        if let Some(existing) = &existing {
+            if existing.id == file.id {
+                return status(StatusCode::NO_CONTENT);
+            }
+            if headers.get("Overwrite").is_some_and(|x| x == "F") {
+                return status(StatusCode::PRECONDITION_FAILED);
+            }
+        }
+        {
+            let mut client = self.gateway.client().await;
+            if let Some(existing) = &existing {
+                client.trash(std::slice::from_ref(&existing.id)).await?;
+            }
+            client.rename_file(&file.id, name).await?;
+        }
+        self.gateway.invalidate(&file.parent_id);
+        match existing {
+            Some(_) => status(StatusCode::NO_CONTENT),
+            None => status(StatusCode::CREATED),
+        }
+    }
+
+    // 已有同名文件时上传完成后替换
+    async fn put(&self, path: &str, mut body: BodyStream) -> Result<Response> {
+        let Some((parent, name)) = split_path(path) else {
+            return status(StatusCode::METHOD_NOT_ALLOWED);
+        };
+        let Some(parent) = self.folder(parent).await? else {
+            return status(StatusCode::CONFLICT);
+        };
+        let existing = self.gateway.resolve(path).await?;
+        if existing.as_ref().is_some_and(is_folder) {
+            return status(StatusCode::METHOD_NOT_ALLOWED);
+        }
+
+        let tmp = temp_path();
+        let res = async {
+            let mut file = tokio::fs::File::create(&tmp)
+                .await
+                .with_context(|| format!("[webdav] create {} failed", tmp.display()))?;
+            while let Some(chunk) = body.next().await {
+                file.write_all(&chunk.context("[webdav] read body failed")?)
+                    .await
+                    .context("[webdav] write temp file failed")?;
+            }
+            file.flush().await?;
+            let mut client = self.gateway.client().await;
+            let uploaded = client.upload_file(&parent.id, name, &tmp).await?;
+            if let Some(old) = &existing {
+                client.replace_remote(old, uploaded, name).await?;
+            }
+            Ok::<_, anyhow::Error>(())
+        }
+        .await;
+        if let Err(err) = tokio::fs::remove_file(&tmp).await {
+            warn!("remove {} failed, err: {}", tmp.display(), err);
+        }
+        res?;
+        self.gateway.invalidate(&parent.id);
+        match existing {
+            Some(_) => status(StatusCode::NO_CONTENT),
+            None => status(StatusCode::CREATED),
+        }
+    }
+
+    async fn handle(
+        &self,
+        method: Method,
+        uri: Uri,
+        headers: HeaderMap,
+        body: BodyStream,
+    ) -> Result<Response> {
+        if let Some(auth) = &self.auth {
+            if !auth.check(&headers) {
+                return Ok((
+                    StatusCode::UNAUTHORIZED,
+                    [(header::WWW_AUTHENTICATE, "Basic realm=\"pikpakcli\"")],
+                )
+                    .into_response());
+            }
+        }
+
+        let path = decode_path(&uri);
+        match method.as_str() {
+            "OPTIONS" => {
+                let mut headers = HeaderMap::new();
+                headers.insert("DAV", HeaderValue::from_static("1"));
+                headers.insert(header::ALLOW, HeaderValue::from_static(ALLOW));
+                Ok((StatusCode::OK, headers).into_response())
+            }
+            "PROPFIND" => self.propfind(&path, &headers).await,
+            "GET" | "HEAD" => {
+                let Some(file) = self.gateway.resolve(&path).await? else {
+                    return Ok(StatusCode::NOT_FOUND.into_response());
+                };
+                if is_folder(&file) {
+                    // 目录没有内容, 部分客户端会对目录发送 HEAD
+                    return Ok(StatusCode::OK.into_response());
+                }
+                self.gateway.proxy(&file, &method, &headers).await
+            }
+            "MKCOL" => self.mkcol(&path).await,
+            "DELETE" => self.delete(&path).await,
+            "MOVE" => self.r#move(&path, &headers).await,
+            "PUT" => self.put(&path, body).await,
+            _ => Ok((StatusCode::METHOD_NOT_ALLOWED, [(header::ALLOW, ALLOW)]).into_response()),
+        }
+    }
+}
+
+async fn webdav_handler(
+    State(webdav): State<Arc<WebDav>>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: BodyStream,
+) -> Response {
+    info!("{} {}", method, uri);
+    match webdav.handle(method, uri, headers, body).await {
+        Result::Ok(resp) => resp,
+        Err(err) => {
+            error!("handle webdav request failed, err: {:#}", err);
+            (StatusCode::BAD_GATEWAY, format!("{:#}", err)).into_response()
+        }
+    }
+}
+
+fn webdav_router(gateway: Gateway, auth: Option<BasicAuth>) -> Router<(), Body> {
+    Router::new()
+        .fallback(webdav_handler)
+        .with_state(Arc::new(WebDav { gateway, auth }))
+}
+
+impl Client {
+    pub async fn serve_webdav(
+        self,
+        bind: SocketAddr,
+        root: String,
+        auth: Option<BasicAuth>,
+    ) -> Result<()> {
+        let gateway = Gateway::new(self, &root);
+        if auth.is_none() && !bind.ip().is_loopback() {
+            warn!("webdav is served on {} without authentication", bind);
+        }
+        info!("serving webdav on http://{}", bind);
+        axum::Server::try_bind(&bind)
+            .with_context(|| format!("[serve_webdav] bind {} failed", bind))?
+            .serve(webdav_router(gateway, auth).into_make_service())
+            .await
+            .context("[serve_webdav] server error")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::gateway::tests::start;
+    use crate::pikpak::mock::MockServer;
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"user:pass", b"user:pass"));
+        assert!(!constant_time_eq(b"user:pass", b"user:pasS"));
+        assert!(!constant_time_eq(b"user:pass", b"user:pass2"));
+        assert!(!constant_time_eq(b"", b"user:pass"));
+    }
+
+    #[tokio::test]
+    async fn test_webdav_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        let mut client = server.client();
+        client.login().await?;
+        let auth = BasicAuth {
+            username: "dav".into(),
+            password: "secret".into(),
+        };
+        let url = start(webdav_router(Gateway::new(client, "/"), Some(auth))).await;
+        let http = reqwest::Client::new();
+        let propfind = Method::from_bytes(b"PROPFIND")?;
+
+        let resp = http
+            .request(propfind.clone(), format!("{}/My%20Pack", url))
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 401);
+
+        let resp = http
+            .request(propfind.clone(), format!("{}/My%20Pack", url))
+            .basic_auth("dav", Some("secret"))
+            .header("Depth", "1")
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 207);
+        let body = resp.text().await?;
+        assert!(body.contains("<D:href>/My%20Pack/</D:href>"));
+        assert!(body.contains("<D:href>/My%20Pack/a.txt</D:href>"));
+        assert!(body.contains("<D:getcontentlength>11</D:getcontentlength>"));
+        assert!(body.contains("<D:href>/My%20Pack/sub/</D:href><D:propstat><D:prop><D:displayname>sub</D:displayname><D:resourcetype><D:collection/>"));
+
+        let resp = http
+            .request(propfind, format!("{}/My%20Pack", url))
+            .basic_auth("dav", Some("secret"))
+            .header("Depth", "0")
+            .send()
+            .await?;
+        assert!(!resp.text().await?.contains("a.txt"));
+
+        let resp = http
+            .get(format!("{}/My%20Pack/a.txt", url))
+            .basic_auth("dav", Some("secret"))
+            .header("Range", "bytes=0-4")
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 206);
+        assert_eq!(resp.text().await?, "hello");
+
+        let resp = http
+            .request(
+                Method::from_bytes(b"COPY")?,
+                format!("{}/My%20Pack/a.txt", url),
+            )
+            .basic_auth("dav", Some("secret"))
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 405);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_webdav_write_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        let mut client = server.client();
+        client.login().await?;
+        let url = start(webdav_router(Gateway::new(client, "/My Pack"), None)).await;
+        let http = reqwest::Client::new();
+        let mkcol = Method::from_bytes(b"MKCOL")?;
+        let r#move = Method::from_bytes(b"MOVE")?;
+        let get = |path: &str| http.get(format!("{}{}", url, path)).send();
+
+        let resp = http
+            .request(mkcol.clone(), format!("{}/new", url))
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 201);
+        let resp = http
+            .request(mkcol.clone(), format!("{}/new", url))
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 405);
+        let resp = http
+            .request(mkcol, format!("{}/missing/new", url))
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 409);
+
+        let resp = http
+            .put(format!("{}/new/c.txt", url))
+            .body("uploaded")
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 201);
+        assert_eq!(get("/new/c.txt").await?.text().await?, "uploaded");
+        // 覆盖已有文件时远程只保留新版本
+        let resp = http
+            .put(format!("{}/new/c.txt", url))
+            .body("version 2")
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 204);
+        assert_eq!(get("/new/c.txt").await?.text().await?, "version 2");
+        let folder = server
+            .state()
+            .files
+            .iter()
+            .find(|x| x.name == "new")
+            .unwrap()
+            .id
+            .clone();
+        let names: Vec<_> = server
+            .state()
+            .files
+            .iter()
+            .filter(|x| x.parent_id == folder)
+            .map(|x| x.name.clone())
+            .collect();
+        assert_eq!(names, vec!["c.txt"]);
+
+        let resp = http
+            .request(r#move.clone(), format!("{}/new/c.txt", url))
+            .header("Destination", format!("{}/new/d.txt", url))
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 201);
+        assert_eq!(get("/new/d.txt").await?.text().await?, "version 2");
+        assert_eq!(get("/new/c.txt").await?.status(), 404);
+        let resp = http
+            .request(r#move, format!("{}/new/d.txt", url))
+            .header("Destination", format!("{}/d.txt", url))
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 403);
+
+        let resp = http.delete(format!("{}/new", url)).send().await?;
+        assert_eq!(resp.status(), 204);
+        assert_eq!(get("/new/d.txt").await?.status(), 404);
+        assert!(server.state().trashed.contains(&folder));
+        Ok(())
+    }
+}
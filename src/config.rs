@@ -10,6 +10,75 @@ pub struct Config {
     pub password: String,
     #[serde(default)]
     pub log_path: String,
+    #[serde(default)]
+    pub endpoints: Endpoints,
+    pub cassette: Option<CassetteConfig>,
+    #[serde(default)]
+    pub job_dir: String,
+}
+
+pub const DEFAULT_JOB_DIR: &str = ".pikpakcli/jobs";
+
+impl Config {
+    pub fn job_dir(&self) -> &str {
+        if self.job_dir.is_empty() {
+            DEFAULT_JOB_DIR
+        } else {
+            &self.job_dir
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CassetteMode {
+    Record,
+    Replay,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CassetteConfig {
+    pub mode: CassetteMode,
+    pub path: String,
+}
+
+pub const USER_ENDPOINT_ENV: &str = "PIKPAK_USER_ENDPOINT";
+pub const DRIVE_ENDPOINT_ENV: &str = "PIKPAK_DRIVE_ENDPOINT";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Endpoints {
+    pub user: String,
+    pub drive: String,
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Endpoints {
+            user: "https://user.mypikpak.com".into(),
+            drive: "https://api-drive.mypikpak.com".into(),
+        }
+    }
+}
+
+impl Endpoints {
+    // 环境变量优先级高于配置文件
+    pub fn apply_env(&mut self) {
+        if let Ok(user) = std::env::var(USER_ENDPOINT_ENV) {
+            self.user = user;
+        }
+        if let Ok(drive) = std::env::var(DRIVE_ENDPOINT_ENV) {
+            self.drive = drive;
+        }
+    }
+
+    pub fn user_url(&self, path: &str) -> String {
+        self.user.trim_end_matches('/').to_string() + path
+    }
+
+    pub fn drive_url(&self, path: &str) -> String {
+        self.drive.trim_end_matches('/').to_string() + path
+    }
 }
 
 static CONF: OnceLock<Config> = OnceLock::new();
@@ -17,7 +86,8 @@ static CONF: OnceLock<Config> = OnceLock::new();
 pub fn load_config(path: &str) -> Result<()> {
     let file = std::fs::File::open(path).context("load file failed")?;
 
-    let conf: Config = serde_yaml::from_reader(file).context("parse file failed")?;
+    let mut conf: Config = serde_yaml::from_reader(file).context("parse file failed")?;
+    conf.endpoints.apply_env();
     CONF.set(conf)
         .map_err(|_| anyhow::anyhow!("failed to set config"))?;
 
@@ -27,3 +97,25 @@ pub fn load_config(path: &str) -> Result<()> {
 pub fn get_config() -> &'static Config {
     CONF.get().expect("config not init")
 }
+
+pub fn try_get_config() -> Option<&'static Config> {
+    CONF.get()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoints() -> Result<()> {
+        let conf: Config = serde_yaml::from_str(
+            "username: a\npassword: b\nendpoints:\n  drive: http://127.0.0.1:8080/\n",
+        )?;
+        assert_eq!(conf.endpoints.user, Endpoints::default().user);
+        assert_eq!(
+            conf.endpoints.drive_url("/drive/v1/files"),
+            "http://127.0.0.1:8080/drive/v1/files"
+        );
+        Ok(())
+    }
+}
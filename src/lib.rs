@@ -4,6 +4,7 @@ use cli::handle;
 use config::{get_config, load_config};
 use log::*;
 use logger::setup_logger;
+use pikpak::captcha_token::VerificationRequired;
 
 mod args;
 mod cli;
@@ -12,6 +13,8 @@ mod logger;
 mod pikpak;
 mod utils;
 
+pub const VERIFICATION_REQUIRED_EXIT_CODE: u8 = 3;
+
 pub fn run_cmd() -> Result<()> {
     let cli = parse_cli();
 
@@ -22,16 +25,27 @@ pub fn run_cmd() -> Result<()> {
 
     load_config("config.yml")?;
     let log_path = get_config().log_path.as_str();
+    let stderr = cli.command.as_ref().is_some_and(|x| x.writes_stdout());
     if cli.debug {
-        setup_logger(LevelFilter::Debug, log_path)?;
+        setup_logger(LevelFilter::Debug, log_path, stderr)?;
     } else {
-        setup_logger(LevelFilter::Info, log_path)?;
+        setup_logger(LevelFilter::Info, log_path, stderr)?;
     }
 
     if let Some(x) = cli.command {
         let rt = tokio::runtime::Runtime::new()?;
-        rt.block_on(async { handle(x, cli.retry).await })?;
+        rt.block_on(async { handle(x, cli.retry, !cli.non_interactive).await })?;
     }
 
     Ok(())
 }
+
+pub fn exit_code(err: &anyhow::Error) -> u8 {
+    if err
+        .chain()
+        .any(|x| x.downcast_ref::<VerificationRequired>().is_some())
+    {
+        return VERIFICATION_REQUIRED_EXIT_CODE;
+    }
+    1
+}
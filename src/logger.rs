@@ -1,7 +1,8 @@
 use anyhow::Result;
 use std::time::SystemTime;
 
-pub fn setup_logger(level: log::LevelFilter, log_path: &str) -> Result<()> {
+// stderr 为 true 时日志输出到 stderr, 避免混入输出到 stdout 的文件内容
+pub fn setup_logger(level: log::LevelFilter, log_path: &str, stderr: bool) -> Result<()> {
     let mut logger = fern::Dispatch::new()
         .level(level)
         .format(|out, message, record| {
@@ -13,8 +14,12 @@ pub fn setup_logger(level: log::LevelFilter, log_path: &str) -> Result<()> {
                 record.line().unwrap_or_default(),
                 message
             ))
-        })
-        .chain(std::io::stdout());
+        });
+    logger = if stderr {
+        logger.chain(std::io::stderr())
+    } else {
+        logger.chain(std::io::stdout())
+    };
 
     if !log_path.is_empty() {
         logger = logger.chain(fern::log_file(log_path)?)
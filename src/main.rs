@@ -1,9 +1,12 @@
-use pikpakcli::run_cmd;
+use std::process::ExitCode;
 
-fn main() -> Result<(), anyhow::Error> {
+use pikpakcli::{exit_code, run_cmd};
+
+fn main() -> ExitCode {
     if let Err(err) = run_cmd() {
         log::error!("Error: {:?}", err);
-        return Err(err);
+        eprintln!("Error: {:?}", err);
+        return ExitCode::from(exit_code(&err));
     }
-    Ok(())
+    ExitCode::SUCCESS
 }
@@ -3,10 +3,11 @@ use std::sync::OnceLock;
 use anyhow::{Context, Result};
 use chrono::Utc;
 use log::*;
+use reqwest::Url;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
-use crate::pikpak::{Resp, RetrySend};
+use crate::pikpak::Resp;
 
 use super::{Client, CLIENT_ID};
 
@@ -28,8 +29,99 @@ fn get_md5_object() -> &'static [Md5Obj] {
     })
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerificationRequired {
+    pub url: String,
+}
+
+impl std::fmt::Display for VerificationRequired {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "captcha verification required, url: {}", self.url)
+    }
+}
+
+impl std::error::Error for VerificationRequired {}
+
+// 用户可以直接粘贴 captcha token, 也可以粘贴验证完成后跳转的链接
+fn parse_captcha_input(input: &str) -> Option<String> {
+    let input = input.trim();
+    if input.is_empty() {
+        return None;
+    }
+    if let Ok(url) = Url::parse(input) {
+        return url
+            .query_pairs()
+            .find(|(k, _)| k == "captcha_token")
+            .map(|(_, v)| v.to_string());
+    }
+    Some(input.to_string())
+}
+
 impl Client {
     pub async fn auth_captcha_token(&mut self, action: String) -> Result<()> {
+        for _ in 0..2 {
+            match self.init_captcha_token(&action).await? {
+                Resp::Success(resp) => {
+                    self.captcha_token = resp
+                        .get("captcha_token")
+                        .and_then(|x| x.as_str())
+                        .map(|x| x.to_string())
+                        .unwrap_or_default();
+                    let url = resp.get("url").and_then(|x| x.as_str()).unwrap_or_default();
+                    if !url.is_empty() {
+                        // 验证完成后当前 captcha token 即生效
+                        self.verify_captcha(url).await?;
+                    }
+                    return Ok(());
+                }
+                Resp::Err(err) => {
+                    if err.error_url.is_empty() {
+                        return Err(anyhow::Error::new(err).context("[auth_captcha_token] failed"));
+                    }
+                    if self.verify_captcha(&err.error_url).await? {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+
+        Err(anyhow::anyhow!(
+            "[auth_captcha_token] failed, captcha still not verified"
+        ))
+    }
+
+    // 提示用户完成人机验证, 返回用户是否直接提供了 captcha token
+    pub async fn verify_captcha(&mut self, url: &str) -> Result<bool> {
+        if !self.interactive {
+            return Err(VerificationRequired {
+                url: url.to_string(),
+            }
+            .into());
+        }
+
+        eprintln!("PikPak requires captcha verification, please open the url below in a browser:");
+        eprintln!();
+        eprintln!("    {}", url);
+        eprintln!();
+        eprintln!("After finishing, paste the captcha token (or the redirected url), or press Enter to continue:");
+
+        let input = tokio::task::spawn_blocking(|| {
+            let mut line = String::new();
+            std::io::stdin().read_line(&mut line).map(|_| line)
+        })
+        .await?
+        .context("[verify_captcha] read input failed")?;
+
+        match parse_captcha_input(&input) {
+            Some(token) => {
+                self.captcha_token = token;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn init_captcha_token(&mut self, action: &str) -> Result<Resp<Value>> {
         let ts = Utc::now().timestamp_millis().to_string();
         let mut key = CLIENT_ID.to_string() + CLIENT_VERSION + PACKAGE_NAME + &self.device_id + &ts;
         for obj in get_md5_object() {
@@ -37,50 +129,49 @@ impl Client {
                 key = format!("{:x}", md5::compute(key + &obj.salt));
             }
         }
+        let mut meta = json!({
+            "captcha_sign": "1.".to_string() + &key,
+            "user_id": self.sub,
+            "package_name": PACKAGE_NAME,
+            "client_version": CLIENT_VERSION,
+            "timestamp": ts,
+        });
+        // 登录前没有 user_id, 需要带上账号信息
+        if self.sub.is_empty() {
+            let field = if self.account.contains('@') {
+                "email"
+            } else if self.account.chars().all(|x| x.is_ascii_digit() || x == '+') {
+                "phone_number"
+            } else {
+                "username"
+            };
+            meta[field] = json!(self.account);
+        }
         let body = json!({
             "action": action,
             "captcha_token": self.captcha_token,
             "client_id": CLIENT_ID,
             "device_id": self.device_id,
             "redirect_uri": "ttps://api.mypikpak.com/v1/auth/callback",
-            "meta": {
-                "captcha_sign": "1.".to_string() + &key,
-                "user_id": self.sub,
-                "package_name": PACKAGE_NAME,
-                "client_version": CLIENT_VERSION,
-                "timestamp": ts,
-            }
+            "meta": meta,
         });
-        let req = self
+        let mut req = self
             .client
-            .post("https://user.mypikpak.com/v1/shield/captcha/init")
+            .post(self.endpoints.user_url("/v1/shield/captcha/init"))
             .query(&[("client_id", CLIENT_ID)])
-            .bearer_auth(&self.jwt_token)
             .json(&body);
+        if !self.jwt_token.is_empty() {
+            req = req.bearer_auth(&self.jwt_token);
+        }
 
         debug!("req: {:?}", req);
 
-        match req
-            .retry_send(self.retry_times)
-            .await
-            .context("[auth_captcha_token]")?
-            .json::<Resp<Value>>()
-            .await?
-        {
-            Resp::Success(resp) => {
-                debug!("resp: {:?}", resp);
-                self.captcha_token = resp
-                    .get("captcha_token")
-                    .and_then(|x| x.as_str())
-                    .map(|x| x.to_string())
-                    .unwrap_or_default();
-                Ok(())
-            }
-            Resp::Err(err) => {
-                error!("[auth_captcha_token] failed, err: {:#?}", err);
-                Err(anyhow::anyhow!("[auth_captcha_token] failed"))
-            }
-        }
+        let resp = serde_json::from_value::<Resp<Value>>(
+            self.send_json(req).await.context("[auth_captcha_token]")?,
+        )
+        .context("[auth_captcha_token]")?;
+        debug!("resp: {:?}", resp);
+        Ok(resp)
     }
 }
 
@@ -88,6 +179,7 @@ impl Client {
 mod tests {
     use crate::config::load_config;
     use crate::logger::setup_test_logger;
+    use crate::pikpak::mock::MockServer;
 
     use super::*;
 
@@ -107,4 +199,32 @@ mod tests {
         }
         Ok(())
     }
+
+    #[test]
+    fn test_parse_captcha_input() {
+        assert_eq!(parse_captcha_input("  \n"), None);
+        assert_eq!(
+            parse_captcha_input("ck0.abc\n"),
+            Some("ck0.abc".to_string())
+        );
+        assert_eq!(
+            parse_captcha_input("https://example.com/callback?captcha_token=ck0.abc&x=1"),
+            Some("ck0.abc".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verification_required_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        server.state().verification_url = "https://example.com/verify".into();
+        let mut client = server.client();
+
+        let err = client.login().await.unwrap_err();
+        let verification = err
+            .chain()
+            .find_map(|x| x.downcast_ref::<VerificationRequired>())
+            .expect("login should require verification");
+        assert_eq!(verification.url, "https://example.com/verify");
+        Ok(())
+    }
 }
@@ -0,0 +1,313 @@
+// 录制/回放 api 请求, 用于在不访问真实服务的情况下回归测试返回值的解析
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use itertools::Itertools;
+use log::*;
+use reqwest::{Request, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::config::{CassetteConfig, CassetteMode};
+
+const REDACTED: &str = "<redacted>";
+
+// 包含 token 或个人信息的字段
+const REDACTED_KEYS: &[&str] = &[
+    "access_token",
+    "refresh_token",
+    "captcha_token",
+    "captcha_sign",
+    "client_secret",
+    "password",
+    "username",
+    "email",
+    "phone_number",
+    "user_id",
+    "sub",
+    "device_id",
+    "token",
+];
+
+// 值为带签名链接的字段, 只保留 query 之前的部分
+const URL_KEYS: &[&str] = &["url", "web_content_link", "thumbnail_link", "icon_link"];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Interaction {
+    pub method: String,
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request: Option<Value>,
+    pub status: u16,
+    pub response: Value,
+    #[serde(skip)]
+    used: bool,
+}
+
+#[derive(Debug)]
+pub struct Cassette {
+    mode: CassetteMode,
+    path: PathBuf,
+    interactions: Vec<Interaction>,
+}
+
+pub fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (k, v) in map.iter_mut() {
+                if REDACTED_KEYS.contains(&k.as_str()) {
+                    if v.as_str().is_some_and(|x| !x.is_empty()) {
+                        *v = Value::String(REDACTED.into());
+                    }
+                } else if URL_KEYS.contains(&k.as_str()) {
+                    if let Some(url) = v.as_str().and_then(|x| Url::parse(x).ok()) {
+                        if url.query().is_some() {
+                            let mut url = url;
+                            url.set_query(None);
+                            *v = Value::String(url.to_string());
+                        }
+                    }
+                } else {
+                    redact(v);
+                }
+            }
+        }
+        Value::Array(list) => list.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+// 请求路径, 不包含 host, 以便回放时与 endpoint 无关
+fn request_path(url: &Url) -> String {
+    let mut path = url.path().to_string();
+    // HashMap 构造的 query 顺序不固定, 需要排序
+    let query: Vec<String> = url
+        .query_pairs()
+        .sorted()
+        .map(|(k, v)| {
+            if REDACTED_KEYS.contains(&k.as_ref()) {
+                format!("{}={}", k, REDACTED)
+            } else {
+                format!("{}={}", k, v)
+            }
+        })
+        .collect();
+    if !query.is_empty() {
+        path += "?";
+        path += &query.join("&");
+    }
+    path
+}
+
+impl Cassette {
+    pub fn load(conf: &CassetteConfig) -> Result<Self> {
+        let path = PathBuf::from(&conf.path);
+        let interactions = match conf.mode {
+            CassetteMode::Record => vec![],
+            CassetteMode::Replay => {
+                let file = std::fs::File::open(&path)
+                    .with_context(|| format!("[cassette] open {} failed", path.display()))?;
+                serde_json::from_reader(file)
+                    .with_context(|| format!("[cassette] parse {} failed", path.display()))?
+            }
+        };
+        Ok(Cassette {
+            mode: conf.mode.clone(),
+            path,
+            interactions,
+        })
+    }
+
+    pub fn is_replay(&self) -> bool {
+        self.mode == CassetteMode::Replay
+    }
+
+    pub fn replay(&mut self, req: &Request) -> Result<Value> {
+        let method = req.method().to_string();
+        let path = request_path(req.url());
+        let interaction = self
+            .interactions
+            .iter_mut()
+            .find(|x| !x.used && x.method == method && x.path == path)
+            .ok_or_else(|| anyhow::anyhow!("[cassette] no interaction for {} {}", method, path))?;
+        interaction.used = true;
+        debug!("replay: {} {}", method, path);
+        Ok(interaction.response.clone())
+    }
+
+    pub fn record(&mut self, req: &Request, status: u16, resp: &Value) -> Result<()> {
+        let mut request = req
+            .body()
+            .and_then(|x| x.as_bytes())
+            .and_then(|x| serde_json::from_slice::<Value>(x).ok());
+        if let Some(request) = request.as_mut() {
+            redact(request);
+        }
+        let mut response = resp.clone();
+        redact(&mut response);
+
+        self.interactions.push(Interaction {
+            method: req.method().to_string(),
+            path: request_path(req.url()),
+            request,
+            status,
+            response,
+            used: false,
+        });
+
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).context("[cassette] create dir failed")?;
+        }
+        let file = std::fs::File::create(&self.path)
+            .with_context(|| format!("[cassette] create {} failed", self.path.display()))?;
+        serde_json::to_writer_pretty(file, &self.interactions).context("[cassette] save failed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::config::Config;
+    use crate::pikpak::file::{FileStatus, FileType};
+    use crate::pikpak::mock::MockServer;
+    use crate::pikpak::{Client, Resp};
+
+    fn cassette_path(name: &str) -> String {
+        format!("{}/tests/cassettes/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    #[test]
+    fn test_cassette_payloads() -> Result<()> {
+        let file = std::fs::File::open(cassette_path("drive.json"))?;
+        let interactions: Vec<Interaction> = serde_json::from_reader(file)?;
+
+        let err = serde_json::from_value::<Resp<FileType>>(interactions[2].response.clone())?;
+        assert!(matches!(err, Resp::Err(err) if err.error_code == 9));
+
+        let list = interactions[4].response["files"].clone();
+        let list: Vec<FileStatus> = serde_json::from_value(list)?;
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].kind, "drive#folder");
+
+        let file = serde_json::from_value::<Resp<FileType>>(interactions[5].response.clone())?;
+        let Resp::Success(file) = file else {
+            panic!("file detail should be parsed as success");
+        };
+        assert_eq!(file.size, "10485760");
+        assert_eq!(file.file_category, "VIDEO");
+        assert_eq!(file.user_modified_time, "2024-01-10T12:03:58.456+08:00");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replay_cassette() -> Result<()> {
+        let conf = Config {
+            cassette: Some(CassetteConfig {
+                mode: CassetteMode::Replay,
+                path: cassette_path("drive.json"),
+            }),
+            ..Default::default()
+        };
+        let mut client = Client::from_config(&conf, 0)?;
+        client.login().await?;
+
+        let list = client.get_file_status_list_by_folder_id("").await?;
+        let names: Vec<_> = list.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, vec!["My Pack", "sample.mp4"]);
+
+        let file = client.get_file_by_id(list[1].id.clone()).await?;
+        assert_eq!(file.mime_type, "video/mp4");
+        assert_eq!(
+            file.links.application_octet_stream.expire,
+            "2024-01-11T12:04:00.000+08:00"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_record_cassette_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("record.json").to_str().unwrap().to_string();
+
+        let mut conf = server.config();
+        conf.cassette = Some(CassetteConfig {
+            mode: CassetteMode::Record,
+            path: path.clone(),
+        });
+        let mut client = Client::from_config(&conf, 0)?;
+        client.login().await?;
+        let file = client.get_file_by_id("file-a".into()).await?;
+
+        let recorded = std::fs::read_to_string(&path)?;
+        assert!(!recorded.contains("access-token-"));
+        assert!(!recorded.contains(crate::pikpak::mock::MOCK_PASSWORD));
+        assert!(!recorded.contains(crate::pikpak::mock::MOCK_USERNAME));
+
+        // 回放时不再访问 mock server
+        conf.endpoints = Default::default();
+        conf.cassette = Some(CassetteConfig {
+            mode: CassetteMode::Replay,
+            path,
+        });
+        let mut client = Client::from_config(&conf, 0)?;
+        client.login().await?;
+        let replayed = client.get_file_by_id("file-a".into()).await?;
+        assert_eq!(replayed.name, file.name);
+        assert_eq!(replayed.size, file.size);
+        Ok(())
+    }
+
+    #[test]
+    fn test_redact() {
+        let mut value = json!({
+            "access_token": "secret",
+            "refresh_token": "",
+            "files": [{
+                "name": "a.mp4",
+                "user_id": "user",
+                "links": {
+                    "application/octet-stream": {
+                        "url": "https://dl.example.com/download/?fid=1&sign=abc",
+                        "token": "abc",
+                    }
+                }
+            }]
+        });
+        redact(&mut value);
+        assert_eq!(
+            value,
+            json!({
+                "access_token": REDACTED,
+                "refresh_token": "",
+                "files": [{
+                    "name": "a.mp4",
+                    "user_id": REDACTED,
+                    "links": {
+                        "application/octet-stream": {
+                            "url": "https://dl.example.com/download/",
+                            "token": REDACTED,
+                        }
+                    }
+                }]
+            })
+        );
+    }
+
+    #[test]
+    fn test_request_path() -> Result<()> {
+        let url = Url::parse("https://api.example.com/drive/v1/files?parent_id=1&password=x")?;
+        assert_eq!(
+            request_path(&url),
+            "/drive/v1/files?parent_id=1&password=<redacted>"
+        );
+        let url = Url::parse("https://api.example.com/drive/v1/files?password=x&parent_id=1")?;
+        assert_eq!(
+            request_path(&url),
+            "/drive/v1/files?parent_id=1&password=<redacted>"
+        );
+        Ok(())
+    }
+}
@@ -11,18 +11,19 @@ use log::warn;
 use reqwest::Client;
 use tokio::fs::File;
 use tokio::fs::OpenOptions;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 
-use crate::config::get_config;
+use crate::config::try_get_config;
 use crate::pikpak::RetrySend;
 
 use super::file::FileType;
 use super::USER_AGENT;
 
-fn get_download_client() -> &'static Client {
+pub(crate) fn get_download_client() -> &'static Client {
     static CLIENT: OnceLock<Client> = OnceLock::new();
     CLIENT.get_or_init(|| {
         let mut client_builder = Client::builder().user_agent(USER_AGENT);
-        if let Some(proxy) = &get_config().proxy {
+        if let Some(proxy) = try_get_config().and_then(|x| x.proxy.as_ref()) {
             client_builder =
                 client_builder.proxy(reqwest::Proxy::all(proxy).expect(
                     "[get_download_client] parse proxy failed, please check your config file",
@@ -105,6 +106,128 @@ pub async fn download_with_file(path: &Path, file: &FileType, retry_times: i8) -
     Ok(())
 }
 
+// 通过 HEAD 请求获取文件大小和类型, 服务端不返回时为 None
+pub async fn content_info(url: &str) -> Result<(Option<u64>, Option<String>)> {
+    let resp = get_download_client()
+        .head(url)
+        .header("User-Agent", USER_AGENT)
+        .send()
+        .await
+        .context("[content_info]")?;
+    if !resp.status().is_success() {
+        return Err(anyhow::anyhow!(
+            "[content_info] unexpected status: {}",
+            resp.status()
+        ));
+    }
+    let header = |name| {
+        resp.headers()
+            .get(name)
+            .and_then(|x| x.to_str().ok())
+            .map(|x| x.to_string())
+    };
+    let size = header("Content-Length").and_then(|x| x.parse().ok());
+    Ok((size, header("Content-Type")))
+}
+
+// 下载链接过期或被拒绝, pos 是已经写到的位置, 调用方刷新链接后从这里继续
+#[derive(Debug)]
+pub struct LinkRejected {
+    pub pos: u64,
+    pub status: reqwest::StatusCode,
+}
+
+impl std::fmt::Display for LinkRejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "download link rejected at {} bytes, status: {}",
+            self.pos, self.status
+        )
+    }
+}
+
+impl std::error::Error for LinkRejected {}
+
+// 将 [start, end) 范围内的内容写入 out, 连接中断时从已写入的位置继续请求
+pub async fn stream_range<W: AsyncWrite + Unpin>(
+    file: &FileType,
+    start: u64,
+    end: u64,
+    out: &mut W,
+    retry_times: i8,
+) -> Result<u64> {
+    let size = file.size.parse::<u64>().unwrap_or(end);
+    let mut pos = start;
+    let mut now = 0;
+    while pos < end {
+        let mut req = get_download_client()
+            .get(&file.links.application_octet_stream.url)
+            .header("User-Agent", USER_AGENT);
+        let partial = pos != 0 || end != size;
+        if partial {
+            req = req.header("Range", format!("bytes={}-{}", pos, end - 1));
+        }
+        debug!("req: {:?}", req);
+
+        let res = async {
+            let resp = req
+                .retry_send(retry_times)
+                .await
+                .context("[stream_range]")?;
+            let status = resp.status();
+            if status == reqwest::StatusCode::FORBIDDEN || status == reqwest::StatusCode::GONE {
+                return Err(anyhow::Error::new(LinkRejected { pos, status }));
+            }
+            // 已经写出的内容无法撤回, 服务端不支持 Range 时直接失败
+            if (partial && status != 206) || !status.is_success() {
+                return Err(anyhow::anyhow!(
+                    "[stream_range] unexpected status: {}",
+                    status
+                ));
+            }
+            let mut stream = resp.bytes_stream();
+            while let Some(item) = stream.next().await {
+                let item = item.context("[stream_range] stream error")?;
+                // 服务端可能返回超出请求范围的内容
+                let len = (item.len() as u64).min(end - pos) as usize;
+                out.write_all(&item[..len])
+                    .await
+                    .map_err(|x| anyhow::Error::new(x).context("[stream_range] write error"))?;
+                pos += len as u64;
+                if pos >= end {
+                    break;
+                }
+            }
+            out.flush().await.context("[stream_range] flush error")?;
+            if pos < end {
+                return Err(anyhow::anyhow!(
+                    "[stream_range] connection closed at {} bytes",
+                    pos
+                ));
+            }
+            Ok(())
+        }
+        .await;
+
+        if let Err(err) = res {
+            // 输出端关闭或链接失效时不需要重试
+            let closed = err
+                .downcast_ref::<std::io::Error>()
+                .is_some_and(|x| x.kind() == std::io::ErrorKind::BrokenPipe);
+            if closed || err.is::<LinkRejected>() || (retry_times >= 0 && now >= retry_times) {
+                return Err(err);
+            }
+            warn!(
+                "stream interrupted at {} bytes, retry: {} times, err: {:#}",
+                pos, now, err
+            );
+            now += 1;
+        }
+    }
+    Ok(pos - start)
+}
+
 #[cfg(test)]
 mod tests {
     use std::path::Path;
@@ -115,7 +238,11 @@ mod tests {
     use crate::{
         config::load_config,
         logger::setup_test_logger,
-        pikpak::{download::download_with_file, Client},
+        pikpak::{
+            download::{download_with_file, stream_range},
+            mock::MockServer,
+            Client,
+        },
     };
 
     #[tokio::test]
@@ -139,4 +266,55 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_download_resume_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        let mut client = server.client();
+        client.login().await?;
+        let file = client.get_file_by_id("file-b".into()).await?;
+        let content = server.state().file("file-b").unwrap().content.clone();
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("b.bin");
+        server.state().truncate_downloads = 1;
+        assert!(download_with_file(&path, &file, 0).await.is_err());
+        let partial = std::fs::read(&path)?;
+        assert!(!partial.is_empty() && partial.len() < content.len());
+
+        download_with_file(&path, &file, 0).await?;
+        assert_eq!(std::fs::read(&path)?, content);
+        assert_eq!(
+            server.state().download_ranges,
+            vec![None, Some(format!("bytes={}-", partial.len()))]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stream_range_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        let mut client = server.client();
+        client.login().await?;
+        let file = client.get_file_by_id("file-b".into()).await?;
+        let content = server.state().file("file-b").unwrap().content.clone();
+
+        let mut out = vec![];
+        server.state().truncate_downloads = 1;
+        stream_range(&file, 0, 4096, &mut out, 1).await?;
+        assert_eq!(out, content);
+        assert_eq!(
+            server.state().download_ranges[1..],
+            vec![Some("bytes=2048-4095".to_string())]
+        );
+
+        let mut out = vec![];
+        stream_range(&file, 100, 200, &mut out, 0).await?;
+        assert_eq!(out, content[100..200]);
+
+        let mut out = vec![];
+        server.state().truncate_downloads = 1;
+        assert!(stream_range(&file, 0, 4096, &mut out, 0).await.is_err());
+        Ok(())
+    }
 }
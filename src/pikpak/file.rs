@@ -1,14 +1,15 @@
 use std::collections::HashMap;
 
 use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
 use log::*;
+use reqwest::Method;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
-use crate::pikpak::RetrySend;
+use super::Client;
 
-use super::{Client, Resp};
-
-#[derive(Default, Debug, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct FileStatus {
     pub kind: String,
     pub id: String,
@@ -25,9 +26,14 @@ pub struct FileStatus {
     pub md5_checksum: String,
     pub hash: String,
     pub phase: String,
+    #[serde(default)]
+    pub file_category: String,
+    // 媒体文件的附加信息, 例如时长, 字段不固定
+    #[serde(default)]
+    pub params: Value,
 }
 
-#[derive(Default, Debug, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct FileType {
     pub kind: String,
@@ -59,15 +65,44 @@ pub struct FileType {
     pub sort_name: String,
     pub user_modified_time: String,
     pub file_category: String,
+    // 原始文件和转码后的视频
+    pub medias: Vec<Media>,
+    // 下载时选择的转码视频名称, 原始文件为空
+    #[serde(skip)]
+    pub variant: String,
 }
 
-#[derive(Default, Debug, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Media {
+    pub media_id: String,
+    pub media_name: String,
+    pub resolution_name: String,
+    pub is_origin: bool,
+    pub is_default: bool,
+    pub video: MediaVideo,
+    pub link: ApplicationOctetStream,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MediaVideo {
+    pub height: u64,
+    pub width: u64,
+    pub duration: f64,
+    pub bit_rate: u64,
+    pub video_codec: String,
+    pub audio_codec: String,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct Links {
     #[serde(rename = "application/octet-stream")]
     pub application_octet_stream: ApplicationOctetStream,
 }
 
-#[derive(Default, Debug, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ApplicationOctetStream {
     pub url: String,
     pub token: String,
@@ -76,19 +111,83 @@ pub struct ApplicationOctetStream {
     pub type_field: String,
 }
 
+// 列目录和获取文件信息时返回的缩略图尺寸
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ThumbnailSize {
+    Small,
+    #[default]
+    Medium,
+    Large,
+}
+
+impl ThumbnailSize {
+    pub fn as_param(self) -> &'static str {
+        match self {
+            ThumbnailSize::Small => "SIZE_SMALL",
+            ThumbnailSize::Medium => "SIZE_MEDIUM",
+            ThumbnailSize::Large => "SIZE_LARGE",
+        }
+    }
+}
+
 #[derive(Default, Debug, Serialize, Deserialize)]
 struct StatusResp {
     pub next_page_token: String,
     pub files: Vec<FileStatus>,
 }
 
+impl FileType {
+    // 优先使用上传前的本地修改时间
+    pub fn modified(&self) -> Option<DateTime<Utc>> {
+        [&self.user_modified_time, &self.modified_time]
+            .into_iter()
+            .find_map(|x| DateTime::parse_from_rfc3339(x).ok())
+            .map(|x| x.with_timezone(&Utc))
+    }
+}
+
+impl From<&FileType> for FileStatus {
+    fn from(file: &FileType) -> Self {
+        FileStatus {
+            kind: file.kind.clone(),
+            id: file.id.clone(),
+            parent_id: file.parent_id.clone(),
+            name: file.name.clone(),
+            user_id: file.user_id.clone(),
+            size: file.size.clone(),
+            file_extension: file.file_extension.clone(),
+            mime_type: file.mime_type.clone(),
+            created_time: file.created_time.clone(),
+            modified_time: file.modified_time.clone(),
+            icon_link: file.icon_link.clone(),
+            thumbnail_link: file.thumbnail_link.clone(),
+            md5_checksum: file.md5_checksum.clone(),
+            hash: file.hash.clone(),
+            phase: file.phase.clone(),
+            file_category: file.file_category.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+impl FileStatus {
+    // 媒体时长, 单位秒, 有的接口返回字符串, 有的返回数字
+    pub fn duration(&self) -> Option<f64> {
+        match &self.params["duration"] {
+            Value::String(x) => x.parse().ok(),
+            x => x.as_f64(),
+        }
+        .filter(|x| *x > 0.0)
+    }
+}
+
 impl Client {
     pub async fn get_file_status_list_by_folder_id(
         &mut self,
         folder_id: &str,
     ) -> Result<Vec<FileStatus>> {
         let mut query = HashMap::from([
-            ("thumbnail_size", "SIZE_MEDIUM".into()),
+            ("thumbnail_size", self.thumbnail_size.as_param().into()),
             ("limit", "100".into()),
             ("parent_id", folder_id.to_owned()),
             ("with_audit", "false".into()),
@@ -97,108 +196,53 @@ impl Client {
         let mut file_list = vec![];
 
         loop {
-            let req = self
-                .client
-                .get("https://api-drive.mypikpak.com/drive/v1/files")
-                .query(&query)
-                .bearer_auth(&self.jwt_token)
-                .header("X-Captcha-Token", &self.captcha_token)
-                .header("Content-Type", "application/json");
-
-            debug!("req: {:?}", req);
-
-            match req
-                .retry_send(self.retry_times)
+            let resp: StatusResp = self
+                .request(
+                    Method::GET,
+                    &self.endpoints.drive_url("/drive/v1/files"),
+                    |_, req| req.query(&query).header("Content-Type", "application/json"),
+                )
                 .await
-                .context("[get_folder_file_stat_list]")?
-                .json::<Resp<StatusResp>>()
-                .await
-                .context("[get_folder_file_stat_list]")?
-            {
-                Resp::Success(resp) => {
-                    debug!("resp: {:?}", resp);
-                    file_list.extend(resp.files);
-                    if resp.next_page_token.is_empty() {
-                        break;
-                    }
-                    query.insert("page_token", resp.next_page_token);
-                }
-                Resp::Err(err) => {
-                    if err.error_code == 9 {
-                        if let Err(err) =
-                            self.auth_captcha_token("GET:/drive/v1/files".into()).await
-                        {
-                            error!(
-                                "[get_folder_file_stat_list] auth captcha token failed, err: {:#?}",
-                                err
-                            );
-                            return Err(anyhow::anyhow!(
-                                "[get_folder_file_stat_list] auth captcha token failed"
-                            ));
-                        }
-                    } else {
-                        error!("[get_folder_file_stat_list] failed, err: {:#?}", err);
-                        return Err(anyhow::anyhow!("[get_folder_file_stat_list]  failed"));
-                    }
-                }
+                .context("[get_folder_file_stat_list]")?;
+
+            debug!("resp: {:?}", resp);
+            file_list.extend(resp.files);
+            if resp.next_page_token.is_empty() {
+                break;
             }
+            query.insert("page_token", resp.next_page_token);
         }
 
         Ok(file_list)
     }
 
     pub async fn get_file_by_id(&mut self, file_id: String) -> Result<FileType> {
-        let req = self
-            .client
-            .get(format!(
-                "https://api-drive.mypikpak.com/drive/v1/files/{}",
-                file_id
-            ))
-            .bearer_auth(&self.jwt_token)
-            .header("thumbnail_size", "SIZE_MEDIUM")
-            .header("X-Captcha-Token", &self.captcha_token)
-            .header("X-Device-Id", &self.device_id);
-
-        debug!("req: {:?}", req);
-
-        for _ in 0..2 {
-            match req
-                .try_clone()
-                .ok_or(anyhow::anyhow!("clone request failed"))?
-                .retry_send(self.retry_times)
-                .await
-                .context("[get_file_by_id]")?
-                .json::<Resp<FileType>>()
-                .await
-                .context("[get_file_by_id]")?
-            {
-                Resp::Success(resp) => {
-                    debug!("resp: {:?}", resp);
-                    return Ok(resp);
-                }
-                Resp::Err(err) => {
-                    if err.error_code == 9 {
-                        if let Err(err) =
-                            self.auth_captcha_token("GET:/drive/v1/files".into()).await
-                        {
-                            error!("[get_file_by_id] failed, err: {:#?}", err);
-                            return Err(anyhow::anyhow!("[get_file_by_id]  failed"));
-                        }
-                    } else {
-                        error!("[get_file_by_id] failed, err: {:#?}", err);
-                        return Err(anyhow::anyhow!("[get_file_by_id] failed"));
-                    }
-                }
-            }
-        }
+        let resp: FileType = self
+            .request(
+                Method::GET,
+                &self
+                    .endpoints
+                    .drive_url(&format!("/drive/v1/files/{}", file_id)),
+                |c, req| {
+                    req.header("thumbnail_size", c.thumbnail_size.as_param())
+                        .header("X-Device-Id", &c.device_id)
+                },
+            )
+            .await
+            .context("[get_file_by_id]")?;
 
-        Err(anyhow::anyhow!("[get_file_by_id] failed"))
+        debug!("resp: {:?}", resp);
+        Ok(resp)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{config::load_config, logger::setup_test_logger};
+    use crate::{
+        config::load_config,
+        logger::setup_test_logger,
+        pikpak::mock::{MockFile, MockServer},
+    };
 
     use super::*;
 
@@ -236,4 +280,47 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_get_file_status_list_pagination_mock() -> Result<()> {
+        let mut files = vec![MockFile::folder("folder", "", "folder")];
+        for i in 0..5 {
+            files.push(MockFile::file(
+                &format!("file-{}", i),
+                "folder",
+                &format!("{}.txt", i),
+                b"",
+            ));
+        }
+        let server = MockServer::start_with_files(files).await;
+        let mut client = server.client();
+        client.login().await?;
+
+        let list = client.get_file_status_list_by_folder_id("folder").await?;
+        let names: Vec<_> = list.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, vec!["0.txt", "1.txt", "2.txt", "3.txt", "4.txt"]);
+        assert_eq!(server.state().list_requests, 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_captcha_renewal_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        let mut client = server.client();
+        client.login().await?;
+        client.get_file_status_list_by_folder_id("").await?;
+
+        server.state().expire_captcha_tokens();
+        let file = client.get_file_by_id("file-a".into()).await?;
+        assert_eq!(file.name, "a.txt");
+        assert_eq!(
+            file.links.application_octet_stream.url,
+            format!("{}/download/file-a", server.url())
+        );
+        assert_eq!(
+            server.state().captcha_actions,
+            vec!["POST:/v1/auth/signin", "GET:/drive/v1/files"]
+        );
+        Ok(())
+    }
 }
@@ -1,15 +1,17 @@
 use anyhow::{Context, Result};
 use log::*;
-use reqwest::header::HeaderMap;
+use reqwest::{header::HeaderMap, Method};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
-use crate::{pikpak::RetrySend, utils::path::slash};
+use crate::utils::path::slash;
 
-use super::{Client, Resp};
+use super::Client;
 
 #[derive(Default, Debug, Serialize, Deserialize)]
 pub struct GetFolderResp {
+    #[serde(default)]
+    pub next_page_token: String,
     pub files: Vec<Value>,
 }
 
@@ -40,7 +42,7 @@ impl Client {
     // dir 可以包括 /.
     // 若以 / 开头，函数会去除 /， 且会从 parent 目录开始查找
     pub async fn get_deep_folder_id(
-        &self,
+        &mut self,
         mut parent_id: FileIDType,
         path: &str,
     ) -> Result<FileIDType> {
@@ -60,7 +62,7 @@ impl Client {
         Ok(parent_id)
     }
 
-    async fn get_sub_folder_id(&self, parent_id: &str, path: &str) -> Result<FileIDType> {
+    async fn get_sub_folder_id(&mut self, parent_id: &str, path: &str) -> Result<FileIDType> {
         let dir = slash(path).context("get_folder_id")?;
 
         let infos = self.get_info_by_id(parent_id).await?;
@@ -96,14 +98,8 @@ impl Client {
         Err(anyhow::anyhow!("[get_folder_id] folder not found"))
     }
 
-    async fn get_info_by_id(&self, parent_id: &str) -> Result<Vec<Value>> {
-        let query = [
-            ("parent_id", parent_id),
-            ("page_token", ""),
-            ("with_audit", "false"),
-            ("thumbnail_size", "SIZE_LARGE"),
-            ("limit", "200"),
-        ];
+    async fn get_info_by_id(&mut self, parent_id: &str) -> Result<Vec<Value>> {
+        let mut page_token = String::new();
 
         let mut headers = HeaderMap::new();
         headers.insert("Country", "CN".parse()?);
@@ -111,41 +107,40 @@ impl Client {
         headers.insert("X-User-Region", "1".parse()?);
         headers.insert("X-Alt-Capability", "3".parse()?);
         headers.insert("X-Client-Version-Code", "10083".parse()?);
-        headers.insert("X-Captcha-Token", self.captcha_token.parse()?);
 
-        let req = self
-            .client
-            .get("https://api-drive.mypikpak.com/drive/v1/files")
-            .query(&query)
-            .headers(headers)
-            .bearer_auth(&self.jwt_token);
-
-        debug!("req: {:?}", req);
+        let mut files = vec![];
+        loop {
+            let query = [
+                ("parent_id", parent_id),
+                ("page_token", &page_token),
+                ("with_audit", "false"),
+                ("thumbnail_size", self.thumbnail_size.as_param()),
+                ("limit", "200"),
+            ];
+            let resp: GetFolderResp = self
+                .request(
+                    Method::GET,
+                    &self.endpoints.drive_url("/drive/v1/files"),
+                    |_, req| req.query(&query).headers(headers.clone()),
+                )
+                .await
+                .context("[get_folder_id] get folder failed")?;
 
-        match req
-            .retry_send(self.retry_times)
-            .await
-            .context("[get_folder_id]")?
-            .json::<Resp<GetFolderResp>>()
-            .await
-            .context("[get_folder_id]")?
-        {
-            Resp::Success(resp) => {
-                debug!("resp: {:?}", resp);
-                Ok(resp.files)
-            }
-            Resp::Err(err) => {
-                error!("[get_folder_id] get folder failed, err: {:#?}", err);
-                Err(anyhow::anyhow!("[get_folder_id] get folder failed"))
+            debug!("resp: {:?}", resp);
+            files.extend(resp.files);
+            if resp.next_page_token.is_empty() {
+                break;
             }
+            page_token = resp.next_page_token;
         }
+        Ok(files)
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::{config::load_config, logger::setup_test_logger};
+    use crate::{config::load_config, logger::setup_test_logger, pikpak::mock::MockServer};
 
     #[tokio::test]
     async fn test_get_path_folder_id() -> Result<()> {
@@ -181,4 +176,27 @@ mod test {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_get_path_id_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        server.state().page_size = 1;
+        let mut client = server.client();
+        client.login().await?;
+
+        assert!(matches!(
+            client.get_path_id("/My Pack/sub").await?,
+            FileIDType::Folder(id) if id == "folder-sub"
+        ));
+        assert!(matches!(
+            client.get_path_id("My Pack/./sub/b.bin").await?,
+            FileIDType::File(id) if id == "file-b"
+        ));
+        assert!(matches!(
+            client.get_path_id("/").await?,
+            FileIDType::Folder(id) if id.is_empty()
+        ));
+        assert!(client.get_path_id("/My Pack/none").await.is_err());
+        Ok(())
+    }
 }
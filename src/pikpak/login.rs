@@ -1,8 +1,7 @@
-use crate::pikpak::RetrySend;
-
-use super::{Client, Resp, CLIENT_ID, CLIENT_SECRET};
+use super::{Client, CLIENT_ID, CLIENT_SECRET};
 use anyhow::{Context, Result};
 use log::*;
+use reqwest::Method;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
@@ -17,50 +16,41 @@ pub struct LoginResp {
 
 impl Client {
     pub async fn login(&mut self) -> Result<()> {
-        let mut req = self
-            .client
-            .post("https://user.mypikpak.com/v1/auth/signin?client_id=".to_string() + CLIENT_ID)
-            .json(&json!({
-                "captcha_token": self.captcha_token,
-                "client_id": CLIENT_ID,
-                "client_secret": CLIENT_SECRET,
-                "username": self.account,
-                "password": self.password,
-            }));
-
-        if !self.jwt_token.is_empty() {
-            req = req.bearer_auth(&self.jwt_token);
-        }
-
-        debug!("req: {:?}", req);
-
-        match req
-            .retry_send(self.retry_times)
+        let resp: LoginResp = self
+            .request(
+                Method::POST,
+                &self
+                    .endpoints
+                    .user_url(&("/v1/auth/signin?client_id=".to_string() + CLIENT_ID)),
+                |c, req| {
+                    req.json(&json!({
+                        "captcha_token": c.captcha_token,
+                        "client_id": CLIENT_ID,
+                        "client_secret": CLIENT_SECRET,
+                        "username": c.account,
+                        "password": c.password,
+                    }))
+                },
+            )
             .await
-            .context("[login]")?
-            .json::<Resp<LoginResp>>()
-            .await
-            .context("[login]")?
-        {
-            Resp::Success(resp) => {
-                debug!("resp: {:?}", resp);
-                self.jwt_token = resp.access_token;
-                self.refresh_token = resp.refresh_token;
-                self.sub = resp.sub;
-                self.refresh_second = resp.expires_in;
-                Ok(())
-            }
-            Resp::Err(err) => {
-                error!("login failed, err: {:#?}", err);
-                Err(anyhow::anyhow!("login failed"))
-            }
-        }
+            .context("[login] login failed")?;
+
+        debug!("resp: {:?}", resp);
+        self.jwt_token = resp.access_token;
+        self.refresh_token = resp.refresh_token;
+        self.sub = resp.sub;
+        self.refresh_second = resp.expires_in;
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{config::load_config, logger::setup_test_logger};
+    use crate::{
+        config::load_config,
+        logger::setup_test_logger,
+        pikpak::{mock::MockServer, ErrResp},
+    };
 
     use super::*;
 
@@ -77,4 +67,30 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_login_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        let mut client = server.client();
+        client.login().await?;
+
+        assert_eq!(client.sub, "mock-user");
+        assert!(!client.jwt_token.is_empty());
+        assert_eq!(server.state().captcha_actions, vec!["POST:/v1/auth/signin"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_login_wrong_password_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        let mut client = server.client();
+        client.password = "wrong".into();
+
+        let err = client.login().await.unwrap_err();
+        let resp = err
+            .downcast_ref::<ErrResp>()
+            .expect("login error should be an ErrResp");
+        assert_eq!(resp.error_code, 4022);
+        Ok(())
+    }
 }
@@ -0,0 +1,732 @@
+// 测试用的 PikPak mock server, 在进程内启动, 不依赖真实账号和网络
+use std::collections::{HashMap, HashSet};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use axum::body::{Bytes, StreamBody};
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post, put};
+use axum::{Json, Router};
+use futures::StreamExt;
+use serde_json::{json, Value};
+
+use crate::config::{Config, Endpoints};
+
+use super::Client;
+
+pub const MOCK_USERNAME: &str = "user@example.com";
+pub const MOCK_PASSWORD: &str = "mock-secret";
+
+#[derive(Debug, Clone)]
+pub struct MockFile {
+    pub id: String,
+    pub parent_id: String,
+    pub name: String,
+    pub folder: bool,
+    pub content: Vec<u8>,
+    pub modified_time: String,
+    pub params: Value,
+    // 转码后的视频 (清晰度名称, 高度, 文件 id), 对应的 MockFile 不在任何目录下
+    pub medias: Vec<(String, u64, String)>,
+    // 是否有缩略图, 链接中带上请求的尺寸
+    pub thumbnail: bool,
+    // 上传完成前为 PHASE_TYPE_PENDING
+    pub phase: String,
+}
+
+impl MockFile {
+    pub fn folder(id: &str, parent_id: &str, name: &str) -> Self {
+        MockFile {
+            id: id.into(),
+            parent_id: parent_id.into(),
+            name: name.into(),
+            folder: true,
+            content: vec![],
+            modified_time: "2024-01-01T00:00:00.000+08:00".into(),
+            params: Value::Null,
+            medias: vec![],
+            thumbnail: false,
+            phase: "PHASE_TYPE_COMPLETE".into(),
+        }
+    }
+
+    pub fn file(id: &str, parent_id: &str, name: &str, content: &[u8]) -> Self {
+        MockFile {
+            id: id.into(),
+            parent_id: parent_id.into(),
+            name: name.into(),
+            folder: false,
+            content: content.to_vec(),
+            modified_time: "2024-01-01T00:00:00.000+08:00".into(),
+            params: Value::Null,
+            medias: vec![],
+            thumbnail: false,
+            phase: "PHASE_TYPE_COMPLETE".into(),
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        if self.folder {
+            "drive#folder"
+        } else {
+            "drive#file"
+        }
+    }
+
+    fn status(&self) -> Value {
+        json!({
+            "kind": self.kind(),
+            "id": self.id,
+            "parent_id": self.parent_id,
+            "name": self.name,
+            "user_id": "mock-user",
+            "size": self.content.len().to_string(),
+            "file_extension": "",
+            "mime_type": "",
+            "created_time": self.modified_time,
+            "modified_time": self.modified_time,
+            "icon_link": "",
+            "thumbnail_link": "",
+            "md5_checksum": format!("{:x}", md5::compute(&self.content)),
+            "hash": "",
+            "phase": self.phase,
+            "trashed": false,
+            "params": self.params,
+        })
+    }
+}
+
+impl MockFile {
+    fn status_with_thumbnail(&self, addr: &str, size: &str) -> Value {
+        let mut status = self.status();
+        if self.thumbnail {
+            status["thumbnail_link"] = json!(format!("{}/thumbnail/{}/{}", addr, self.id, size));
+        }
+        status
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MockState {
+    pub addr: String,
+    pub files: Vec<MockFile>,
+    pub page_size: usize,
+    pub access_tokens: HashSet<String>,
+    pub captcha_tokens: HashSet<String>,
+    pub captcha_actions: Vec<String>,
+    // 不为空时 captcha init 会要求人机验证
+    pub verification_url: String,
+    // 接下来多少次下载请求只返回一半内容后断开
+    pub truncate_downloads: usize,
+    pub download_ranges: Vec<Option<String>>,
+    pub list_requests: usize,
+    pub file_requests: usize,
+    // 下载链接的过期时间
+    pub link_expire: String,
+    // 不为 0 时每个下载链接只能用这么多次, 之后返回 403 直到重新获取文件信息
+    pub link_uses: usize,
+    link_used: usize,
+    // 创建的文件和目录数量, 用于生成 id
+    pub created: usize,
+    pub trashed: Vec<String>,
+}
+
+impl MockState {
+    pub fn expire_captcha_tokens(&mut self) {
+        self.captcha_tokens.clear();
+    }
+
+    pub fn file(&self, id: &str) -> Option<&MockFile> {
+        self.files.iter().find(|x| x.id == id)
+    }
+}
+
+type Shared = Arc<Mutex<MockState>>;
+
+pub struct MockServer {
+    pub addr: SocketAddr,
+    state: Shared,
+}
+
+// 默认的目录结构
+// /
+// ├── My Pack
+// │   ├── a.txt
+// │   └── sub
+// │       └── b.bin
+// └── readme.md
+pub fn default_files() -> Vec<MockFile> {
+    vec![
+        MockFile::folder("folder-my-pack", "", "My Pack"),
+        MockFile::file("file-a", "folder-my-pack", "a.txt", b"hello world"),
+        MockFile::folder("folder-sub", "folder-my-pack", "sub"),
+        MockFile::file(
+            "file-b",
+            "folder-sub",
+            "b.bin",
+            &(0..4096).map(|x| (x % 251) as u8).collect::<Vec<_>>(),
+        ),
+        MockFile::file("file-readme", "", "readme.md", b"# readme"),
+    ]
+}
+
+impl MockServer {
+    pub async fn start() -> MockServer {
+        Self::start_with_files(default_files()).await
+    }
+
+    pub async fn start_with_files(files: Vec<MockFile>) -> MockServer {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock server failed");
+        let addr = listener.local_addr().expect("get mock server addr failed");
+        let state = Arc::new(Mutex::new(MockState {
+            addr: format!("http://{}", addr),
+            files,
+            page_size: 2,
+            link_expire: "2099-01-01T00:00:00.000+08:00".into(),
+            ..Default::default()
+        }));
+
+        let app = Router::new()
+            .route("/v1/auth/signin", post(signin))
+            .route("/v1/shield/captcha/init", post(captcha_init))
+            .route("/drive/v1/files", get(list_files).post(create_file))
+            .route("/drive/v1/files:batchTrash", post(batch_trash))
+            .route("/drive/v1/files/:id", get(get_file).patch(update_file))
+            .route("/download/:id", get(download))
+            .route("/thumbnail/:id/:size", get(thumbnail))
+            .route("/oss/:bucket/:key", put(put_object))
+            .with_state(state.clone());
+
+        let server = axum::Server::from_tcp(listener)
+            .expect("start mock server failed")
+            .serve(app.into_make_service());
+        tokio::spawn(server);
+
+        MockServer { addr, state }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn config(&self) -> Config {
+        Config {
+            username: MOCK_USERNAME.into(),
+            password: MOCK_PASSWORD.into(),
+            endpoints: Endpoints {
+                user: self.url(),
+                drive: self.url(),
+            },
+            ..Default::default()
+        }
+    }
+
+    pub fn client(&self) -> Client {
+        Client::from_config(&self.config(), 0).expect("create mock client failed")
+    }
+
+    pub fn state(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().expect("lock mock state failed")
+    }
+}
+
+fn err_resp(status: StatusCode, error: &str, code: i64, url: &str) -> Response {
+    (
+        status,
+        Json(json!({
+            "error": error,
+            "error_code": code,
+            "error_url": url,
+            "error_description": error,
+        })),
+    )
+        .into_response()
+}
+
+// 校验失败时返回错误响应
+fn check_auth(state: &MockState, headers: &HeaderMap) -> Option<Response> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    if !state.access_tokens.contains(bearer) {
+        return Some(err_resp(
+            StatusCode::UNAUTHORIZED,
+            "unauthenticated",
+            16,
+            "",
+        ));
+    }
+    let captcha = headers
+        .get("X-Captcha-Token")
+        .and_then(|x| x.to_str().ok())
+        .unwrap_or_default();
+    if !state.captcha_tokens.contains(captcha) {
+        return Some(err_resp(StatusCode::OK, "captcha_invalid", 9, ""));
+    }
+    None
+}
+
+async fn signin(State(state): State<Shared>, body: Bytes) -> Response {
+    let mut state = state.lock().unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap_or_default();
+    let captcha = body["captcha_token"].as_str().unwrap_or_default();
+    if !state.captcha_tokens.contains(captcha) {
+        return err_resp(StatusCode::OK, "captcha_invalid", 9, "");
+    }
+    if body["username"] != MOCK_USERNAME || body["password"] != MOCK_PASSWORD {
+        return err_resp(StatusCode::OK, "invalid_account_or_password", 4022, "");
+    }
+    let token = format!("access-token-{}", state.access_tokens.len());
+    state.access_tokens.insert(token.clone());
+    Json(json!({
+        "token_type": "Bearer",
+        "access_token": token,
+        "refresh_token": "refresh-token",
+        "expires_in": 7200,
+        "sub": "mock-user",
+    }))
+    .into_response()
+}
+
+async fn captcha_init(State(state): State<Shared>, body: Bytes) -> Response {
+    let mut state = state.lock().unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap_or_default();
+    state
+        .captcha_actions
+        .push(body["action"].as_str().unwrap_or_default().to_string());
+    let token = format!("captcha-token-{}", state.captcha_actions.len());
+    if !state.verification_url.is_empty() {
+        // 需要人机验证时返回的 token 在验证完成前不可用
+        return Json(json!({
+            "captcha_token": token,
+            "expires_in": 300,
+            "url": state.verification_url,
+        }))
+        .into_response();
+    }
+    state.captcha_tokens.insert(token.clone());
+    Json(json!({ "captcha_token": token, "expires_in": 300 })).into_response()
+}
+
+async fn list_files(
+    State(state): State<Shared>,
+    headers: HeaderMap,
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    let mut state = state.lock().unwrap();
+    if let Some(resp) = check_auth(&state, &headers) {
+        return resp;
+    }
+    state.list_requests += 1;
+
+    let parent_id = query.get("parent_id").cloned().unwrap_or_default();
+    let start = query
+        .get("page_token")
+        .and_then(|x| x.parse::<usize>().ok())
+        .unwrap_or_default();
+    let children: Vec<_> = state
+        .files
+        .iter()
+        .filter(|x| x.parent_id == parent_id)
+        .collect();
+    let end = (start + state.page_size).min(children.len());
+    let next_page_token = if end < children.len() {
+        end.to_string()
+    } else {
+        "".to_string()
+    };
+    let size = query.get("thumbnail_size").cloned().unwrap_or_default();
+    let files: Vec<_> = children[start..end]
+        .iter()
+        .map(|x| x.status_with_thumbnail(&state.addr, &size))
+        .collect();
+    Json(json!({
+        "kind": "drive#fileList",
+        "next_page_token": next_page_token,
+        "files": files,
+    }))
+    .into_response()
+}
+
+async fn get_file(
+    State(state): State<Shared>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Response {
+    let mut state = state.lock().unwrap();
+    if let Some(resp) = check_auth(&state, &headers) {
+        return resp;
+    }
+    state.file_requests += 1;
+    state.link_used = 0;
+    let Some(file) = state.file(&id) else {
+        return err_resp(StatusCode::NOT_FOUND, "file_not_found", 5, "");
+    };
+    let link = |id: &str| {
+        json!({
+            "url": format!("{}/download/{}", state.addr, id),
+            "token": "",
+            "expire": state.link_expire,
+            "type": "application/octet-stream",
+        })
+    };
+    let size = headers
+        .get("thumbnail_size")
+        .and_then(|x| x.to_str().ok())
+        .unwrap_or_default();
+    let mut resp = file.status_with_thumbnail(&state.addr, size);
+    resp["links"] = json!({ "application/octet-stream": link(&file.id) });
+    if !file.medias.is_empty() {
+        let mut medias = vec![json!({
+            "media_id": format!("{}-origin", file.id),
+            "media_name": "Original",
+            "resolution_name": "Original",
+            "is_origin": true,
+            "link": link(&file.id),
+        })];
+        for (name, height, id) in &file.medias {
+            medias.push(json!({
+                "media_id": id,
+                "media_name": name,
+                "resolution_name": name,
+                "is_origin": false,
+                "video": { "height": height, "width": height * 16 / 9, "duration": 10.5 },
+                "link": link(id),
+            }));
+        }
+        resp["medias"] = json!(medias);
+    }
+    Json(resp).into_response()
+}
+
+// 空文件直接完成, 其他文件返回对象存储的临时凭证, key 即文件 id
+async fn create_file(State(state): State<Shared>, headers: HeaderMap, body: Bytes) -> Response {
+    let mut state = state.lock().unwrap();
+    if let Some(resp) = check_auth(&state, &headers) {
+        return resp;
+    }
+    let body: Value = serde_json::from_slice(&body).unwrap_or_default();
+    let parent_id = body["parent_id"].as_str().unwrap_or_default();
+    let mut name = body["name"].as_str().unwrap_or_default().to_string();
+    // 和真实接口一样, 同名时自动重命名而不是覆盖
+    let taken = |name: &str| {
+        state
+            .files
+            .iter()
+            .any(|x| x.parent_id == parent_id && x.name == name)
+    };
+    if taken(&name) {
+        let (stem, ext) = match name.rsplit_once('.') {
+            Some((stem, ext)) => (stem.to_string(), format!(".{}", ext)),
+            None => (name.clone(), String::new()),
+        };
+        name = (1..)
+            .map(|n| format!("{}({}){}", stem, n, ext))
+            .find(|x| !taken(x))
+            .unwrap();
+    }
+    state.created += 1;
+    let id = format!("created-{}", state.created);
+    let folder = body["kind"] == "drive#folder";
+    let mut file = if folder {
+        MockFile::folder(&id, parent_id, &name)
+    } else {
+        MockFile::file(&id, parent_id, &name, b"")
+    };
+    let mut resp = json!({
+        "upload_type": "UPLOAD_TYPE_UNKNOWN",
+        "file": file.status(),
+    });
+    if !folder && body["size"] != "0" {
+        file.phase = "PHASE_TYPE_PENDING".into();
+        resp["upload_type"] = json!("UPLOAD_TYPE_RESUMABLE");
+        resp["file"]["phase"] = json!(file.phase);
+        resp["resumable"] = json!({
+            "kind": "drive#resumable",
+            "provider": "PROVIDER_ALIYUN",
+            "params": {
+                "access_key_id": "mock-key-id",
+                "access_key_secret": "mock-key-secret",
+                "bucket": "mock-bucket",
+                "endpoint": format!("{}/oss", state.addr),
+                "key": id,
+                "security_token": "mock-security-token",
+            },
+        });
+    }
+    state.files.push(file);
+    Json(resp).into_response()
+}
+
+// 移到回收站, 同时移除目录下的所有文件
+async fn batch_trash(State(state): State<Shared>, headers: HeaderMap, body: Bytes) -> Response {
+    let mut state = state.lock().unwrap();
+    if let Some(resp) = check_auth(&state, &headers) {
+        return resp;
+    }
+    let body: Value = serde_json::from_slice(&body).unwrap_or_default();
+    let mut ids: Vec<String> = body["ids"]
+        .as_array()
+        .map(|x| {
+            x.iter()
+                .filter_map(|x| x.as_str())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default();
+    while let Some(id) = ids.pop() {
+        ids.extend(
+            state
+                .files
+                .iter()
+                .filter(|x| x.parent_id == id)
+                .map(|x| x.id.clone()),
+        );
+        state.files.retain(|x| x.id != id);
+        state.trashed.push(id);
+    }
+    Json(json!({ "task_id": "" })).into_response()
+}
+
+async fn put_object(
+    State(state): State<Shared>,
+    headers: HeaderMap,
+    Path((bucket, key)): Path<(String, String)>,
+    body: Bytes,
+) -> Response {
+    let mut state = state.lock().unwrap();
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|x| x.to_str().ok())
+            .unwrap_or_default()
+    };
+    if bucket != "mock-bucket"
+        || !header("Authorization").starts_with("OSS mock-key-id:")
+        || header("x-oss-security-token") != "mock-security-token"
+    {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let Some(file) = state.files.iter_mut().find(|x| x.id == key) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    file.content = body.to_vec();
+    file.phase = "PHASE_TYPE_COMPLETE".into();
+    StatusCode::OK.into_response()
+}
+
+async fn update_file(
+    State(state): State<Shared>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    body: Bytes,
+) -> Response {
+    let mut state = state.lock().unwrap();
+    if let Some(resp) = check_auth(&state, &headers) {
+        return resp;
+    }
+    let body: Value = serde_json::from_slice(&body).unwrap_or_default();
+    let Some(file) = state.files.iter_mut().find(|x| x.id == id) else {
+        return err_resp(StatusCode::NOT_FOUND, "file_not_found", 5, "");
+    };
+    if let Some(name) = body["name"].as_str() {
+        file.name = name.to_string();
+    }
+    Json(file.status()).into_response()
+}
+
+async fn thumbnail(Path((id, size)): Path<(String, String)>) -> Response {
+    (
+        [(header::CONTENT_TYPE, "image/jpeg")],
+        format!("thumbnail of {} in {}", id, size),
+    )
+        .into_response()
+}
+
+async fn download(
+    State(state): State<Shared>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Response {
+    let mut state = state.lock().unwrap();
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|x| x.to_str().ok())
+        .map(|x| x.to_string());
+    state.download_ranges.push(range.clone());
+    if state.link_uses > 0 {
+        if state.link_used >= state.link_uses {
+            return StatusCode::FORBIDDEN.into_response();
+        }
+        state.link_used += 1;
+    }
+    let Some(file) = state.file(&id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let content = file.content.clone();
+    let mp4 = file.name.ends_with(".mp4");
+
+    let bounds = range
+        .as_deref()
+        .and_then(|x| x.strip_prefix("bytes="))
+        .and_then(|x| x.split_once('-'));
+    let start = bounds.and_then(|(x, _)| x.parse::<usize>().ok());
+    let end = bounds
+        .and_then(|(_, x)| x.parse::<usize>().ok())
+        .map_or(content.len(), |x| (x + 1).min(content.len()));
+    let (status, body) = match start {
+        Some(start) if start < end => (StatusCode::PARTIAL_CONTENT, content[start..end].to_vec()),
+        Some(_) => return StatusCode::RANGE_NOT_SATISFIABLE.into_response(),
+        None => (StatusCode::OK, content.clone()),
+    };
+
+    let mut resp_headers = HeaderMap::new();
+    resp_headers.insert(header::CONTENT_LENGTH, body.len().into());
+    resp_headers.insert(header::ACCEPT_RANGES, "bytes".parse().unwrap());
+    if mp4 {
+        resp_headers.insert(header::CONTENT_TYPE, "video/mp4".parse().unwrap());
+    }
+    if let Some(start) = start {
+        resp_headers.insert(
+            header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", start, end - 1, content.len())
+                .parse()
+                .unwrap(),
+        );
+    }
+
+    if state.truncate_downloads > 0 {
+        state.truncate_downloads -= 1;
+        // 只发送一半内容后中断连接
+        let half = Bytes::from(body[..body.len() / 2].to_vec());
+        let stream = futures::stream::iter(vec![Ok(half), Err(())]).then(|x| async move {
+            // 等待前半部分发送出去后再中断
+            if x.is_err() {
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            }
+            x.map_err(|_| {
+                std::io::Error::new(std::io::ErrorKind::ConnectionReset, "mock truncated")
+            })
+        });
+        return (status, resp_headers, StreamBody::new(stream)).into_response();
+    }
+
+    (status, resp_headers, body).into_response()
+}
+
+#[derive(Debug, Default)]
+pub struct MockAria2State {
+    pub secret: Option<String>,
+    // (uri, options)
+    pub added: Vec<(String, Value)>,
+    pub removed: Vec<String>,
+    // 接下来多少个提交的任务会失败
+    pub fail_first: usize,
+    // 接下来多少次查询状态返回错误
+    pub status_errors: usize,
+}
+
+// 模拟 aria2 的 JSON-RPC 接口, 提交的任务在第一次查询时即完成或失败
+pub struct MockAria2 {
+    pub addr: SocketAddr,
+    state: Arc<Mutex<MockAria2State>>,
+}
+
+impl MockAria2 {
+    pub async fn start(secret: Option<String>) -> MockAria2 {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock aria2 failed");
+        let addr = listener.local_addr().expect("get mock aria2 addr failed");
+        let state = Arc::new(Mutex::new(MockAria2State {
+            secret,
+            ..Default::default()
+        }));
+        let app = Router::new()
+            .route("/jsonrpc", post(aria2_rpc))
+            .with_state(state.clone());
+        let server = axum::Server::from_tcp(listener)
+            .expect("start mock aria2 failed")
+            .serve(app.into_make_service());
+        tokio::spawn(server);
+        MockAria2 { addr, state }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}/jsonrpc", self.addr)
+    }
+
+    pub fn state(&self) -> MutexGuard<'_, MockAria2State> {
+        self.state.lock().expect("lock mock aria2 state failed")
+    }
+}
+
+async fn aria2_rpc(State(state): State<Arc<Mutex<MockAria2State>>>, body: Bytes) -> Response {
+    let mut state = state.lock().unwrap();
+    let req: Value = serde_json::from_slice(&body).unwrap_or_default();
+    let id = req["id"].clone();
+    let mut params = req["params"].as_array().cloned().unwrap_or_default();
+    if let Some(secret) = &state.secret {
+        if params.first().and_then(|x| x.as_str()) != Some(&format!("token:{}", secret)) {
+            return Json(json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": 1, "message": "Unauthorized" },
+            }))
+            .into_response();
+        }
+        params.remove(0);
+    }
+
+    let result = match req["method"].as_str().unwrap_or_default() {
+        "aria2.addUri" => {
+            let uri = params[0][0].as_str().unwrap_or_default().to_string();
+            state.added.push((uri, params[1].clone()));
+            json!(format!("gid-{}", state.added.len()))
+        }
+        "aria2.tellStatus" if state.status_errors > 0 => {
+            state.status_errors -= 1;
+            return Json(json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": 1, "message": "Internal error" },
+            }))
+            .into_response();
+        }
+        "aria2.tellStatus" => {
+            let gid = params[0].as_str().unwrap_or_default();
+            let index = gid
+                .strip_prefix("gid-")
+                .and_then(|x| x.parse::<usize>().ok())
+                .unwrap_or_default();
+            if index == 0 || index > state.added.len() {
+                json!({ "status": "removed" })
+            } else if index <= state.fail_first {
+                json!({
+                    "status": "error",
+                    "completedLength": "0",
+                    "errorCode": "22",
+                    "errorMessage": "The response status is not successful. status=403",
+                })
+            } else {
+                json!({ "status": "complete", "completedLength": "11", "totalLength": "11" })
+            }
+        }
+        "aria2.removeDownloadResult" => {
+            let gid = params[0].as_str().unwrap_or_default().to_string();
+            state.removed.push(gid);
+            json!("OK")
+        }
+        method => {
+            return Json(json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": 1, "message": format!("method not found: {}", method) },
+            }))
+            .into_response()
+        }
+    };
+    Json(json!({ "jsonrpc": "2.0", "id": id, "result": result })).into_response()
+}
@@ -2,13 +2,19 @@ use anyhow::Result;
 use reqwest::{header, RequestBuilder};
 use serde::{Deserialize, Serialize};
 
-use crate::config::get_config;
+use crate::config::{get_config, Config, Endpoints};
+use cassette::Cassette;
 
-mod captcha_token;
+pub mod captcha_token;
+pub mod cassette;
 pub mod download;
 pub mod file;
 pub mod folder;
 mod login;
+#[cfg(test)]
+pub mod mock;
+mod request;
+pub mod upload;
 
 #[derive(Debug, Default)]
 pub struct Client {
@@ -20,18 +26,29 @@ pub struct Client {
     sub: String,
     device_id: String,
     refresh_second: i64,
+    endpoints: Endpoints,
+    cassette: Option<Cassette>,
     client: reqwest::Client,
     pub retry_times: i8,
+    // 下载失败后重试的间隔
+    pub retry_interval: std::time::Duration,
+    pub interactive: bool,
+    pub thumbnail_size: file::ThumbnailSize,
 }
 
-const USER_AGENT: &str = "ANDROID-com.pikcloud.pikpak/1.21.0";
+pub(crate) const USER_AGENT: &str = "ANDROID-com.pikcloud.pikpak/1.21.0";
 const CLIENT_ID: &str = "YNxT9w7GMdWvEOKa";
 const CLIENT_SECRET: &str = "dbw2OtmVEeuUvIptb1Coyg";
+const RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
 
 impl Client {
     pub fn new(retry_times: i8) -> Result<Self> {
-        let account = get_config().username.clone();
-        let password = get_config().password.clone();
+        Self::from_config(get_config(), retry_times)
+    }
+
+    pub fn from_config(conf: &Config, retry_times: i8) -> Result<Self> {
+        let account = conf.username.clone();
+        let password = conf.password.clone();
         let device_id = format!("{:x}", md5::compute(&account));
 
         let mut headers = header::HeaderMap::new();
@@ -47,7 +64,7 @@ impl Client {
 
         let mut client_builder: reqwest::ClientBuilder =
             reqwest::Client::builder().default_headers(headers);
-        if let Some(proxy) = get_config().proxy.as_ref() {
+        if let Some(proxy) = conf.proxy.as_ref() {
             client_builder = client_builder.proxy(reqwest::Proxy::all(proxy)?);
         }
         let client = client_builder.build()?;
@@ -57,7 +74,10 @@ impl Client {
             password,
             client,
             device_id,
+            endpoints: conf.endpoints.clone(),
+            cassette: conf.cassette.as_ref().map(Cassette::load).transpose()?,
             retry_times,
+            retry_interval: RETRY_INTERVAL,
             ..Default::default()
         })
     }
@@ -69,17 +89,30 @@ pub struct ErrResp {
     pub error: String,
     #[serde(rename = "error_code")]
     pub error_code: i64,
-    #[serde(rename = "error_url")]
+    #[serde(rename = "error_url", default)]
     pub error_url: String,
-    #[serde(rename = "error_description")]
+    #[serde(rename = "error_description", default)]
     pub error_description: String,
 }
 
+impl std::fmt::Display for ErrResp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} (code: {}): {}",
+            self.error, self.error_code, self.error_description
+        )
+    }
+}
+
+impl std::error::Error for ErrResp {}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(untagged)]
+// Err 需要放在前面, 否则字段都有默认值的 T 会把错误返回解析成 Success
 enum Resp<T> {
-    Success(T),
     Err(ErrResp),
+    Success(T),
 }
 
 pub trait RetrySend {
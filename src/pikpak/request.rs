@@ -0,0 +1,146 @@
+use anyhow::{Context, Result};
+use log::*;
+use reqwest::{Method, RequestBuilder, Url};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::pikpak::RetrySend;
+
+use super::{Client, Resp};
+
+// captcha token 过期或与 action 不匹配时返回的错误码
+const CAPTCHA_INVALID_CODE: i64 = 9;
+
+// 根据请求生成 captcha action, 如 GET:/drive/v1/files
+// drive 接口按资源集合计算, /drive/v1/files/<id> 与 /drive/v1/files 共用同一个 action
+pub fn captcha_action(method: &Method, url: &str) -> Result<String> {
+    let url = Url::parse(url).context("[captcha_action] parse url failed")?;
+    let segments: Vec<&str> = url
+        .path_segments()
+        .map(|x| x.filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default();
+    let segments = if segments.first() == Some(&"drive") {
+        &segments[..segments.len().min(3)]
+    } else {
+        &segments[..]
+    };
+    Ok(format!("{}:/{}", method, segments.join("/")))
+}
+
+impl Client {
+    // 发送 api 请求并解析返回值
+    // 每次请求都会调用 build 重新构造请求, 保证 captcha token 等字段是最新的
+    // captcha token 为空时先获取, 服务端返回 captcha token 失效时刷新并重试一次
+    pub async fn request<T, F>(&mut self, method: Method, url: &str, build: F) -> Result<T>
+    where
+        T: DeserializeOwned,
+        F: Fn(&Client, RequestBuilder) -> RequestBuilder,
+    {
+        let action = captcha_action(&method, url)?;
+        if self.captcha_token.is_empty() {
+            self.auth_captcha_token(action.clone())
+                .await
+                .context("[request] auth captcha token failed")?;
+        }
+
+        for i in 0..2 {
+            let mut req = build(self, self.client.request(method.clone(), url));
+            if !self.jwt_token.is_empty() {
+                req = req.bearer_auth(&self.jwt_token);
+            }
+            if !self.captcha_token.is_empty() {
+                req = req.header("X-Captcha-Token", &self.captcha_token);
+            }
+
+            debug!("req: {:?}", req);
+
+            let resp = self
+                .send_json(req)
+                .await
+                .with_context(|| format!("[request] {}", action))?;
+            match serde_json::from_value::<Resp<T>>(resp)
+                .with_context(|| format!("[request] {}", action))?
+            {
+                Resp::Success(resp) => return Ok(resp),
+                Resp::Err(err) => {
+                    if !err.error_url.is_empty() && i == 0 {
+                        debug!("captcha verification required, action: {}", action);
+                        if !self.verify_captcha(&err.error_url).await? {
+                            self.auth_captcha_token(action.clone())
+                                .await
+                                .context("[request] auth captcha token failed")?;
+                        }
+                        continue;
+                    }
+                    if err.error_code == CAPTCHA_INVALID_CODE && i == 0 {
+                        debug!("captcha token invalid, refreshing, action: {}", action);
+                        self.auth_captcha_token(action.clone())
+                            .await
+                            .context("[request] auth captcha token failed")?;
+                        continue;
+                    }
+                    return Err(err.into());
+                }
+            }
+        }
+
+        Err(anyhow::anyhow!("[request] {} failed", action))
+    }
+}
+
+impl Client {
+    // 发送请求并返回 json, 配置了 cassette 时会录制或回放
+    pub async fn send_json(&mut self, req: RequestBuilder) -> Result<Value> {
+        let cassette_req = match self.cassette {
+            Some(_) => Some(
+                req.try_clone()
+                    .ok_or(anyhow::anyhow!("clone request failed"))?
+                    .build()?,
+            ),
+            None => None,
+        };
+        if let (Some(cassette), Some(cassette_req)) = (self.cassette.as_mut(), &cassette_req) {
+            if cassette.is_replay() {
+                return cassette.replay(cassette_req);
+            }
+        }
+
+        let resp = req.retry_send(self.retry_times).await?;
+        let status = resp.status().as_u16();
+        let resp = resp.json::<Value>().await?;
+
+        if let (Some(cassette), Some(cassette_req)) = (self.cassette.as_mut(), &cassette_req) {
+            cassette.record(cassette_req, status, &resp)?;
+        }
+        Ok(resp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_captcha_action() -> Result<()> {
+        assert_eq!(
+            captcha_action(
+                &Method::GET,
+                "https://api-drive.mypikpak.com/drive/v1/files"
+            )?,
+            "GET:/drive/v1/files"
+        );
+        assert_eq!(
+            captcha_action(
+                &Method::GET,
+                // cspell: disable-next-line
+                "https://api-drive.mypikpak.com/drive/v1/files/VNnUEooZhMP43acATLjCgCLeo1?a=b"
+            )?,
+            "GET:/drive/v1/files"
+        );
+        assert_eq!(
+            captcha_action(&Method::POST, "https://user.mypikpak.com/v1/auth/signin")?,
+            "POST:/v1/auth/signin"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,259 @@
+// 创建目录, 移到回收站和上传文件
+// 上传先在 drive 创建文件, 服务端已有相同 hash 的文件时秒传, 否则用返回的临时凭证 PUT 到对象存储
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use log::*;
+use reqwest::Method;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha1::Sha1;
+
+use crate::utils::hash::gcid_file;
+
+use super::download::get_download_client;
+use super::file::FileType;
+use super::{Client, USER_AGENT};
+
+#[derive(Default, Debug, Deserialize)]
+#[serde(default)]
+struct CreateFileResp {
+    upload_type: String,
+    resumable: Option<Resumable>,
+    file: FileType,
+}
+
+#[derive(Default, Debug, Deserialize)]
+#[serde(default)]
+struct Resumable {
+    provider: String,
+    params: OssParams,
+}
+
+#[derive(Default, Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct OssParams {
+    pub access_key_id: String,
+    pub access_key_secret: String,
+    pub bucket: String,
+    pub endpoint: String,
+    pub key: String,
+    pub security_token: String,
+}
+
+impl OssParams {
+    // 带协议的 endpoint 使用 path-style, 否则使用 bucket 子域名
+    fn url(&self) -> String {
+        if self.endpoint.contains("://") {
+            format!(
+                "{}/{}/{}",
+                self.endpoint.trim_end_matches('/'),
+                self.bucket,
+                self.key
+            )
+        } else {
+            format!("https://{}.{}/{}", self.bucket, self.endpoint, self.key)
+        }
+    }
+
+    // 对象存储 v1 签名, 见 https://help.aliyun.com/document_detail/31951.html
+    pub fn authorization(&self, method: &str, content_type: &str, date: &str) -> String {
+        let content = format!(
+            "{}\n\n{}\n{}\nx-oss-security-token:{}\n/{}/{}",
+            method, content_type, date, self.security_token, self.bucket, self.key
+        );
+        let mut mac = Hmac::<Sha1>::new_from_slice(self.access_key_secret.as_bytes())
+            .expect("hmac accepts keys of any size");
+        mac.update(content.as_bytes());
+        let signature =
+            base64::engine::general_purpose::STANDARD.encode(mac.finalize().into_bytes());
+        format!("OSS {}:{}", self.access_key_id, signature)
+    }
+}
+
+impl Client {
+    pub async fn create_folder(&mut self, parent_id: &str, name: &str) -> Result<FileType> {
+        let body = json!({
+            "kind": "drive#folder",
+            "parent_id": parent_id,
+            "name": name,
+        });
+        let resp: CreateFileResp = self
+            .request(
+                Method::POST,
+                &self.endpoints.drive_url("/drive/v1/files"),
+                |_, req| req.json(&body),
+            )
+            .await
+            .with_context(|| format!("[create_folder] {}", name))?;
+        debug!("resp: {:?}", resp);
+        Ok(resp.file)
+    }
+
+    pub async fn trash(&mut self, ids: &[String]) -> Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let body = json!({ "ids": ids });
+        let resp: Value = self
+            .request(
+                Method::POST,
+                &self.endpoints.drive_url("/drive/v1/files:batchTrash"),
+                |_, req| req.json(&body),
+            )
+            .await
+            .context("[trash]")?;
+        debug!("resp: {:?}", resp);
+        Ok(())
+    }
+
+    pub async fn rename_file(&mut self, file_id: &str, name: &str) -> Result<FileType> {
+        let body = json!({ "name": name });
+        let resp: FileType = self
+            .request(
+                Method::PATCH,
+                &self
+                    .endpoints
+                    .drive_url(&format!("/drive/v1/files/{}", file_id)),
+                |_, req| req.json(&body),
+            )
+            .await
+            .with_context(|| format!("[rename_file] {}", name))?;
+        debug!("resp: {:?}", resp);
+        Ok(resp)
+    }
+
+    // 上传本地文件到 parent_id 下, 返回上传后的文件信息
+    // 同名文件不会被覆盖, 服务端会自动重命名
+    pub async fn upload_file(
+        &mut self,
+        parent_id: &str,
+        name: &str,
+        path: &Path,
+    ) -> Result<FileType> {
+        let size = std::fs::metadata(path)
+            .with_context(|| format!("[upload_file] get metadata of {} failed", path.display()))?
+            .len();
+        let hash_path = path.to_path_buf();
+        let hash = tokio::task::spawn_blocking(move || gcid_file(&hash_path)).await??;
+
+        let body = json!({
+            "kind": "drive#file",
+            "parent_id": parent_id,
+            "name": name,
+            "size": size.to_string(),
+            "hash": hash,
+            "upload_type": "UPLOAD_TYPE_RESUMABLE",
+            "objProvider": { "provider": "UPLOAD_TYPE_UNKNOWN" },
+        });
+        let resp: CreateFileResp = self
+            .request(
+                Method::POST,
+                &self.endpoints.drive_url("/drive/v1/files"),
+                |_, req| req.json(&body),
+            )
+            .await
+            .with_context(|| format!("[upload_file] create {}", name))?;
+        debug!("resp: {:?}", resp);
+
+        match resp.resumable {
+            Some(resumable) if resp.file.phase != "PHASE_TYPE_COMPLETE" => {
+                debug!(
+                    "upload {} to {}, upload type: {}",
+                    name, resumable.provider, resp.upload_type
+                );
+                put_object(&resumable.params, path, size).await?;
+            }
+            _ => info!("instant upload: {}", name),
+        }
+        self.get_file_by_id(resp.file.id)
+            .await
+            .context("[upload_file]")
+    }
+}
+
+async fn put_object(params: &OssParams, path: &Path, size: u64) -> Result<()> {
+    let content_type = "application/octet-stream";
+    let date = Utc::now().format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+    let file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("[put_object] open {} failed", path.display()))?;
+    let resp = get_download_client()
+        .put(params.url())
+        .header("User-Agent", USER_AGENT)
+        .header("Content-Type", content_type)
+        .header("Content-Length", size)
+        .header("Date", &date)
+        .header("x-oss-security-token", &params.security_token)
+        .header(
+            "Authorization",
+            params.authorization("PUT", content_type, &date),
+        )
+        .body(file)
+        .send()
+        .await
+        .context("[put_object] request failed")?;
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let text = resp.text().await.unwrap_or_default();
+        return Err(anyhow::anyhow!(
+            "[put_object] unexpected status: {}, {}",
+            status,
+            text
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pikpak::mock::MockServer;
+
+    #[test]
+    fn test_oss_authorization() {
+        let params = OssParams {
+            access_key_id: "key-id".into(),
+            access_key_secret: "secret".into(),
+            bucket: "bucket".into(),
+            endpoint: "oss.example.com".into(),
+            key: "user/file".into(),
+            security_token: "token".into(),
+        };
+        assert_eq!(params.url(), "https://bucket.oss.example.com/user/file");
+        assert_eq!(
+            params.authorization(
+                "PUT",
+                "application/octet-stream",
+                "Mon, 01 Jan 2024 00:00:00 GMT"
+            ),
+            "OSS key-id:yr8QAg7Gs0RKzlUqu9wtYRvQjCA="
+        );
+    }
+
+    #[tokio::test]
+    async fn test_upload_mock() -> Result<()> {
+        let server = MockServer::start().await;
+        let mut client = server.client();
+        client.login().await?;
+
+        let folder = client.create_folder("folder-my-pack", "new").await?;
+        assert_eq!(folder.kind, "drive#folder");
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("c.txt");
+        std::fs::write(&path, b"uploaded")?;
+        let file = client.upload_file(&folder.id, "c.txt", &path).await?;
+        assert_eq!(file.size, "8");
+        assert_eq!(file.parent_id, folder.id);
+        assert_eq!(server.state().file(&file.id).unwrap().content, b"uploaded");
+
+        client.trash(std::slice::from_ref(&folder.id)).await?;
+        assert!(server.state().file(&folder.id).is_none());
+        assert!(server.state().file(&file.id).is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum FileCategory {
+    Video,
+    Audio,
+    Image,
+    Document,
+    Archive,
+    Other,
+}
+
+const VIDEO_EXTENSIONS: &[&str] = &[
+    "mp4", "mkv", "avi", "mov", "wmv", "flv", "webm", "m4v", "ts", "rmvb", "mpg", "mpeg",
+];
+const AUDIO_EXTENSIONS: &[&str] = &["mp3", "flac", "aac", "m4a", "ogg", "wav", "ape", "opus"];
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "gif", "bmp", "webp", "heic", "tiff"];
+const DOCUMENT_EXTENSIONS: &[&str] = &[
+    "pdf", "doc", "docx", "xls", "xlsx", "ppt", "pptx", "txt", "md", "epub", "mobi", "azw3", "odt",
+    "rtf", "csv",
+];
+const ARCHIVE_EXTENSIONS: &[&str] = &["zip", "rar", "7z", "tar", "gz", "bz2", "xz", "zst", "iso"];
+
+// 优先使用 PikPak 返回的 file_category, 无法识别时根据 mime type 和扩展名判断
+pub fn classify(file_category: &str, mime_type: &str, name: &str) -> FileCategory {
+    match file_category.to_uppercase().as_str() {
+        "VIDEO" => return FileCategory::Video,
+        "AUDIO" => return FileCategory::Audio,
+        "IMAGE" => return FileCategory::Image,
+        "TEXT" | "DOCUMENT" => return FileCategory::Document,
+        "ARCHIVE" => return FileCategory::Archive,
+        _ => {}
+    }
+
+    let mime_type = mime_type.to_lowercase();
+    if mime_type.starts_with("video/") {
+        return FileCategory::Video;
+    }
+    if mime_type.starts_with("audio/") {
+        return FileCategory::Audio;
+    }
+    if mime_type.starts_with("image/") {
+        return FileCategory::Image;
+    }
+
+    let extension = name
+        .rsplit_once('.')
+        .map(|(_, x)| x.to_lowercase())
+        .unwrap_or_default();
+    let extension = extension.as_str();
+    if VIDEO_EXTENSIONS.contains(&extension) {
+        return FileCategory::Video;
+    }
+    if AUDIO_EXTENSIONS.contains(&extension) {
+        return FileCategory::Audio;
+    }
+    if IMAGE_EXTENSIONS.contains(&extension) {
+        return FileCategory::Image;
+    }
+    if mime_type.starts_with("text/") || DOCUMENT_EXTENSIONS.contains(&extension) {
+        return FileCategory::Document;
+    }
+    if ARCHIVE_EXTENSIONS.contains(&extension) {
+        return FileCategory::Archive;
+    }
+    FileCategory::Other
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify("VIDEO", "", "a"), FileCategory::Video);
+        assert_eq!(classify("", "audio/mpeg", "a"), FileCategory::Audio);
+        assert_eq!(classify("OTHER", "", "a.PDF"), FileCategory::Document);
+        assert_eq!(classify("", "", "a.tar.gz"), FileCategory::Archive);
+        assert_eq!(classify("", "", "a.MKV"), FileCategory::Video);
+        assert_eq!(classify("", "", "a"), FileCategory::Other);
+    }
+}
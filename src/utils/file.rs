@@ -1,7 +1,8 @@
 use std::fs;
 use std::path::Path;
+use std::time::SystemTime;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 
 pub fn create_dir_if_not_exists(path: &Path) -> Result<()> {
     if !path.exists() {
@@ -9,3 +10,83 @@ pub fn create_dir_if_not_exists(path: &Path) -> Result<()> {
     }
     Ok(())
 }
+
+// 先写临时文件再重命名, 写入中断时不会留下不完整的文件
+pub fn write_atomic(path: &Path, content: &[u8]) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, content).context("[write_atomic] write temp file failed")?;
+    fs::rename(&tmp, path).context("[write_atomic] rename temp file failed")
+}
+
+pub fn set_modified(path: &Path, time: SystemTime) -> Result<()> {
+    let file = fs::File::options()
+        .write(true)
+        .open(path)
+        .context("[set_modified] open file failed")?;
+    file.set_modified(time)
+        .context("[set_modified] set modified time failed")
+}
+
+// 写入 user 命名空间的扩展属性, 跳过空值
+#[cfg(target_os = "linux")]
+pub fn set_xattrs(path: &Path, attrs: &[(&str, &str)]) -> Result<()> {
+    for (name, value) in attrs.iter().filter(|(_, v)| !v.is_empty()) {
+        xattr::set(path, format!("user.{}", name), value.as_bytes())
+            .with_context(|| format!("[set_xattrs] set {} failed", name))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_xattrs(_path: &Path, _attrs: &[(&str, &str)]) -> Result<()> {
+    Err(anyhow::anyhow!(
+        "[set_xattrs] extended attributes are only supported on linux"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_atomic() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("state.json");
+        write_atomic(&path, b"{}")?;
+        write_atomic(&path, b"[]")?;
+        assert_eq!(fs::read(&path)?, b"[]");
+        assert!(!dir.path().join("state.json.tmp").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_modified() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("a");
+        fs::write(&path, b"a")?;
+        let time = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1704038400);
+        set_modified(&path, time)?;
+        assert_eq!(fs::metadata(&path)?.modified()?, time);
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_set_xattrs() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("a");
+        fs::write(&path, b"a")?;
+        if let Err(err) = set_xattrs(&path, &[("pikpak.id", "file-a"), ("pikpak.hash", "")]) {
+            // 部分文件系统不支持扩展属性
+            eprintln!("skip xattr test: {:#}", err);
+            return Ok(());
+        }
+        assert_eq!(
+            xattr::get(&path, "user.pikpak.id")?,
+            Some(b"file-a".to_vec())
+        );
+        assert_eq!(xattr::get(&path, "user.pikpak.hash")?, None);
+        Ok(())
+    }
+}
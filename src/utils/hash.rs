@@ -0,0 +1,88 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use sha1::{Digest, Sha1};
+
+const BUFFER_SIZE: usize = 1 << 16;
+
+pub fn md5_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path).context("[md5_file] open file failed")?;
+    let mut ctx = md5::Context::new();
+    let mut buf = vec![0; BUFFER_SIZE];
+    loop {
+        let n = file.read(&mut buf).context("[md5_file] read file failed")?;
+        if n == 0 {
+            break;
+        }
+        ctx.consume(&buf[..n]);
+    }
+    Ok(format!("{:x}", ctx.compute()))
+}
+
+// PikPak 文件的 hash 字段, 即迅雷的 gcid
+// 按文件大小确定分块大小, 对每块的 sha1 拼接后再计算一次 sha1
+pub fn gcid_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path).context("[gcid_file] open file failed")?;
+    let size = file
+        .metadata()
+        .context("[gcid_file] get metadata failed")?
+        .len();
+    let mut block_size: u64 = 0x40000;
+    while size / block_size > 0x200 && block_size < 0x200000 {
+        block_size <<= 1;
+    }
+
+    let mut hasher = Sha1::new();
+    let mut block = vec![0; block_size as usize];
+    loop {
+        let mut n = 0;
+        while n < block.len() {
+            let read = file
+                .read(&mut block[n..])
+                .context("[gcid_file] read file failed")?;
+            if read == 0 {
+                break;
+            }
+            n += read;
+        }
+        if n == 0 {
+            break;
+        }
+        hasher.update(Sha1::digest(&block[..n]));
+    }
+    Ok(format!("{:X}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("a");
+
+        std::fs::write(&path, b"")?;
+        assert_eq!(
+            gcid_file(&path)?,
+            "DA39A3EE5E6B4B0D3255BFEF95601890AFD80709"
+        );
+
+        std::fs::write(&path, b"hello world")?;
+        assert_eq!(md5_file(&path)?, "5eb63bbbe01eeed093cb22bb8f5acdc3");
+        assert_eq!(
+            gcid_file(&path)?,
+            "67BECF85308ACF0261750DA1075681EE5C412F05"
+        );
+
+        let data: Vec<u8> = (0..600000).map(|x| (x % 251) as u8).collect();
+        std::fs::write(&path, data)?;
+        assert_eq!(
+            gcid_file(&path)?,
+            "3FC0617C331816DA4EE9C19C6F532F2D6D4FD6CC"
+        );
+        Ok(())
+    }
+}
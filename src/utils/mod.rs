@@ -1,2 +1,6 @@
+pub mod category;
 pub mod file;
+pub mod hash;
+pub mod parse;
 pub mod path;
+pub mod sort;
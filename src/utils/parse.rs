@@ -0,0 +1,116 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+
+// 解析文件大小, 如 100, 10K, 1.5G, 2MiB, 单位为 1024 进制
+pub fn parse_size(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let index = size
+        .find(|x: char| !x.is_ascii_digit() && x != '.')
+        .unwrap_or(size.len());
+    let (num, unit) = size.split_at(index);
+    let num: f64 = num
+        .parse()
+        .with_context(|| format!("[parse_size] invalid size: {}", size))?;
+    let unit = unit.trim().to_uppercase();
+    let unit = unit.trim_end_matches("IB").trim_end_matches('B');
+    let scale: u64 = match unit {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(anyhow::anyhow!("[parse_size] invalid unit: {}", size)),
+    };
+    Ok((num * scale as f64) as u64)
+}
+
+// 解析时间, 支持 rfc3339, 日期 (2024-01-01) 和相对时长 (7d, 12h)
+pub fn parse_time(time: &str) -> Result<DateTime<Utc>> {
+    let time = time.trim();
+    if let Ok(t) = DateTime::parse_from_rfc3339(time) {
+        return Ok(t.with_timezone(&Utc));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(time, "%Y-%m-%d") {
+        return Ok(date
+            .and_hms_opt(0, 0, 0)
+            .context("[parse_time] invalid date")?
+            .and_utc());
+    }
+    let duration = humantime::parse_duration(time)
+        .with_context(|| format!("[parse_time] invalid time: {}", time))?;
+    Ok(Utc::now() - chrono::Duration::from_std(duration)?)
+}
+
+// 字节范围, 与 http Range 一致, end 包含在内
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: Option<u64>,
+}
+
+// 解析字节范围, 如 100-199, 100-, 支持带单位的大小, 如 1M-2M
+pub fn parse_range(range: &str) -> Result<ByteRange> {
+    let (start, end) = range
+        .split_once('-')
+        .with_context(|| format!("[parse_range] invalid range: {}", range))?;
+    let start = parse_size(start)?;
+    let end = match end.trim() {
+        "" => None,
+        end => Some(parse_size(end)?),
+    };
+    if end.is_some_and(|x| x < start) {
+        return Err(anyhow::anyhow!("[parse_range] end before start: {}", range));
+    }
+    Ok(ByteRange { start, end })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() -> Result<()> {
+        assert_eq!(parse_size("100")?, 100);
+        assert_eq!(parse_size("10K")?, 10 * 1024);
+        assert_eq!(parse_size("1.5GiB")?, 1536 * 1024 * 1024);
+        assert_eq!(parse_size("2 mb")?, 2 * 1024 * 1024);
+        assert!(parse_size("1X").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_time() -> Result<()> {
+        assert_eq!(
+            parse_time("2024-01-10T12:00:00+08:00")?,
+            parse_time("2024-01-10T04:00:00Z")?
+        );
+        assert_eq!(
+            parse_time("2024-01-10")?.to_rfc3339(),
+            "2024-01-10T00:00:00+00:00"
+        );
+        let t = parse_time("1d")?;
+        assert!((Utc::now() - t).num_hours() == 24);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_range() -> Result<()> {
+        assert_eq!(
+            parse_range("100-199")?,
+            ByteRange {
+                start: 100,
+                end: Some(199)
+            }
+        );
+        assert_eq!(
+            parse_range("1K-")?,
+            ByteRange {
+                start: 1024,
+                end: None
+            }
+        );
+        assert!(parse_range("200-100").is_err());
+        assert!(parse_range("100").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,53 @@
+use std::cmp::Ordering;
+
+// 把字符串拆成数字和非数字的片段
+fn chunks(s: &str) -> Vec<&str> {
+    let mut res = vec![];
+    let mut start = 0;
+    let mut last_digit = None;
+    for (i, c) in s.char_indices() {
+        let digit = c.is_ascii_digit();
+        if last_digit.is_some_and(|x| x != digit) {
+            res.push(&s[start..i]);
+            start = i;
+        }
+        last_digit = Some(digit);
+    }
+    if start < s.len() {
+        res.push(&s[start..]);
+    }
+    res
+}
+
+// 自然排序, 数字按数值比较, 例如 ep2 排在 ep10 前面, 字母不区分大小写
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    for (x, y) in chunks(a).into_iter().zip(chunks(b)) {
+        let digits = |s: &str| s.starts_with(|c: char| c.is_ascii_digit());
+        let ord = if digits(x) && digits(y) {
+            let (x, y) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+            x.len().cmp(&y.len()).then_with(|| x.cmp(y))
+        } else {
+            x.to_lowercase().cmp(&y.to_lowercase())
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    chunks(a).len().cmp(&chunks(b).len()).then_with(|| a.cmp(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_natural_cmp() {
+        let mut names = vec!["ep10.mkv", "Ep2.mkv", "ep1.mkv", "ep01.mkv", "a", "ep"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            names,
+            vec!["a", "ep", "ep01.mkv", "ep1.mkv", "Ep2.mkv", "ep10.mkv"]
+        );
+        assert_eq!(natural_cmp("S2/E1", "S10/E1"), Ordering::Less);
+    }
+}